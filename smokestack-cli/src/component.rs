@@ -2,8 +2,8 @@ use crate::print_response;
 use clap::Subcommand;
 use reqwest::{Client, Url};
 use smokestack::{
-    api::{CreateComponentRequest, ListComponentsResponse},
-    model::Component,
+    api::{CreateComponentRequest, ListComponentsResponse, UpdateComponentRequest},
+    model::{ApprovalRequirement, Component},
 };
 
 #[derive(Debug, Subcommand)]
@@ -15,8 +15,41 @@ pub enum ComponentCommand {
         #[arg(short, long)]
         description: String,
 
+        /// Users or groups owning the component
         #[arg(short, long, alias = "owner", num_args = 1..)]
         owners: Vec<String>,
+
+        /// Users or groups whose approvals count for operations targeting
+        /// this component
+        #[arg(long, num_args = 1..)]
+        requires_approval_by: Vec<String>,
+
+        /// Number of approvals required before operations targeting this
+        /// component can start
+        #[arg(long, default_value_t = 0)]
+        required_approvals: u32,
+    },
+
+    /// Edit a component
+    Edit {
+        name: String,
+
+        #[arg(short, long)]
+        description: Option<String>,
+
+        /// Users or groups owning the component
+        #[arg(short, long, alias = "owner", num_args = 1..)]
+        owners: Option<Vec<String>>,
+
+        /// Users or groups whose approvals count for operations targeting
+        /// this component
+        #[arg(long, num_args = 0..)]
+        requires_approval_by: Option<Vec<String>>,
+
+        /// Number of approvals required before operations targeting this
+        /// component can start
+        #[arg(long)]
+        required_approvals: Option<u32>,
     },
 
     /// Show a component
@@ -33,11 +66,17 @@ impl ComponentCommand {
                 name,
                 description,
                 owners,
+                requires_approval_by,
+                required_approvals,
             } => {
                 let request = CreateComponentRequest {
                     name,
                     description,
                     owners,
+                    approval: ApprovalRequirement {
+                        requires_approval_by,
+                        required_approvals,
+                    },
                 };
                 let response = client
                     .post(api_root.join("components")?)
@@ -46,6 +85,26 @@ impl ComponentCommand {
                     .await?;
                 print_response::<Component>(response).await?;
             }
+            Self::Edit {
+                name,
+                description,
+                owners,
+                requires_approval_by,
+                required_approvals,
+            } => {
+                let request = UpdateComponentRequest {
+                    description,
+                    owners,
+                    requires_approval_by,
+                    required_approvals,
+                };
+                let response = client
+                    .patch(api_root.join(&format!("components/{name}"))?)
+                    .json(&request)
+                    .send()
+                    .await?;
+                print_response::<Component>(response).await?;
+            }
             Self::Show { name } => {
                 let response = client
                     .get(api_root.join(&format!("components/{name}"))?)
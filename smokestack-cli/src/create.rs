@@ -1,5 +1,6 @@
 use crate::{edit_yaml, print_response};
 use anyhow::Context;
+use chrono::{DateTime, Utc};
 use clap::Args;
 use http::Uri;
 use reqwest::{Client, Url};
@@ -85,7 +86,7 @@ impl Input {
                 std::io::stdin().lock().read_to_end(&mut buf)?;
                 Ok(buf)
             }
-            Self::Path(path) => std::fs::read(path).map_err(Into::into),
+            Self::Path(path) => std::fs::read(path),
         }
     }
 }
@@ -107,6 +108,8 @@ struct OperationCreation {
     locks: Vec<String>,
     tags: Vec<String>,
     depends_on: Vec<u64>,
+    starts_at: Option<DateTime<Utc>>,
+    ends_at: Option<DateTime<Utc>>,
     operators: Vec<String>,
     annotations: HashMap<String, String>,
 }
@@ -121,7 +124,10 @@ impl From<OperationCreation> for CreateOperationRequest {
             locks: oc.locks,
             tags: oc.tags,
             depends_on: oc.depends_on,
+            starts_at: oc.starts_at,
+            ends_at: oc.ends_at,
             operators: oc.operators,
+            on_behalf_of: None,
             annotations: oc.annotations,
         }
     }
@@ -153,6 +159,8 @@ impl CreateArgs {
                     locks: Vec::new(),
                     tags: Vec::new(),
                     depends_on: Vec::new(),
+                    starts_at: None,
+                    ends_at: None,
                     operators: vec![username.to_owned()],
                     annotations: HashMap::new(),
                 })?
@@ -0,0 +1,134 @@
+use crate::{extract_result, print_response};
+use clap::Subcommand;
+use reqwest::{Client, Url};
+use smokestack::{
+    api::{CreateGroupRequest, ListGroupsResponse, UpdateGroupRequest},
+    model::Group,
+};
+
+#[derive(Debug, Subcommand)]
+pub enum GroupCommand {
+    /// Create a new user group
+    Create {
+        name: String,
+
+        #[arg(short, long)]
+        description: String,
+
+        #[arg(short, long, alias = "member", num_args = 1..)]
+        members: Vec<String>,
+    },
+
+    /// Edit a user group
+    Edit {
+        name: String,
+
+        #[arg(short, long)]
+        description: Option<String>,
+
+        #[arg(short, long, alias = "member", num_args = 0..)]
+        members: Option<Vec<String>>,
+    },
+
+    /// Add users to a user group
+    Add {
+        name: String,
+
+        #[arg(required = true)]
+        users: Vec<String>,
+    },
+
+    /// Remove users from a user group
+    Remove {
+        name: String,
+
+        #[arg(required = true)]
+        users: Vec<String>,
+    },
+
+    /// Delete a user group
+    Delete { name: String },
+
+    /// Show a user group
+    Show { name: String },
+
+    /// List user groups
+    List,
+}
+
+impl GroupCommand {
+    pub async fn invoke(self, client: &Client, api_root: &Url) -> anyhow::Result<()> {
+        match self {
+            Self::Create {
+                name,
+                description,
+                members,
+            } => {
+                let request = CreateGroupRequest {
+                    name,
+                    description,
+                    members,
+                };
+                let response = client
+                    .post(api_root.join("groups")?)
+                    .json(&request)
+                    .send()
+                    .await?;
+                print_response::<Group>(response).await?;
+            }
+            Self::Edit {
+                name,
+                description,
+                members,
+            } => {
+                let request = UpdateGroupRequest {
+                    description,
+                    members,
+                };
+                let response = client
+                    .patch(api_root.join(&format!("groups/{name}"))?)
+                    .json(&request)
+                    .send()
+                    .await?;
+                print_response::<Group>(response).await?;
+            }
+            Self::Add { name, users } => {
+                for user in users {
+                    let response = client
+                        .put(api_root.join(&format!("groups/{name}/members/{user}"))?)
+                        .send()
+                        .await?;
+                    extract_result::<Group>(response).await?;
+                }
+            }
+            Self::Remove { name, users } => {
+                for user in users {
+                    let response = client
+                        .delete(api_root.join(&format!("groups/{name}/members/{user}"))?)
+                        .send()
+                        .await?;
+                    extract_result::<Group>(response).await?;
+                }
+            }
+            Self::Delete { name } => {
+                let response = client
+                    .delete(api_root.join(&format!("groups/{name}"))?)
+                    .send()
+                    .await?;
+                extract_result::<()>(response).await?;
+            }
+            Self::Show { name } => {
+                let response = client
+                    .get(api_root.join(&format!("groups/{name}"))?)
+                    .send()
+                    .await?;
+                print_response::<Group>(response).await?;
+            }
+            Self::List => {
+                let response = client.get(api_root.join("groups")?).send().await?;
+                print_response::<ListGroupsResponse>(response).await?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{colorize_status, extract_result};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, Utc};
+use clap::Args;
+use reqwest::{Client, Url};
+use smokestack::{
+    api::ListHistoryResponse,
+    model::{EventKind, OperationEvent, OperationState},
+};
+use std::io::Write;
+use unicode_width::UnicodeWidthStr;
+
+#[derive(Debug, Args)]
+pub struct HistoryArgs {
+    /// Show the history of this operation only
+    #[arg(
+        conflicts_with_all = ["COMPONENT", "TAG", "STATUS", "OPERATOR", "ACTOR", "since", "until"]
+    )]
+    operation_id: Option<u64>,
+
+    #[arg(short, long = "component", name = "COMPONENT", num_args = 1..)]
+    components: Vec<String>,
+
+    #[arg(short, long = "tag", name = "TAG", num_args = 1..)]
+    tags: Vec<String>,
+
+    #[arg(short, long = "status", name = "STATUS", num_args = 1..)]
+    statuses: Vec<OperationState>,
+
+    #[arg(short, long = "operator", name = "OPERATOR", num_args = 1..)]
+    operators: Vec<String>,
+
+    /// Users who caused the events
+    #[arg(short, long = "actor", name = "ACTOR", num_args = 1..)]
+    actors: Vec<String>,
+
+    /// Show events at or after this time (e.g. "2024-01-02 15:00")
+    #[arg(long, value_parser = parse_timestamp)]
+    since: Option<DateTime<Utc>>,
+
+    /// Show events before this time (e.g. "2024-01-02 15:00")
+    #[arg(long, value_parser = parse_timestamp)]
+    until: Option<DateTime<Utc>>,
+}
+
+impl HistoryArgs {
+    pub async fn invoke(self, client: &Client, api_root: &Url) -> anyhow::Result<()> {
+        if let Some(operation_id) = self.operation_id {
+            let response = client
+                .get(api_root.join(&format!("operations/{operation_id}/history"))?)
+                .send()
+                .await?;
+            let ListHistoryResponse { events, .. } = extract_result(response).await?;
+            print_events(&events)?;
+            return Ok(());
+        }
+
+        let mut query = Vec::new();
+        for component in self.components {
+            query.push(("component", component));
+        }
+        for tag in self.tags {
+            query.push(("tag", tag));
+        }
+        for operator in self.operators {
+            query.push(("operator", operator));
+        }
+        for status in self.statuses {
+            query.push(("status", status.to_string()));
+        }
+        for actor in self.actors {
+            query.push(("actor", actor));
+        }
+        if let Some(since) = self.since {
+            query.push(("since", since.to_rfc3339()));
+        }
+        if let Some(until) = self.until {
+            query.push(("until", until.to_rfc3339()));
+        }
+        let mut events = Vec::new();
+        let mut after = None;
+        loop {
+            let mut request = client.get(api_root.join("history")?).query(&query);
+            if let Some(after) = after {
+                request = request.query(&[("after", after)]);
+            }
+            let response = request.send().await?;
+            let ListHistoryResponse {
+                events: mut page,
+                next,
+            } = extract_result(response).await?;
+            events.append(&mut page);
+            if next.is_none() {
+                break;
+            }
+            after = next;
+        }
+        print_events(&events)?;
+        Ok(())
+    }
+}
+
+/// Parses a timestamp either in RFC 3339 or in local time
+/// (`YYYY-mm-dd[ HH:MM[:SS]]`).
+pub fn parse_timestamp(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(s) {
+        return Ok(timestamp.into());
+    }
+    let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .ok_or_else(|| format!("invalid timestamp: {s}"))?;
+    naive
+        .and_local_timezone(Local)
+        .earliest()
+        .map(Into::into)
+        .ok_or_else(|| format!("nonexistent local time: {s}"))
+}
+
+fn print_events(events: &[OperationEvent]) -> std::io::Result<()> {
+    const TIME_WIDTH: usize = "YYYY-mm-dd HH:MM:SS".len();
+    let mut max_actor_width = "actor".len();
+    let mut max_event_width = "event".len();
+    let mut max_status_width = "status".len();
+    for event in events {
+        max_actor_width = max_actor_width.max(event.actor.width());
+        max_event_width = max_event_width.max(event.kind.to_string().len());
+        max_status_width = max_status_width.max(event.operation.status.to_string().len());
+    }
+    let mut stdout = std::io::stdout().lock();
+    writeln!(
+        &mut stdout,
+        "{:time_width$}  {:actor_width$}  {:event_width$}  {:status_width$}  changes",
+        "time",
+        "actor",
+        "event",
+        "status",
+        time_width = TIME_WIDTH,
+        actor_width = max_actor_width,
+        event_width = max_event_width,
+        status_width = max_status_width,
+    )?;
+    for width in [
+        TIME_WIDTH,
+        max_actor_width,
+        max_event_width,
+        max_status_width,
+    ] {
+        for _ in 0..width {
+            stdout.write_all(b"-")?;
+        }
+        stdout.write_all(b"  ")?;
+    }
+    stdout.write_all(b"-------\n")?;
+    for event in events {
+        write!(
+            &mut stdout,
+            "{}  {}",
+            event
+                .timestamp
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S"),
+            event.actor
+        )?;
+        for _ in event.actor.width()..max_actor_width {
+            stdout.write_all(b" ")?;
+        }
+        write!(
+            &mut stdout,
+            "  {:event_width$}  {}  ",
+            event.kind.to_string(),
+            colorize_status(event.operation.status),
+            event_width = max_event_width
+        )?;
+        for _ in event.operation.status.to_string().len()..max_status_width {
+            stdout.write_all(b" ")?;
+        }
+        if event.kind != EventKind::Created {
+            let fields: Vec<_> = event.changes.keys().map(String::as_str).collect();
+            stdout.write_all(fields.join(", ").as_bytes())?;
+        }
+        stdout.write_all(b"\n")?;
+    }
+    Ok(())
+}
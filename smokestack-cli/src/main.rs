@@ -1,28 +1,44 @@
 mod component;
 mod create;
+mod group;
+mod history;
 mod list;
+mod service_account;
 mod subscription;
 mod tag;
+mod user;
+mod webhook;
 
+use anyhow::Context;
 use clap::{Parser, Subcommand};
 use component::ComponentCommand;
 use create::CreateArgs;
+use group::GroupCommand;
+use history::HistoryArgs;
 use http::{HeaderMap, HeaderValue};
-use list::ListArgs;
 use reqwest::{Response, Url};
 use serde::{de::DeserializeOwned, Serialize};
+use service_account::ServiceAccountCommand;
 use smokestack::{
-    api::{ApiResponse, AuthRequest, AuthResponse, UpdateOperationRequest},
+    api::{ApiResponse, AuthRequest, AuthResponse, RefreshTokenRequest, UpdateOperationRequest},
+    args::{ListArgs, SubscribeArgs, UnsubscribeArgs},
     model::{Claims, Operation, OperationState},
 };
-use std::{ffi::OsString, io::Write, path::Path, process::Stdio};
-use subscription::SubscribeArgs;
+use std::{
+    ffi::OsString,
+    io::Write,
+    path::Path,
+    process::Stdio,
+    time::{SystemTime, UNIX_EPOCH},
+};
 use syntect::{
     highlighting::{Style, ThemeSet},
     parsing::SyntaxSet,
     util::as_24_bit_terminal_escaped,
 };
 use tag::TagCommand;
+use user::UserCommand;
+use webhook::WebhookCommand;
 
 #[derive(Debug, Parser)]
 #[clap(version)]
@@ -63,11 +79,28 @@ enum Command {
     /// Cancel an operation before starting
     Cancel { operation_id: u64 },
 
+    /// Show the history of operations
+    History(HistoryArgs),
+
     /// Subscribe to an operation, component, or tag
     Subscribe(SubscribeArgs),
 
+    /// Unsubscribe from an operation, component, or tag
+    Unsubscribe(UnsubscribeArgs),
+
     /// Watch notifications
-    Watch,
+    Watch {
+        /// Also show the past events after the event with this ID (0 for all
+        /// of them). By default, only new events are shown.
+        #[arg(long)]
+        since: Option<u64>,
+    },
+
+    /// Approve an operation
+    Approve { operation_id: u64 },
+
+    /// Revoke your approval of an operation
+    Unapprove { operation_id: u64 },
 
     /// Manage components
     Component {
@@ -81,10 +114,42 @@ enum Command {
         command: TagCommand,
     },
 
+    /// Manage user groups
+    Group {
+        #[command(subcommand)]
+        command: GroupCommand,
+    },
+
+    /// Manage the current user
+    User {
+        #[command(subcommand)]
+        command: UserCommand,
+    },
+
+    /// Manage webhooks
+    Webhook {
+        #[command(subcommand)]
+        command: WebhookCommand,
+    },
+
+    /// Manage service accounts and their API tokens
+    ServiceAccount {
+        #[command(subcommand)]
+        command: ServiceAccountCommand,
+    },
+
     /// Authenticate with the server
     Auth {
         #[arg(short, long)]
         username: String,
+
+        /// Prompt for a password
+        #[arg(short, long)]
+        password: bool,
+
+        /// Read the password from the standard input
+        #[arg(long, conflicts_with = "password")]
+        password_stdin: bool,
     },
 }
 
@@ -128,31 +193,34 @@ async fn main() -> anyhow::Result<()> {
     let app_dir = Path::new(&home).join(".smokestack");
 
     let api_root = cli.endpoint.join("/api/v1/")?;
-    let token = match std::fs::read_to_string(app_dir.join("token")) {
-        Ok(token) => token,
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-            let Command::Auth { username } = cli.command else {
-                anyhow::bail!("not authenticated; run `smokestack auth`");
-            };
-            let request = AuthRequest { username };
-            let response = reqwest::Client::new()
-                .post(api_root.join("auth")?)
-                .json(&request)
-                .send()
-                .await?;
-            let response: AuthResponse = extract_result(response).await?;
-            let path = app_dir.join("token");
-            std::fs::write(path, response.token)?;
-            return Ok(());
-        }
-        Err(e) => return Err(e.into()),
-    };
-
-    let mut validation = jsonwebtoken::Validation::default();
-    validation.insecure_disable_signature_validation();
-    let key = jsonwebtoken::DecodingKey::from_secret(&[]);
-    let token_data = jsonwebtoken::decode(&token, &key, &validation)?;
-    let Claims { username, .. } = token_data.claims;
+    let token_path = app_dir.join("token");
+    if let Command::Auth {
+        username,
+        password,
+        password_stdin,
+    } = cli.command
+    {
+        let password = if password {
+            Some(rpassword::prompt_password("Password: ")?)
+        } else if password_stdin {
+            let mut line = String::new();
+            std::io::stdin().read_line(&mut line)?;
+            Some(line.trim_end_matches(['\r', '\n']).to_string())
+        } else {
+            None
+        };
+        let request = AuthRequest { username, password };
+        let response = reqwest::Client::new()
+            .post(api_root.join("auth")?)
+            .json(&request)
+            .send()
+            .await?;
+        let response: AuthResponse = extract_result(response).await?;
+        std::fs::create_dir_all(&app_dir)?;
+        std::fs::write(token_path, serde_json::to_string(&response)?)?;
+        return Ok(());
+    }
+    let (token, username) = load_token(&api_root, &token_path).await?;
 
     let authorization = (
         reqwest::header::AUTHORIZATION,
@@ -171,7 +239,7 @@ async fn main() -> anyhow::Result<()> {
                 .await?;
             print_response::<Operation>(response).await?;
         }
-        Command::List(args) => args.invoke(&client, &api_root).await?,
+        Command::List(args) => list::list(args, &client, &api_root).await?,
         Command::Edit { operation_id } => {
             let response = client
                 .get(api_root.join(&format!("operations/{operation_id}"))?)
@@ -186,6 +254,8 @@ async fn main() -> anyhow::Result<()> {
                 locks: Some(operation.locks),
                 tags: Some(operation.tags),
                 depends_on: Some(operation.depends_on),
+                starts_at: Some(operation.starts_at),
+                ends_at: Some(operation.ends_at),
                 operators: Some(operation.operators),
                 status: Some(operation.status),
                 annotations: operation.annotations,
@@ -223,15 +293,89 @@ async fn main() -> anyhow::Result<()> {
                 .await?;
             print_response::<Operation>(response).await?;
         }
-        Command::Subscribe(args) => args.invoke(&client, &api_root).await?,
-        Command::Watch => subscription::watch(&client, &api_root, authorization).await?,
+        Command::History(args) => args.invoke(&client, &api_root).await?,
+        Command::Subscribe(args) => subscription::subscribe(args, &client, &api_root).await?,
+        Command::Unsubscribe(args) => subscription::unsubscribe(args, &client, &api_root).await?,
+        Command::Watch { since } => subscription::watch(&api_root, authorization, since).await?,
+        Command::Approve { operation_id } => {
+            let response = client
+                .post(api_root.join(&format!("operations/{operation_id}/approvals"))?)
+                .send()
+                .await?;
+            print_response::<Operation>(response).await?;
+        }
+        Command::Unapprove { operation_id } => {
+            let response = client
+                .delete(api_root.join(&format!("operations/{operation_id}/approvals"))?)
+                .send()
+                .await?;
+            print_response::<Operation>(response).await?;
+        }
         Command::Component { command } => command.invoke(&client, &api_root).await?,
         Command::Tag { command } => command.invoke(&client, &api_root).await?,
-        Command::Auth { .. } => anyhow::bail!("already authenticated as {}", username),
+        Command::Group { command } => command.invoke(&client, &api_root).await?,
+        Command::User { command } => command.invoke(&client, &api_root).await?,
+        Command::Webhook { command } => command.invoke(&client, &api_root).await?,
+        Command::ServiceAccount { command } => command.invoke(&client, &api_root).await?,
+        Command::Auth { .. } => unreachable!(),
     }
     Ok(())
 }
 
+/// Seconds before expiry at which access tokens are refreshed
+const TOKEN_REFRESH_MARGIN: u64 = 60;
+
+/// Reads the tokens saved by `smokestack auth`, refreshing the access token
+/// if it is about to expire.
+///
+/// Returns the access token and the name of the user it belongs to.
+async fn load_token(api_root: &Url, path: &Path) -> anyhow::Result<(String, String)> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            anyhow::bail!("not authenticated; run `smokestack auth`");
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    // Older versions saved only the access token, which cannot be refreshed.
+    let Ok(tokens) = serde_json::from_str::<AuthResponse>(&content) else {
+        let token = content.trim().to_string();
+        let Claims { username, .. } = decode_claims(&token)?;
+        return Ok((token, username));
+    };
+
+    let Claims { exp, username, .. } = decode_claims(&tokens.token)?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    if exp > now + TOKEN_REFRESH_MARGIN {
+        return Ok((tokens.token, username));
+    }
+    let request = RefreshTokenRequest {
+        refresh_token: tokens.refresh_token,
+    };
+    let response = reqwest::Client::new()
+        .post(api_root.join("auth/refresh")?)
+        .json(&request)
+        .send()
+        .await?;
+    let tokens: AuthResponse = extract_result(response)
+        .await
+        .context("failed to refresh the token; run `smokestack auth`")?;
+    std::fs::write(path, serde_json::to_string(&tokens)?)?;
+    let Claims { username, .. } = decode_claims(&tokens.token)?;
+    Ok((tokens.token, username))
+}
+
+/// Reads the claims of a token without verifying it, which is up to the
+/// server.
+fn decode_claims(token: &str) -> anyhow::Result<Claims> {
+    let mut validation = jsonwebtoken::Validation::default();
+    validation.insecure_disable_signature_validation();
+    validation.validate_exp = false;
+    let key = jsonwebtoken::DecodingKey::from_secret(&[]);
+    Ok(jsonwebtoken::decode(token, &key, &validation)?.claims)
+}
+
 fn edit_yaml<T: AsRef<[u8]>>(s: T) -> anyhow::Result<Vec<u8>> {
     let mut file = tempfile::Builder::new().suffix(".yml").tempfile()?;
     file.write_all(s.as_ref())?;
@@ -0,0 +1,107 @@
+use crate::{extract_result, print_response};
+use clap::Subcommand;
+use reqwest::{Client, Url};
+use smokestack::{
+    api::{
+        CreateApiTokenRequest, CreateApiTokenResponse, CreateServiceAccountRequest,
+        ListApiTokensResponse, ListServiceAccountsResponse,
+    },
+    model::{Scope, ServiceAccount},
+};
+
+#[derive(Debug, Subcommand)]
+pub enum ServiceAccountCommand {
+    /// Create a new service account
+    Create {
+        name: String,
+
+        #[arg(short, long, default_value = "")]
+        description: String,
+    },
+
+    /// Show a service account
+    Show { name: String },
+
+    /// List service accounts
+    List,
+
+    /// Issue an API token to a service account. The token is shown only once.
+    CreateToken {
+        name: String,
+
+        /// What the token is allowed to do (operations:read, operations:write
+        /// or subscriptions:write)
+        #[arg(short, long = "scope", name = "SCOPE", num_args = 1.., required = true)]
+        scopes: Vec<Scope>,
+
+        #[arg(short, long, default_value = "")]
+        description: String,
+    },
+
+    /// List the API tokens of a service account
+    Tokens { name: String },
+
+    /// Revoke an API token of a service account
+    RevokeToken { name: String, id: u64 },
+}
+
+impl ServiceAccountCommand {
+    pub async fn invoke(self, client: &Client, api_root: &Url) -> anyhow::Result<()> {
+        match self {
+            Self::Create { name, description } => {
+                let request = CreateServiceAccountRequest { name, description };
+                let response = client
+                    .post(api_root.join("service-accounts")?)
+                    .json(&request)
+                    .send()
+                    .await?;
+                print_response::<ServiceAccount>(response).await?;
+            }
+            Self::Show { name } => {
+                let response = client
+                    .get(api_root.join(&format!("service-accounts/{name}"))?)
+                    .send()
+                    .await?;
+                print_response::<ServiceAccount>(response).await?;
+            }
+            Self::List => {
+                let response = client
+                    .get(api_root.join("service-accounts")?)
+                    .send()
+                    .await?;
+                print_response::<ListServiceAccountsResponse>(response).await?;
+            }
+            Self::CreateToken {
+                name,
+                scopes,
+                description,
+            } => {
+                let request = CreateApiTokenRequest {
+                    description,
+                    scopes,
+                };
+                let response = client
+                    .post(api_root.join(&format!("service-accounts/{name}/tokens"))?)
+                    .json(&request)
+                    .send()
+                    .await?;
+                print_response::<CreateApiTokenResponse>(response).await?;
+            }
+            Self::Tokens { name } => {
+                let response = client
+                    .get(api_root.join(&format!("service-accounts/{name}/tokens"))?)
+                    .send()
+                    .await?;
+                print_response::<ListApiTokensResponse>(response).await?;
+            }
+            Self::RevokeToken { name, id } => {
+                let response = client
+                    .delete(api_root.join(&format!("service-accounts/{name}/tokens/{id}"))?)
+                    .send()
+                    .await?;
+                extract_result::<()>(response).await?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,107 +1,139 @@
-use std::io::Write;
+use std::{io::Write, time::Duration};
 
 use crate::{colorize_status, extract_result, print_response};
-use clap::{Args, Subcommand};
 use futures_util::StreamExt;
 use http::{HeaderName, HeaderValue};
 use reqwest::{Client, Url};
 use smokestack::{
-    api::{CreateSubscriptionRequest, ListOperationsResponse, ListSubscriptionResponse},
-    model::Operation,
+    api::{DeleteSubscriptionQuery, ListSubscriptionResponse},
+    args::{SubscribeArgs, UnsubscribeArgs},
+    model::{Operation, OperationEvent},
 };
-use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Message};
 
-#[derive(Debug, Args)]
-#[group(required = true, multiple = false)]
-pub struct SubscribeArgs {
-    /// List subscriptions
-    #[arg(short, long)]
-    list: bool,
-
-    /// Operation ID to subscribe to
-    #[arg(short, long)]
-    operation: Option<u64>,
-
-    /// Component name to subscribe to
-    #[arg(short, long)]
-    component: Option<String>,
-
-    /// Tag name to subscribe to
-    #[arg(short, long)]
-    tag: Option<String>,
-}
-
-#[derive(Debug, Subcommand)]
-enum SubscribeCommand {
-    /// List subscriptions
-    List,
+/// Subscribes to an operation, component, or tag, or lists subscriptions.
+pub async fn subscribe(args: SubscribeArgs, client: &Client, api_root: &Url) -> anyhow::Result<()> {
+    if let Some(request) = args.into_request() {
+        let response = client
+            .post(api_root.join("subscriptions")?)
+            .json(&request)
+            .send()
+            .await?;
+        extract_result::<()>(response).await?;
+    } else {
+        let response = client.get(api_root.join("subscriptions")?).send().await?;
+        print_response::<ListSubscriptionResponse>(response).await?;
+    }
+    Ok(())
 }
 
-impl SubscribeArgs {
-    pub async fn invoke(self, client: &Client, api_root: &Url) -> anyhow::Result<()> {
-        if self.list {
-            let response = client.get(api_root.join("subscriptions")?).send().await?;
-            print_response::<ListSubscriptionResponse>(response).await?;
-        } else {
-            let request = CreateSubscriptionRequest {
-                operation: self.operation,
-                component: self.component,
-                tag: self.tag,
-            };
-            let response = client
-                .post(api_root.join("subscriptions")?)
-                .json(&request)
-                .send()
-                .await?;
-            extract_result::<()>(response).await?;
-        }
-        Ok(())
-    }
+pub async fn unsubscribe(
+    args: UnsubscribeArgs,
+    client: &Client,
+    api_root: &Url,
+) -> anyhow::Result<()> {
+    let response = client
+        .delete(api_root.join("subscriptions")?)
+        .query(&DeleteSubscriptionQuery::from(args))
+        .send()
+        .await?;
+    extract_result::<()>(response).await?;
+    Ok(())
 }
 
 pub async fn watch(
-    client: &Client,
     api_root: &Url,
     authorization: (HeaderName, HeaderValue),
+    mut since: Option<u64>,
 ) -> anyhow::Result<()> {
-    fn print_operation<W: std::io::Write>(
-        out: &mut W,
-        operation: &Operation,
-    ) -> std::io::Result<()> {
-        write!(
-            out,
-            "{}  {:>9}  ",
-            chrono::Local::now().format("%Y-%m-%d %H:%M:%S"), // Fake timestamp
-            operation.id
-        )?;
-        out.write_all(colorize_status(operation.status).as_bytes())?;
-        for _ in operation.status.to_string().len().."in_progress".len() {
-            out.write_all(b" ")?;
-        }
-        writeln!(out, "  {}", operation.title)
-    }
+    const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+    const MAX_BACKOFF: Duration = Duration::from_secs(30);
 
     let mut stdout = std::io::stdout();
     stdout.write_all(b"time                 operation  status       title\n-------------------  ---------  -----------  -----\n")?;
 
-    // TODO: fetch history instead of the final states of the past operations
-    let response = client.get(api_root.join("operations")?).send().await?;
-    let ListOperationsResponse { operations } = extract_result(response).await?;
-    for operation in operations {
-        print_operation(&mut stdout, &operation)?;
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        let mut connected = false;
+        let result = stream_events(
+            api_root,
+            authorization.clone(),
+            &mut since,
+            &mut connected,
+            &mut stdout,
+        )
+        .await;
+        if connected {
+            backoff = INITIAL_BACKOFF;
+        }
+        match result {
+            Ok(()) => eprintln!("connection closed"),
+            Err(e) => {
+                // Retrying won't help if the server rejected us.
+                if let Some(tokio_tungstenite::tungstenite::Error::Http(response)) =
+                    e.downcast_ref()
+                {
+                    if response.status().is_client_error() {
+                        return Err(e);
+                    }
+                }
+                eprintln!("connection lost: {e}");
+            }
+        }
+        eprintln!("reconnecting in {} seconds", backoff.as_secs());
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
     }
+}
 
+/// Prints events until the connection is closed, preceded by the events after
+/// the event with the ID `since` if given. `since` is updated as events
+/// arrive, so that reconnecting resumes where the stream stopped.
+async fn stream_events<W: std::io::Write>(
+    api_root: &Url,
+    authorization: (HeaderName, HeaderValue),
+    since: &mut Option<u64>,
+    connected: &mut bool,
+    out: &mut W,
+) -> anyhow::Result<()> {
     let mut url = api_root.join("subscriptions/watch")?;
     url.set_scheme("ws").unwrap();
+    if let Some(since) = since {
+        url.query_pairs_mut()
+            .append_pair("since", &since.to_string());
+    }
     let mut request = url.into_client_request()?;
     request.headers_mut().extend([authorization]);
     let (mut stream, _) = tokio_tungstenite::connect_async(request).await?;
+    *connected = true;
     while let Some(msg) = stream.next().await {
-        let tokio_tungstenite::tungstenite::Message::Text(msg) = msg? else {
-            anyhow::bail!("unexpected message type");
+        let msg = match msg? {
+            Message::Text(msg) => msg,
+            Message::Close(_) => break,
+            Message::Ping(_) | Message::Pong(_) => continue,
+            _ => anyhow::bail!("unexpected message type"),
         };
-        let operation: Operation = serde_json::from_str(&msg)?;
-        print_operation(&mut stdout, &operation)?;
+        let event: OperationEvent = serde_json::from_str(&msg)?;
+        print_operation(out, event.timestamp.into(), &event.operation)?;
+        *since = Some(event.id);
     }
     Ok(())
 }
+
+fn print_operation<W: std::io::Write>(
+    out: &mut W,
+    timestamp: chrono::DateTime<chrono::Local>,
+    operation: &Operation,
+) -> std::io::Result<()> {
+    write!(
+        out,
+        "{}  {:>9}  ",
+        timestamp.format("%Y-%m-%d %H:%M:%S"),
+        operation.id
+    )?;
+    out.write_all(colorize_status(operation.status).as_bytes())?;
+    for _ in operation.status.to_string().len().."in_progress".len() {
+        out.write_all(b" ")?;
+    }
+    writeln!(out, "  {}", operation.title)
+}
@@ -2,8 +2,8 @@ use crate::print_response;
 use clap::Subcommand;
 use reqwest::{Client, Url};
 use smokestack::{
-    api::{CreateTagRequest, ListTagsResponse},
-    model::Tag,
+    api::{CreateTagRequest, ListTagsResponse, UpdateTagRequest},
+    model::{ApprovalRequirement, Tag},
 };
 
 #[derive(Debug, Subcommand)]
@@ -14,6 +14,32 @@ pub enum TagCommand {
 
         #[arg(short, long)]
         description: String,
+
+        /// Users or groups whose approvals count for operations with this tag
+        #[arg(long, num_args = 1..)]
+        requires_approval_by: Vec<String>,
+
+        /// Number of approvals required before operations with this tag can
+        /// start
+        #[arg(long, default_value_t = 0)]
+        required_approvals: u32,
+    },
+
+    /// Edit a tag
+    Edit {
+        name: String,
+
+        #[arg(short, long)]
+        description: Option<String>,
+
+        /// Users or groups whose approvals count for operations with this tag
+        #[arg(long, num_args = 0..)]
+        requires_approval_by: Option<Vec<String>>,
+
+        /// Number of approvals required before operations with this tag can
+        /// start
+        #[arg(long)]
+        required_approvals: Option<u32>,
     },
 
     /// Show a tag
@@ -26,8 +52,20 @@ pub enum TagCommand {
 impl TagCommand {
     pub async fn invoke(self, client: &Client, api_root: &Url) -> anyhow::Result<()> {
         match self {
-            Self::Create { name, description } => {
-                let request = CreateTagRequest { name, description };
+            Self::Create {
+                name,
+                description,
+                requires_approval_by,
+                required_approvals,
+            } => {
+                let request = CreateTagRequest {
+                    name,
+                    description,
+                    approval: ApprovalRequirement {
+                        requires_approval_by,
+                        required_approvals,
+                    },
+                };
                 let response = client
                     .post(api_root.join("tags")?)
                     .json(&request)
@@ -35,6 +73,24 @@ impl TagCommand {
                     .await?;
                 print_response::<Tag>(response).await?;
             }
+            Self::Edit {
+                name,
+                description,
+                requires_approval_by,
+                required_approvals,
+            } => {
+                let request = UpdateTagRequest {
+                    description,
+                    requires_approval_by,
+                    required_approvals,
+                };
+                let response = client
+                    .patch(api_root.join(&format!("tags/{name}"))?)
+                    .json(&request)
+                    .send()
+                    .await?;
+                print_response::<Tag>(response).await?;
+            }
             Self::Show { name } => {
                 let response = client
                     .get(api_root.join(&format!("tags/{name}"))?)
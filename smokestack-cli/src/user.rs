@@ -0,0 +1,48 @@
+use crate::print_response;
+use clap::Subcommand;
+use reqwest::{Client, Url};
+use smokestack::{api::UpdateUserRequest, model::User};
+
+#[derive(Debug, Subcommand)]
+pub enum UserCommand {
+    /// Show the current user
+    Show,
+
+    /// Edit the current user
+    Edit {
+        /// Address to which notifications are emailed
+        #[arg(long)]
+        email: Option<String>,
+
+        /// Stop receiving email notifications
+        #[arg(long, conflicts_with = "email")]
+        no_email: bool,
+    },
+}
+
+impl UserCommand {
+    pub async fn invoke(self, client: &Client, api_root: &Url) -> anyhow::Result<()> {
+        match self {
+            Self::Show => {
+                let response = client.get(api_root.join("users/me")?).send().await?;
+                print_response::<User>(response).await?;
+            }
+            Self::Edit { email, no_email } => {
+                let request = UpdateUserRequest {
+                    email: if no_email {
+                        Some(None)
+                    } else {
+                        email.map(Some)
+                    },
+                };
+                let response = client
+                    .patch(api_root.join("users/me")?)
+                    .json(&request)
+                    .send()
+                    .await?;
+                print_response::<User>(response).await?;
+            }
+        }
+        Ok(())
+    }
+}
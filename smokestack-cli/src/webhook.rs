@@ -0,0 +1,108 @@
+use crate::{extract_result, print_response};
+use clap::Subcommand;
+use http::Uri;
+use reqwest::{Client, Url};
+use smokestack::{
+    api::{CreateWebhookRequest, ListFailedDeliveriesResponse, ListWebhooksResponse},
+    model::{OperationState, Webhook},
+};
+
+#[derive(Debug, Subcommand)]
+pub enum WebhookCommand {
+    /// Register a new webhook
+    Create {
+        url: Uri,
+
+        /// Key used to sign payloads with HMAC-SHA256
+        #[arg(long)]
+        secret: String,
+
+        /// Only notify operations targeting any of these components
+        #[arg(short, long = "component", name = "COMPONENT", num_args = 1..)]
+        components: Vec<String>,
+
+        /// Only notify operations tagged with any of these tags
+        #[arg(short, long = "tag", name = "TAG", num_args = 1..)]
+        tags: Vec<String>,
+
+        /// Only notify operations in any of these states
+        #[arg(short, long = "status", name = "STATUS", num_args = 1..)]
+        statuses: Vec<OperationState>,
+    },
+
+    /// Delete a webhook
+    Delete { id: u64 },
+
+    /// Show a webhook
+    Show { id: u64 },
+
+    /// List webhooks
+    List,
+
+    /// List events that could not be delivered to a webhook
+    Failures { id: u64 },
+
+    /// Deliver a failed event again
+    Redeliver { id: u64, failure_id: u64 },
+}
+
+impl WebhookCommand {
+    pub async fn invoke(self, client: &Client, api_root: &Url) -> anyhow::Result<()> {
+        match self {
+            Self::Create {
+                url,
+                secret,
+                components,
+                tags,
+                statuses,
+            } => {
+                let request = CreateWebhookRequest {
+                    url,
+                    secret,
+                    components,
+                    tags,
+                    statuses,
+                };
+                let response = client
+                    .post(api_root.join("webhooks")?)
+                    .json(&request)
+                    .send()
+                    .await?;
+                print_response::<Webhook>(response).await?;
+            }
+            Self::Delete { id } => {
+                let response = client
+                    .delete(api_root.join(&format!("webhooks/{id}"))?)
+                    .send()
+                    .await?;
+                extract_result::<()>(response).await?;
+            }
+            Self::Show { id } => {
+                let response = client
+                    .get(api_root.join(&format!("webhooks/{id}"))?)
+                    .send()
+                    .await?;
+                print_response::<Webhook>(response).await?;
+            }
+            Self::List => {
+                let response = client.get(api_root.join("webhooks")?).send().await?;
+                print_response::<ListWebhooksResponse>(response).await?;
+            }
+            Self::Failures { id } => {
+                let response = client
+                    .get(api_root.join(&format!("webhooks/{id}/failures"))?)
+                    .send()
+                    .await?;
+                print_response::<ListFailedDeliveriesResponse>(response).await?;
+            }
+            Self::Redeliver { id, failure_id } => {
+                let response = client
+                    .post(api_root.join(&format!("webhooks/{id}/failures/{failure_id}/redeliver"))?)
+                    .send()
+                    .await?;
+                extract_result::<()>(response).await?;
+            }
+        }
+        Ok(())
+    }
+}
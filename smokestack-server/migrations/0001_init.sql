@@ -0,0 +1,142 @@
+CREATE TABLE meta (
+    key TEXT PRIMARY KEY,
+    value INTEGER NOT NULL
+);
+INSERT INTO meta (key, value) VALUES ('next_webhook_id', 1);
+
+CREATE TABLE users (
+    name TEXT PRIMARY KEY,
+    data TEXT NOT NULL
+);
+
+-- kind is operation, component or tag, and subject is the ID or the name of
+-- the entity.
+CREATE TABLE user_subscriptions (
+    user_name TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    PRIMARY KEY (user_name, kind, subject)
+);
+CREATE INDEX user_subscriptions_subject ON user_subscriptions (kind, subject);
+
+-- starts_at and ends_at are in microseconds since the Unix epoch.
+CREATE TABLE operations (
+    id INTEGER PRIMARY KEY,
+    status TEXT NOT NULL,
+    starts_at INTEGER,
+    ends_at INTEGER,
+    data TEXT NOT NULL
+);
+CREATE INDEX operations_status ON operations (status);
+CREATE INDEX operations_starts_at ON operations (starts_at);
+
+CREATE TABLE operation_components (
+    operation_id INTEGER NOT NULL,
+    component TEXT NOT NULL,
+    PRIMARY KEY (operation_id, component)
+);
+CREATE INDEX operation_components_component ON operation_components (component);
+
+CREATE TABLE operation_tags (
+    operation_id INTEGER NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (operation_id, tag)
+);
+CREATE INDEX operation_tags_tag ON operation_tags (tag);
+
+CREATE TABLE operation_dependencies (
+    operation_id INTEGER NOT NULL,
+    dependency INTEGER NOT NULL,
+    PRIMARY KEY (operation_id, dependency)
+);
+CREATE INDEX operation_dependencies_dependency ON operation_dependencies (dependency);
+
+CREATE TABLE components (
+    name TEXT PRIMARY KEY,
+    data TEXT NOT NULL
+);
+
+-- Owners and approvers of components
+CREATE TABLE component_principals (
+    component TEXT NOT NULL,
+    principal TEXT NOT NULL,
+    PRIMARY KEY (component, principal)
+);
+CREATE INDEX component_principals_principal ON component_principals (principal);
+
+CREATE TABLE tags (
+    name TEXT PRIMARY KEY,
+    data TEXT NOT NULL
+);
+
+-- Approvers of tags
+CREATE TABLE tag_principals (
+    tag TEXT NOT NULL,
+    principal TEXT NOT NULL,
+    PRIMARY KEY (tag, principal)
+);
+CREATE INDEX tag_principals_principal ON tag_principals (principal);
+
+CREATE TABLE groups (
+    name TEXT PRIMARY KEY,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE group_members (
+    group_name TEXT NOT NULL,
+    member TEXT NOT NULL,
+    PRIMARY KEY (group_name, member)
+);
+CREATE INDEX group_members_member ON group_members (member);
+
+-- timestamp is in microseconds since the Unix epoch.
+CREATE TABLE events (
+    id INTEGER PRIMARY KEY,
+    operation_id INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    status TEXT NOT NULL,
+    data TEXT NOT NULL
+);
+CREATE INDEX events_operation_id ON events (operation_id);
+CREATE INDEX events_timestamp ON events (timestamp);
+CREATE INDEX events_status ON events (status);
+
+CREATE TABLE event_components (
+    event_id INTEGER NOT NULL,
+    component TEXT NOT NULL,
+    PRIMARY KEY (event_id, component)
+);
+CREATE INDEX event_components_component ON event_components (component);
+
+CREATE TABLE event_tags (
+    event_id INTEGER NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (event_id, tag)
+);
+CREATE INDEX event_tags_tag ON event_tags (tag);
+
+CREATE TABLE webhooks (
+    id INTEGER PRIMARY KEY,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE failed_deliveries (
+    id INTEGER PRIMARY KEY,
+    webhook_id INTEGER NOT NULL,
+    data TEXT NOT NULL
+);
+CREATE INDEX failed_deliveries_webhook_id ON failed_deliveries (webhook_id);
+
+CREATE TABLE channels (
+    id TEXT PRIMARY KEY,
+    data TEXT NOT NULL
+);
+
+-- Columns as in user_subscriptions
+CREATE TABLE channel_subscriptions (
+    channel_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    PRIMARY KEY (channel_id, kind, subject)
+);
+CREATE INDEX channel_subscriptions_subject ON channel_subscriptions (kind, subject);
@@ -0,0 +1,7 @@
+-- hash is the SHA-256 hash of the token, as the token itself is not stored.
+CREATE TABLE refresh_tokens (
+    hash TEXT PRIMARY KEY,
+    username TEXT NOT NULL,
+    data TEXT NOT NULL
+);
+CREATE INDEX refresh_tokens_username ON refresh_tokens (username);
@@ -0,0 +1,13 @@
+CREATE TABLE service_accounts (
+    name TEXT PRIMARY KEY,
+    data TEXT NOT NULL
+);
+
+INSERT INTO meta (key, value) VALUES ('next_api_token_id', 1);
+
+CREATE TABLE api_tokens (
+    id INTEGER PRIMARY KEY,
+    account TEXT NOT NULL,
+    data TEXT NOT NULL
+);
+CREATE INDEX api_tokens_account ON api_tokens (account);
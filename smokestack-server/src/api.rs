@@ -1,48 +1,170 @@
+mod chat;
 mod components;
+mod groups;
+mod history;
 mod operations;
+mod service_accounts;
 mod subscriptions;
 mod tags;
+mod users;
+mod webhooks;
 
 use crate::{Error, Result, SharedState};
-use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
-use smokestack::{
-    api::{ApiResponse, AuthRequest, AuthResponse},
-    model::Claims,
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::post,
+    Json, Router,
 };
-use std::time::{Duration, SystemTime};
+use smokestack::api::{ApiResponse, AuthRequest, AuthResponse, RefreshTokenRequest};
 
 pub fn root() -> Router<SharedState> {
     Router::new()
         .route("/auth", post(auth))
+        .route("/auth/refresh", post(refresh))
+        .route("/auth/revoke", post(revoke))
+        .nest("/users", users::root())
         .nest("/operations", operations::root())
+        .nest("/history", history::root())
         .nest("/components", components::root())
         .nest("/tags", tags::root())
+        .nest("/groups", groups::root())
         .nest("/subscriptions", subscriptions::root())
+        .nest("/chat", chat::root())
+        .nest("/webhooks", webhooks::root())
+        .nest("/service-accounts", service_accounts::root())
 }
 
 async fn auth(
     State(state): State<SharedState>,
+    headers: HeaderMap,
     Json(req): Json<AuthRequest>,
 ) -> Result<(StatusCode, Json<ApiResponse<AuthResponse>>)> {
-    state.write().unwrap().create_user(req.username.clone())?;
-    let claims = Claims {
-        exp: SystemTime::now()
-            .checked_add(Duration::from_secs(60 * 60 * 24 * 365)) // FIXME: 1 year
-            .unwrap()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs(),
-        username: req.username,
-    };
-    jsonwebtoken::encode(
-        &jsonwebtoken::Header::default(),
-        &claims,
-        &jsonwebtoken::EncodingKey::from_secret(crate::JWT_SECRET),
-    )
-    .map_or(Err(Error::Internal), |token| {
-        Ok((
-            StatusCode::CREATED,
-            Json(ApiResponse::Ok(AuthResponse { token })),
-        ))
+    let auth = state.read().unwrap().auth.clone();
+    // Verifying a password hash takes a while, so keep it off the runtime.
+    tokio::task::spawn_blocking({
+        let username = req.username.clone();
+        move || auth.authenticate(&username, req.password.as_deref(), &headers)
     })
+    .await
+    .map_err(|_| Error::Internal)??;
+    let mut state = state.write().unwrap();
+    state.ensure_user(req.username.clone())?;
+    let tokens = state.issue_tokens(&req.username, None)?;
+    state.commit()?;
+    Ok((StatusCode::CREATED, Json(ApiResponse::Ok(tokens))))
+}
+
+async fn refresh(
+    State(state): State<SharedState>,
+    Json(req): Json<RefreshTokenRequest>,
+) -> Result<(StatusCode, Json<ApiResponse<AuthResponse>>)> {
+    let mut state = state.write().unwrap();
+    let tokens = state.refresh(&req.refresh_token)?;
+    state.commit()?;
+    Ok((StatusCode::CREATED, Json(ApiResponse::Ok(tokens))))
+}
+
+async fn revoke(
+    State(state): State<SharedState>,
+    Json(req): Json<RefreshTokenRequest>,
+) -> Result<Json<ApiResponse<()>>> {
+    let mut state = state.write().unwrap();
+    state.revoke(&req.refresh_token)?;
+    state.commit()?;
+    Ok(Json(ApiResponse::Ok(())))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TestServer;
+    use axum::http::StatusCode;
+    use serde_json::{json, Value};
+
+    async fn login(server: &TestServer, username: &str) -> Value {
+        let (status, body) = server
+            .post("/auth", "", json!({ "username": username }))
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        body
+    }
+
+    async fn refresh(server: &TestServer, refresh_token: &Value) -> (StatusCode, Value) {
+        server
+            .post(
+                "/auth/refresh",
+                "",
+                json!({ "refresh_token": refresh_token }),
+            )
+            .await
+    }
+
+    #[tokio::test]
+    async fn logs_in_existing_users_again() {
+        let server = TestServer::new();
+        server.login("alice");
+        for _ in 0..2 {
+            let tokens = login(&server, "alice").await;
+            let (status, body) = server
+                .get("/users/me", tokens["token"].as_str().unwrap())
+                .await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            assert_eq!(body["name"], "alice");
+        }
+    }
+
+    #[tokio::test]
+    async fn refresh_tokens_are_rotated() {
+        let server = TestServer::new();
+        let tokens = login(&server, "alice").await;
+
+        let (status, refreshed) = refresh(&server, &tokens["refresh_token"]).await;
+        assert_eq!(status, StatusCode::CREATED, "{refreshed}");
+        assert_ne!(refreshed["refresh_token"], tokens["refresh_token"]);
+        let (status, body) = server
+            .get("/users/me", refreshed["token"].as_str().unwrap())
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        let (status, body) = refresh(&server, &refreshed["refresh_token"]).await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+    }
+
+    #[tokio::test]
+    async fn reusing_a_refresh_token_revokes_the_session() {
+        let server = TestServer::new();
+        let stolen = login(&server, "alice").await;
+        let other_session = login(&server, "alice").await;
+        let (status, refreshed) = refresh(&server, &stolen["refresh_token"]).await;
+        assert_eq!(status, StatusCode::CREATED, "{refreshed}");
+
+        let (status, body) = refresh(&server, &stolen["refresh_token"]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+        let (status, body) = refresh(&server, &refreshed["refresh_token"]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+
+        let (status, body) = refresh(&server, &other_session["refresh_token"]).await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+    }
+
+    #[tokio::test]
+    async fn revoked_refresh_tokens_cannot_be_used() {
+        let server = TestServer::new();
+        let tokens = login(&server, "alice").await;
+        let (status, refreshed) = refresh(&server, &tokens["refresh_token"]).await;
+        assert_eq!(status, StatusCode::CREATED, "{refreshed}");
+
+        let request = json!({ "refresh_token": refreshed["refresh_token"] });
+        let (status, body) = server.post("/auth/revoke", "", request).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let (status, body) = refresh(&server, &refreshed["refresh_token"]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_refresh_tokens() {
+        let server = TestServer::new();
+        let (status, body) = refresh(&server, &json!("0123456789abcdef")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+    }
 }
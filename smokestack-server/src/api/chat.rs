@@ -0,0 +1,234 @@
+use super::subscriptions::list_subscriptions_of;
+use crate::{chat, AppState, Error, Result, SharedState};
+use axum::{extract::State, http::Uri, routing::post, Form, Json, Router};
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use smokestack::{
+    api::{ListOperationsQuery, ListSubscriptionResponse},
+    args::{ListArgs, SubscribeArgs, UnsubscribeArgs},
+    model::ChatFlavor,
+};
+use std::fmt::Write;
+use subtle::ConstantTimeEq;
+
+pub fn root() -> Router<SharedState> {
+    Router::new().route("/commands", post(command))
+}
+
+/// Payload of a slash command sent by Slack or Mattermost
+#[derive(Debug, Deserialize)]
+struct SlashCommand {
+    token: String,
+    channel_id: String,
+    channel_name: String,
+
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Debug, Serialize)]
+struct CommandResponse {
+    response_type: &'static str,
+    text: String,
+}
+
+/// Commands accepted from chat.
+///
+/// The grammar mirrors the CLI.
+#[derive(Debug, Parser)]
+#[command(name = "/smokestack", no_binary_name = true)]
+enum ChatCommand {
+    /// Post notifications to this channel through an incoming webhook
+    Setup {
+        /// URL of the incoming webhook that posts to this channel
+        webhook_url: Uri,
+
+        #[arg(long, default_value = "slack")]
+        flavor: ChatFlavor,
+    },
+
+    /// Subscribe this channel to operations
+    Subscribe(SubscribeArgs),
+
+    /// Unsubscribe this channel from operations
+    Unsubscribe(UnsubscribeArgs),
+
+    /// List operations
+    List(ListArgs),
+}
+
+async fn command(
+    State(state): State<SharedState>,
+    Form(req): Form<SlashCommand>,
+) -> Result<Json<CommandResponse>> {
+    let mut state = state.write().unwrap();
+    let is_valid = state
+        .chat_token
+        .as_ref()
+        .is_some_and(|token| token.as_bytes().ct_eq(req.token.as_bytes()).into());
+    if !is_valid {
+        return Err(Error::Forbidden);
+    }
+    let text = match shlex::split(&req.text) {
+        Some(args) => match ChatCommand::try_parse_from(args) {
+            Ok(command) => invoke(&mut state, req.channel_id, req.channel_name, command)
+                .and_then(|text| {
+                    state.commit()?;
+                    Ok(text)
+                })
+                .unwrap_or_else(|e| e.to_string()),
+            Err(e) => format!("```\n{}```", e.render()),
+        },
+        None => "invalid quoting".to_owned(),
+    };
+    Ok(Json(CommandResponse {
+        response_type: "ephemeral",
+        text,
+    }))
+}
+
+fn invoke(
+    state: &mut AppState,
+    channel_id: String,
+    channel_name: String,
+    command: ChatCommand,
+) -> Result<String> {
+    match command {
+        ChatCommand::Setup {
+            webhook_url,
+            flavor,
+        } => {
+            state.setup_channel(channel_id, channel_name, webhook_url, flavor)?;
+            Ok("Notifications will be posted to this channel.".to_owned())
+        }
+        ChatCommand::Subscribe(args) => {
+            let Some(request) = args.into_request() else {
+                let ListSubscriptionResponse {
+                    operations,
+                    components,
+                    tags,
+                } = list_subscriptions_of(&state.channel(&channel_id)?.subscriptions);
+                let mut text = String::new();
+                for (kind, target, filter) in operations
+                    .into_iter()
+                    .map(|entry| ("operation", entry.target.to_string(), entry.filter))
+                    .chain(
+                        components
+                            .into_iter()
+                            .map(|entry| ("component", entry.target, entry.filter)),
+                    )
+                    .chain(
+                        tags.into_iter()
+                            .map(|entry| ("tag", entry.target, entry.filter)),
+                    )
+                {
+                    write!(&mut text, "{kind} {target}").unwrap();
+                    if !filter.statuses.is_empty() {
+                        let statuses: Vec<_> =
+                            filter.statuses.iter().map(ToString::to_string).collect();
+                        write!(&mut text, " --status {}", statuses.join(" ")).unwrap();
+                    }
+                    if !filter.kinds.is_empty() {
+                        let kinds: Vec<_> = filter.kinds.iter().map(ToString::to_string).collect();
+                        write!(&mut text, " --kind {}", kinds.join(" ")).unwrap();
+                    }
+                    text.push('\n');
+                }
+                if text.is_empty() {
+                    text.push_str("This channel is not subscribed to anything.");
+                }
+                return Ok(text);
+            };
+            state.subscribe_channel(
+                &channel_id,
+                request.operation,
+                request.component,
+                request.tag,
+                request.filter,
+            )?;
+            Ok("Subscribed.".to_owned())
+        }
+        ChatCommand::Unsubscribe(args) => {
+            state.unsubscribe_channel(
+                &channel_id,
+                args.operation,
+                args.component.as_deref(),
+                args.tag.as_deref(),
+            )?;
+            Ok("Unsubscribed.".to_owned())
+        }
+        ChatCommand::List(args) => {
+            let query = ListOperationsQuery::from(args);
+            let flavor = state
+                .channel(&channel_id)
+                .map_or(ChatFlavor::Slack, |channel| channel.flavor);
+            let mut text = String::new();
+            for operation in state.operations(&query)? {
+                writeln!(&mut text, "{}", chat::format_operation(flavor, &operation)).unwrap();
+            }
+            if text.is_empty() {
+                text.push_str("No operations found.");
+            }
+            Ok(text)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TestServer;
+    use axum::http::StatusCode;
+
+    #[tokio::test]
+    async fn rejects_invalid_tokens() {
+        let server = TestServer::with_chat(&["hooks.slack.com"]);
+        for token in ["", "s3cre", "s3cret!", "wrong!"] {
+            let (status, _) = server.chat_command(token, "list").await;
+            assert_eq!(status, StatusCode::FORBIDDEN);
+        }
+        let (status, body) = server.chat_command("s3cret", "list").await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["text"], "No operations found.");
+
+        // Without a token configured, every command is rejected.
+        let server = TestServer::new();
+        let (status, _) = server.chat_command("", "list").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn only_posts_to_allowed_hosts() {
+        let server = TestServer::with_chat(&["hooks.slack.com"]);
+        for url in [
+            "http://127.0.0.1:8080/admin",
+            "https://hooks.slack.com.example.com/services/T0",
+            "https://example.com/hooks.slack.com",
+        ] {
+            let (status, body) = server.chat_command("s3cret", &format!("setup {url}")).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            assert_eq!(
+                body["text"], "webhook url should point to one of: hooks.slack.com",
+                "{url}"
+            );
+        }
+        assert!(server
+            .state
+            .read()
+            .unwrap()
+            .storage
+            .channels()
+            .unwrap()
+            .is_empty());
+
+        let url = "https://hooks.slack.com/services/T0/B0/X";
+        let (status, body) = server.chat_command("s3cret", &format!("setup {url}")).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(
+            body["text"],
+            "Notifications will be posted to this channel."
+        );
+        let channels = server.state.read().unwrap().storage.channels().unwrap();
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].webhook_url.to_string(), url);
+    }
+}
@@ -2,12 +2,11 @@ use crate::{Result, SharedState};
 use axum::{
     extract::{Path, State},
     http::StatusCode,
-    response::IntoResponse,
-    routing::{get, post},
+    routing::{get, patch, post},
     Json, Router,
 };
 use smokestack::{
-    api::{ApiResponse, CreateComponentRequest, ListComponentsResponse},
+    api::{ApiResponse, CreateComponentRequest, ListComponentsResponse, UpdateComponentRequest},
     model::{Claims, Component},
 };
 
@@ -16,13 +15,17 @@ pub fn root() -> Router<SharedState> {
         .route("/", post(create_component))
         .route("/", get(list_components))
         .route("/:name", get(get_component))
+        .route("/:name", patch(update_component))
 }
 
-async fn list_components(_claims: Claims, State(state): State<SharedState>) -> impl IntoResponse {
+async fn list_components(
+    _claims: Claims,
+    State(state): State<SharedState>,
+) -> Result<Json<ApiResponse<ListComponentsResponse>>> {
     let state = state.read().unwrap();
-    Json(ApiResponse::Ok(ListComponentsResponse {
-        components: state.components().cloned().collect::<Vec<_>>(),
-    }))
+    Ok(Json(ApiResponse::Ok(ListComponentsResponse {
+        components: state.components()?,
+    })))
 }
 
 async fn create_component(
@@ -35,8 +38,10 @@ async fn create_component(
         name: req.name,
         description: req.description,
         owners: req.owners,
+        approval: req.approval,
     };
     let component = state.create_component(component)?;
+    state.commit()?;
     Ok((StatusCode::CREATED, Json(ApiResponse::Ok(component))))
 }
 
@@ -46,5 +51,83 @@ async fn get_component(
     Path(name): Path<String>,
 ) -> Result<Json<ApiResponse<Component>>> {
     let state = state.read().unwrap();
-    Ok(Json(ApiResponse::Ok(state.component(&name)?.clone())))
+    Ok(Json(ApiResponse::Ok(state.component(&name)?)))
+}
+
+async fn update_component(
+    claims: Claims,
+    State(state): State<SharedState>,
+    Path(name): Path<String>,
+    Json(req): Json<UpdateComponentRequest>,
+) -> Result<Json<ApiResponse<Component>>> {
+    let mut state = state.write().unwrap();
+    let mut component = state.component(&name)?;
+    if let Some(description) = req.description {
+        component.description = description;
+    }
+    if let Some(owners) = req.owners {
+        component.owners = owners;
+    }
+    if let Some(requires_approval_by) = req.requires_approval_by {
+        component.approval.requires_approval_by = requires_approval_by;
+    }
+    if let Some(required_approvals) = req.required_approvals {
+        component.approval.required_approvals = required_approvals;
+    }
+    let component = state.update_component(&claims.username, component)?;
+    state.commit()?;
+    Ok(Json(ApiResponse::Ok(component)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TestServer;
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn only_owners_and_admins_change_owners_and_approvals() {
+        let server = TestServer::with_admins(&["carol"]);
+        let alice = server.login("alice");
+        let bob = server.login("bob");
+        let carol = server.login("carol");
+        server.create_component(&alice, "foo", &["alice"]).await;
+
+        for change in [
+            json!({ "owners": ["bob"] }),
+            json!({ "requires_approval_by": ["bob"] }),
+            json!({ "required_approvals": 2 }),
+        ] {
+            let (status, body) = server.patch("/components/foo", &bob, change).await;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+        }
+        let (_, body) = server.get("/components/foo", &bob).await;
+        assert_eq!(body["owners"], json!(["alice"]));
+        assert_eq!(body["required_approvals"], 0);
+
+        // Anyone may change the description, or restate the current values.
+        let (status, body) = server
+            .patch(
+                "/components/foo",
+                &bob,
+                json!({ "description": "Foo", "owners": ["alice"] }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        let (status, body) = server
+            .patch(
+                "/components/foo",
+                &alice,
+                json!({ "required_approvals": 1 }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let (status, body) = server
+            .patch("/components/foo", &carol, json!({ "owners": ["bob"] }))
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["owners"], json!(["bob"]));
+        assert_eq!(body["required_approvals"], 1);
+    }
 }
@@ -0,0 +1,208 @@
+use crate::{Error, Result, SharedState};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{delete, get, patch, post, put},
+    Json, Router,
+};
+use smokestack::{
+    api::{ApiResponse, CreateGroupRequest, ListGroupsResponse, UpdateGroupRequest},
+    model::{Claims, Group},
+};
+
+pub fn root() -> Router<SharedState> {
+    Router::new()
+        .route("/", post(create_group))
+        .route("/", get(list_groups))
+        .route("/:name", get(get_group))
+        .route("/:name", patch(update_group))
+        .route("/:name", delete(delete_group))
+        .route("/:name/members/:username", put(add_member))
+        .route("/:name/members/:username", delete(remove_member))
+}
+
+async fn list_groups(
+    _claims: Claims,
+    State(state): State<SharedState>,
+) -> Result<Json<ApiResponse<ListGroupsResponse>>> {
+    let state = state.read().unwrap();
+    Ok(Json(ApiResponse::Ok(ListGroupsResponse {
+        groups: state.groups()?,
+    })))
+}
+
+async fn create_group(
+    claims: Claims,
+    State(state): State<SharedState>,
+    Json(req): Json<CreateGroupRequest>,
+) -> Result<(StatusCode, Json<ApiResponse<Group>>)> {
+    let mut state = state.write().unwrap();
+    let group = Group {
+        name: req.name,
+        description: req.description,
+        members: req.members,
+    };
+    let group = state.create_group(&claims.username, group)?;
+    state.commit()?;
+    Ok((StatusCode::CREATED, Json(ApiResponse::Ok(group))))
+}
+
+async fn get_group(
+    _claims: Claims,
+    State(state): State<SharedState>,
+    Path(name): Path<String>,
+) -> Result<Json<ApiResponse<Group>>> {
+    let state = state.read().unwrap();
+    Ok(Json(ApiResponse::Ok(state.group(&name)?)))
+}
+
+async fn update_group(
+    claims: Claims,
+    State(state): State<SharedState>,
+    Path(name): Path<String>,
+    Json(req): Json<UpdateGroupRequest>,
+) -> Result<Json<ApiResponse<Group>>> {
+    let mut state = state.write().unwrap();
+    let mut group = state.group(&name)?;
+    if let Some(description) = req.description {
+        group.description = description;
+    }
+    if let Some(members) = req.members {
+        group.members = members;
+    }
+    let group = state.update_group(&claims.username, group)?;
+    state.commit()?;
+    Ok(Json(ApiResponse::Ok(group)))
+}
+
+async fn delete_group(
+    claims: Claims,
+    State(state): State<SharedState>,
+    Path(name): Path<String>,
+) -> Result<Json<ApiResponse<()>>> {
+    let mut state = state.write().unwrap();
+    state.delete_group(&claims.username, &name)?;
+    state.commit()?;
+    Ok(Json(ApiResponse::Ok(())))
+}
+
+async fn add_member(
+    claims: Claims,
+    State(state): State<SharedState>,
+    Path((name, username)): Path<(String, String)>,
+) -> Result<Json<ApiResponse<Group>>> {
+    let mut state = state.write().unwrap();
+    let mut group = state.group(&name)?;
+    group.members.push(username);
+    let group = state.update_group(&claims.username, group)?;
+    state.commit()?;
+    Ok(Json(ApiResponse::Ok(group)))
+}
+
+async fn remove_member(
+    claims: Claims,
+    State(state): State<SharedState>,
+    Path((name, username)): Path<(String, String)>,
+) -> Result<Json<ApiResponse<Group>>> {
+    let mut state = state.write().unwrap();
+    let mut group = state.group(&name)?;
+    if !group.members.contains(&username) {
+        return Err(Error::NotFound {
+            entity: "member",
+            id: username,
+        });
+    }
+    group.members.retain(|member| *member != username);
+    let group = state.update_group(&claims.username, group)?;
+    state.commit()?;
+    Ok(Json(ApiResponse::Ok(group)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TestServer;
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn members_manage_groups_without_admins() {
+        let server = TestServer::new();
+        let alice = server.login("alice");
+        let bob = server.login("bob");
+        let (status, body) = server
+            .post(
+                "/groups",
+                &alice,
+                json!({ "name": "ops", "description": "Ops", "members": ["alice"] }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+
+        for (method, path, body) in [
+            (
+                Method::PATCH,
+                "/groups/ops",
+                Some(json!({ "members": ["bob"] })),
+            ),
+            (Method::PUT, "/groups/ops/members/bob", None),
+            (Method::DELETE, "/groups/ops/members/alice", None),
+            (Method::DELETE, "/groups/ops", None),
+        ] {
+            let (status, body) = server.request(method, path, &bob, body).await;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+        }
+
+        let (status, body) = server
+            .request(Method::PUT, "/groups/ops/members/bob", &alice, None)
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["members"], json!(["alice", "bob"]));
+        let (status, body) = server
+            .request(Method::DELETE, "/groups/ops/members/alice", &bob, None)
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let (status, body) = server
+            .request(Method::DELETE, "/groups/ops", &alice, None)
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+        let (status, body) = server
+            .request(Method::DELETE, "/groups/ops", &bob, None)
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+    }
+
+    #[tokio::test]
+    async fn only_admins_manage_groups_when_configured() {
+        let server = TestServer::with_admins(&["carol"]);
+        let alice = server.login("alice");
+        let carol = server.login("carol");
+        let group = json!({ "name": "ops", "description": "Ops", "members": ["alice"] });
+
+        let (status, body) = server.post("/groups", &alice, group.clone()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+        let (status, body) = server.post("/groups", &carol, group).await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+
+        // Members are not trusted to manage the group.
+        let (status, body) = server
+            .patch(
+                "/groups/ops",
+                &alice,
+                json!({ "description": "Operations" }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+        let (status, body) = server
+            .patch(
+                "/groups/ops",
+                &carol,
+                json!({ "description": "Operations" }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let (status, body) = server
+            .request(Method::DELETE, "/groups/ops", &carol, None)
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+    }
+}
@@ -0,0 +1,222 @@
+use crate::{Result, SharedState};
+use axum::{extract::State, routing::get, Json, Router};
+use axum_extra::extract::Query;
+use smokestack::{
+    api::{ApiResponse, HistoryQuery, ListHistoryResponse},
+    model::Claims,
+};
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+pub fn root() -> Router<SharedState> {
+    Router::new().route("/", get(list_history))
+}
+
+async fn list_history(
+    _claims: Claims,
+    State(state): State<SharedState>,
+    Query(mut query): Query<HistoryQuery>,
+) -> Result<Json<ApiResponse<ListHistoryResponse>>> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    // Fetch one more event to tell whether there is a next page.
+    query.limit = Some(limit + 1);
+    let mut events = state.read().unwrap().events(&query)?;
+    let next = if events.len() > limit {
+        events.truncate(limit);
+        events.last().map(|event| event.id)
+    } else {
+        None
+    };
+    Ok(Json(ApiResponse::Ok(ListHistoryResponse { events, next })))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TestServer;
+    use axum::http::StatusCode;
+    use serde_json::{json, Value};
+
+    fn event_ids(body: &Value) -> Vec<u64> {
+        body["events"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|event| event["id"].as_u64().unwrap())
+            .collect()
+    }
+
+    fn operation_ids(body: &Value) -> Vec<u64> {
+        body["events"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|event| event["operation"]["id"].as_u64().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn pages_through_events_in_order() {
+        let server = TestServer::new();
+        let alice = server.login("alice");
+        server.create_component(&alice, "foo", &["alice"]).await;
+        let first = server.create_operation(&alice, &["foo"]).await;
+        let second = server.create_operation(&alice, &["foo"]).await;
+        server.transition(&alice, first, "in_progress").await;
+        server.transition(&alice, second, "canceled").await;
+        server.transition(&alice, first, "completed").await;
+
+        let (status, body) = server.get("/history", &alice).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let all = event_ids(&body);
+        assert_eq!(all.len(), 5);
+        assert!(all.windows(2).all(|ids| ids[0] < ids[1]), "{all:?}");
+        assert!(body.get("next").is_none());
+        assert_eq!(operation_ids(&body), [first, second, first, second, first]);
+
+        let (_, body) = server.get("/history?limit=2", &alice).await;
+        assert_eq!(event_ids(&body), all[..2]);
+        assert_eq!(body["next"], all[1]);
+
+        let (_, body) = server
+            .get(&format!("/history?limit=2&after={}", all[1]), &alice)
+            .await;
+        assert_eq!(event_ids(&body), all[2..4]);
+        assert_eq!(body["next"], all[3]);
+
+        let (_, body) = server
+            .get(&format!("/history?limit=2&after={}", all[3]), &alice)
+            .await;
+        assert_eq!(event_ids(&body), all[4..]);
+        assert!(body.get("next").is_none());
+
+        // A page that exactly fits the remaining events has no next page.
+        let (_, body) = server.get("/history?limit=5", &alice).await;
+        assert_eq!(event_ids(&body), all);
+        assert!(body.get("next").is_none());
+    }
+
+    #[tokio::test]
+    async fn clamps_the_limit() {
+        let server = TestServer::new();
+        let alice = server.login("alice");
+        server.create_component(&alice, "foo", &["alice"]).await;
+        server.create_operation(&alice, &["foo"]).await;
+        server.create_operation(&alice, &["foo"]).await;
+
+        // A page always has at least one event, so that paging progresses.
+        let (status, body) = server.get("/history?limit=0", &alice).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let first = event_ids(&body);
+        assert_eq!(first.len(), 1);
+        assert_eq!(body["next"], first[0]);
+
+        let (status, body) = server
+            .get(&format!("/history?limit={}", usize::MAX), &alice)
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(event_ids(&body).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn filters_events_by_operation() {
+        let server = TestServer::new();
+        let alice = server.login("alice");
+        server.login("bob");
+        server.create_component(&alice, "foo", &["alice"]).await;
+        server.create_component(&alice, "bar", &["alice"]).await;
+        let (status, body) = server
+            .post(
+                "/tags",
+                &alice,
+                json!({ "name": "db", "description": "db" }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let (status, body) = server
+            .post(
+                "/operations",
+                &alice,
+                json!({
+                    "title": "Tagged",
+                    "purpose": "Testing",
+                    "url": "https://example.com/",
+                    "components": ["foo"],
+                    "tags": ["db"],
+                }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let tagged = body["id"].as_u64().unwrap();
+        let (status, body) = server
+            .post(
+                "/operations",
+                &alice,
+                json!({
+                    "title": "Delegated",
+                    "purpose": "Testing",
+                    "url": "https://example.com/",
+                    "components": ["bar"],
+                    "operators": ["bob"],
+                }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let delegated = body["id"].as_u64().unwrap();
+        server.transition(&alice, tagged, "in_progress").await;
+
+        for (query, expected) in [
+            ("component=foo", vec![tagged, tagged]),
+            ("component=bar", vec![delegated]),
+            (
+                "component=foo&component=bar",
+                vec![tagged, delegated, tagged],
+            ),
+            ("tag=db", vec![tagged, tagged]),
+            ("operator=bob", vec![delegated]),
+            ("operator=alice", vec![tagged, tagged]),
+            ("status=planned", vec![tagged, delegated]),
+            ("status=in_progress", vec![tagged]),
+            ("component=foo&status=planned", vec![tagged]),
+            ("component=baz", vec![]),
+        ] {
+            let (status, body) = server.get(&format!("/history?{query}"), &alice).await;
+            assert_eq!(status, StatusCode::OK, "{query}: {body}");
+            assert_eq!(operation_ids(&body), expected, "{query}");
+        }
+    }
+
+    #[tokio::test]
+    async fn filters_events_by_time() {
+        let server = TestServer::new();
+        let alice = server.login("alice");
+        server.create_component(&alice, "foo", &["alice"]).await;
+        for _ in 0..3 {
+            server.create_operation(&alice, &["foo"]).await;
+        }
+        let (_, body) = server.get("/history", &alice).await;
+        let events = body["events"].as_array().unwrap();
+        let all = event_ids(&body);
+        let middle = events[1]["timestamp"].as_str().unwrap();
+
+        // since is inclusive and until is exclusive, so that consecutive
+        // ranges do not overlap.
+        let (status, body) = server
+            .get(&format!("/history?since={middle}"), &alice)
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(event_ids(&body), all[1..]);
+        let (_, body) = server
+            .get(&format!("/history?until={middle}"), &alice)
+            .await;
+        assert_eq!(event_ids(&body), all[..1]);
+        let (_, body) = server
+            .get(&format!("/history?since={middle}&until={middle}"), &alice)
+            .await;
+        assert!(event_ids(&body).is_empty());
+
+        let (status, _) = server.get("/history?since=yesterday", &alice).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -1,17 +1,15 @@
-use crate::Result;
-use crate::SharedState;
+use crate::{Error, Result, SharedState};
 use axum::{
     extract::{Path, State},
     http::StatusCode,
-    response::IntoResponse,
-    routing::{get, patch, post},
+    routing::{delete, get, patch, post},
     Json, Router,
 };
 use axum_extra::extract::Query;
 use smokestack::{
     api::{
-        ApiResponse, CreateOperationRequest, ListOperationsQuery, ListOperationsResponse,
-        UpdateOperationRequest,
+        ApiResponse, CreateOperationRequest, ListHistoryResponse, ListOperationsQuery,
+        ListOperationsResponse, UpdateOperationRequest,
     },
     model::{Claims, Operation, OperationState},
 };
@@ -22,6 +20,9 @@ pub fn root() -> Router<SharedState> {
         .route("/", get(list_operations))
         .route("/:id", get(get_operation))
         .route("/:id", patch(update_operation))
+        .route("/:id/history", get(get_operation_history))
+        .route("/:id/approvals", post(approve_operation))
+        .route("/:id/approvals", delete(revoke_approval))
 }
 
 async fn create_operation(
@@ -30,10 +31,21 @@ async fn create_operation(
     Json(mut req): Json<CreateOperationRequest>,
 ) -> Result<(StatusCode, Json<ApiResponse<Operation>>)> {
     let mut state = state.write().unwrap();
+    if let Some(user) = &req.on_behalf_of {
+        // Only automation acts on behalf of the humans responsible for it.
+        if !state.is_service_account(&claims.username)? {
+            return Err(Error::Forbidden);
+        }
+        if state.is_service_account(user)? {
+            return Err(Error::ServiceAccount(user.clone()));
+        }
+        state.user(user)?;
+    }
     if req.operators.is_empty() {
-        req.operators.push(claims.username);
+        let operator = req.on_behalf_of.as_ref().unwrap_or(&claims.username);
+        req.operators.push(operator.clone());
     }
-    let id = state.next_id();
+    let id = state.next_id()?;
     let operation = Operation {
         id,
         title: req.title,
@@ -43,11 +55,16 @@ async fn create_operation(
         locks: req.locks,
         tags: req.tags,
         depends_on: req.depends_on,
+        starts_at: req.starts_at,
+        ends_at: req.ends_at,
         operators: req.operators,
+        on_behalf_of: req.on_behalf_of,
+        approved_by: Vec::new(),
         status: OperationState::Planned,
         annotations: req.annotations,
     };
-    let operation = state.upsert_operation(operation)?;
+    let operation = state.upsert_operation(&claims.username, operation)?;
+    state.commit()?;
     Ok((StatusCode::CREATED, Json(ApiResponse::Ok(operation))))
 }
 
@@ -55,36 +72,9 @@ async fn list_operations(
     _claims: Claims,
     State(state): State<SharedState>,
     Query(query): Query<ListOperationsQuery>,
-) -> impl IntoResponse {
-    let state = state.read().unwrap();
-    let operations = state.operations().filter(|operation| {
-        if !query.components.is_empty()
-            && !operation
-                .components
-                .iter()
-                .any(|component| query.components.contains(component))
-        {
-            return false;
-        }
-        if !query.tags.is_empty() && !operation.tags.iter().any(|tag| query.tags.contains(tag)) {
-            return false;
-        }
-        if !query.operators.is_empty()
-            && !operation
-                .operators
-                .iter()
-                .any(|operator| query.operators.contains(operator))
-        {
-            return false;
-        }
-        if !query.statuses.is_empty() && !query.statuses.contains(&operation.status) {
-            return false;
-        }
-        true
-    });
-    Json(ApiResponse::Ok(ListOperationsResponse {
-        operations: operations.cloned().collect::<Vec<_>>(),
-    }))
+) -> Result<Json<ApiResponse<ListOperationsResponse>>> {
+    let operations = state.read().unwrap().operations(&query)?;
+    Ok(Json(ApiResponse::Ok(ListOperationsResponse { operations })))
 }
 
 async fn get_operation(
@@ -93,17 +83,30 @@ async fn get_operation(
     Path(id): Path<u64>,
 ) -> Result<Json<ApiResponse<Operation>>> {
     let state = state.read().unwrap();
-    Ok(Json(ApiResponse::Ok(state.operation(id)?.clone())))
+    Ok(Json(ApiResponse::Ok(state.operation(id)?)))
 }
 
-async fn update_operation(
+async fn get_operation_history(
     _claims: Claims,
     State(state): State<SharedState>,
     Path(id): Path<u64>,
+) -> Result<Json<ApiResponse<ListHistoryResponse>>> {
+    let state = state.read().unwrap();
+    let events = state.history(id)?;
+    Ok(Json(ApiResponse::Ok(ListHistoryResponse {
+        events,
+        next: None,
+    })))
+}
+
+async fn update_operation(
+    claims: Claims,
+    State(state): State<SharedState>,
+    Path(id): Path<u64>,
     Json(req): Json<UpdateOperationRequest>,
 ) -> Result<Json<ApiResponse<Operation>>> {
     let mut state = state.write().unwrap();
-    let mut operation = state.operation(id)?.clone();
+    let mut operation = state.operation(id)?;
     if let Some(title) = req.title {
         operation.title = title;
     }
@@ -125,6 +128,12 @@ async fn update_operation(
     if let Some(depends_on) = req.depends_on {
         operation.depends_on = depends_on;
     }
+    if let Some(starts_at) = req.starts_at {
+        operation.starts_at = starts_at;
+    }
+    if let Some(ends_at) = req.ends_at {
+        operation.ends_at = ends_at;
+    }
     if let Some(operators) = req.operators {
         operation.operators = operators;
     }
@@ -132,5 +141,613 @@ async fn update_operation(
         operation.status = status;
     }
     operation.annotations.extend(req.annotations);
-    Ok(Json(ApiResponse::Ok(state.upsert_operation(operation)?)))
+    let operation = state.upsert_operation(&claims.username, operation)?;
+    state.commit()?;
+    Ok(Json(ApiResponse::Ok(operation)))
+}
+
+async fn approve_operation(
+    claims: Claims,
+    State(state): State<SharedState>,
+    Path(id): Path<u64>,
+) -> Result<(StatusCode, Json<ApiResponse<Operation>>)> {
+    let mut state = state.write().unwrap();
+    let operation = state.approve_operation(&claims.username, id)?;
+    state.commit()?;
+    Ok((StatusCode::CREATED, Json(ApiResponse::Ok(operation))))
+}
+
+async fn revoke_approval(
+    claims: Claims,
+    State(state): State<SharedState>,
+    Path(id): Path<u64>,
+) -> Result<Json<ApiResponse<Operation>>> {
+    let mut state = state.write().unwrap();
+    let operation = state.revoke_approval(&claims.username, id)?;
+    state.commit()?;
+    Ok(Json(ApiResponse::Ok(operation)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TestServer;
+    use axum::http::{Method, StatusCode};
+    use chrono::{DateTime, TimeDelta, Utc};
+    use serde_json::{json, Value};
+
+    fn time(value: &Value) -> DateTime<Utc> {
+        serde_json::from_value(value.clone()).unwrap()
+    }
+
+    /// Returns the body of a request creating an operation on foo, with the
+    /// fields replacing the defaults.
+    fn new_operation(fields: Value) -> Value {
+        let mut body = json!({
+            "title": "Test",
+            "purpose": "Testing",
+            "url": "https://example.com/",
+            "components": ["foo"],
+        });
+        body.as_object_mut()
+            .unwrap()
+            .extend(fields.as_object().unwrap().clone());
+        body
+    }
+
+    #[tokio::test]
+    async fn history_lists_the_events_of_the_operation_in_order() {
+        let server = TestServer::new();
+        let alice = server.login("alice");
+        let bob = server.login("bob");
+        server.create_component(&alice, "foo", &["alice"]).await;
+        let id = server.create_operation(&alice, &["foo"]).await;
+        let other = server.create_operation(&alice, &["foo"]).await;
+        server.transition(&bob, id, "in_progress").await;
+        server.transition(&alice, other, "canceled").await;
+        server.transition(&alice, id, "completed").await;
+
+        let (status, body) = server
+            .get(&format!("/operations/{id}/history"), &alice)
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let events = body["events"].as_array().unwrap();
+        let summary: Vec<_> = events
+            .iter()
+            .map(|event| {
+                (
+                    event["kind"].as_str().unwrap(),
+                    event["actor"].as_str().unwrap(),
+                    event["operation"]["status"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("created", "alice", "planned"),
+                ("transitioned", "bob", "in_progress"),
+                ("transitioned", "alice", "completed"),
+            ]
+        );
+        assert!(events
+            .windows(2)
+            .all(|e| e[0]["id"].as_u64() < e[1]["id"].as_u64()));
+        assert_eq!(
+            events[1]["changes"]["status"]["old"].as_str(),
+            Some("planned")
+        );
+
+        let (status, _) = server.get("/operations/1/history", &alice).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn records_actual_start_and_end_times() {
+        let server = TestServer::new();
+        let alice = server.login("alice");
+        server.create_component(&alice, "foo", &["alice"]).await;
+        let scheduled_start: DateTime<Utc> = "2100-01-01T00:00:00Z".parse().unwrap();
+        let scheduled_end: DateTime<Utc> = "2100-01-02T00:00:00Z".parse().unwrap();
+        let (status, body) = server
+            .post(
+                "/operations",
+                &alice,
+                json!({
+                    "title": "Scheduled",
+                    "purpose": "Testing",
+                    "url": "https://example.com/",
+                    "components": ["foo"],
+                    "starts_at": scheduled_start,
+                    "ends_at": scheduled_end,
+                }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let id = body["id"].as_u64().unwrap();
+        let path = format!("/operations/{id}");
+
+        // Starting the operation replaces the scheduled start time with the
+        // actual one, which cannot be edited afterwards.
+        let before = Utc::now();
+        server.transition(&alice, id, "in_progress").await;
+        let (_, body) = server.get(&path, &alice).await;
+        let started = time(&body["starts_at"]);
+        assert!(before <= started && started <= Utc::now(), "{body}");
+        assert_eq!(time(&body["ends_at"]), scheduled_end);
+        let (status, body) = server
+            .patch(
+                &path,
+                &alice,
+                json!({ "url": null, "starts_at": scheduled_start }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(time(&body["starts_at"]), started);
+
+        // Finishing it does the same with the end time.
+        server.transition(&alice, id, "completed").await;
+        let (_, body) = server.get(&path, &alice).await;
+        let ended = time(&body["ends_at"]);
+        assert!(started <= ended && ended <= Utc::now(), "{body}");
+        let (status, body) = server
+            .patch(
+                &path,
+                &alice,
+                json!({ "url": null, "starts_at": null, "ends_at": null }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(time(&body["starts_at"]), started);
+        assert_eq!(time(&body["ends_at"]), ended);
+    }
+
+    #[tokio::test]
+    async fn clears_overdue_end_times_of_running_operations() {
+        let server = TestServer::new();
+        let alice = server.login("alice");
+        server.create_component(&alice, "foo", &["alice"]).await;
+        let now = Utc::now();
+        let (status, body) = server
+            .post(
+                "/operations",
+                &alice,
+                json!({
+                    "title": "Overdue",
+                    "purpose": "Testing",
+                    "url": "https://example.com/",
+                    "components": ["foo"],
+                    "starts_at": now - TimeDelta::hours(2),
+                    "ends_at": now - TimeDelta::hours(1),
+                }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        assert_eq!(time(&body["ends_at"]), now - TimeDelta::hours(1));
+        let id = body["id"].as_u64().unwrap();
+
+        server.transition(&alice, id, "in_progress").await;
+        let (_, body) = server.get(&format!("/operations/{id}"), &alice).await;
+        assert_eq!(body["ends_at"], Value::Null, "{body}");
+    }
+
+    #[tokio::test]
+    async fn rejects_schedules_conflicting_with_exclusive_locks() {
+        let server = TestServer::new();
+        let alice = server.login("alice");
+        for component in ["foo", "bar", "baz", "qux"] {
+            server.create_component(&alice, component, &["alice"]).await;
+        }
+        let at = |hours| {
+            let start: DateTime<Utc> = "2100-01-01T00:00:00Z".parse().unwrap();
+            start + TimeDelta::hours(hours)
+        };
+        let create = |fields| server.post("/operations", &alice, new_operation(fields));
+
+        let (status, body) = create(json!({
+            "locks": ["foo"], "starts_at": at(0), "ends_at": at(2),
+        }))
+        .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let exclusive = body["id"].as_u64().unwrap();
+        let (status, body) = create(json!({
+            "components": ["bar"], "starts_at": at(0), "ends_at": at(2),
+        }))
+        .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let shared = body["id"].as_u64().unwrap();
+        let (status, body) = create(json!({
+            "components": ["baz"], "locks": ["baz"], "starts_at": at(10),
+        }))
+        .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let open_ended = body["id"].as_u64().unwrap();
+        let (status, body) = create(json!({
+            "components": ["qux"], "locks": ["qux"], "ends_at": at(5),
+        }))
+        .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let running = body["id"].as_u64().unwrap();
+        server.transition(&alice, running, "in_progress").await;
+
+        // (fields of the new operation, conflicting operation and component)
+        let cases = [
+            (
+                json!({ "starts_at": at(1), "ends_at": at(3) }),
+                Some((exclusive, "foo")),
+            ),
+            (json!({ "starts_at": at(-1) }), Some((exclusive, "foo"))),
+            (json!({ "starts_at": at(2), "ends_at": at(3) }), None),
+            (json!({ "starts_at": at(-1), "ends_at": at(0) }), None),
+            (
+                json!({ "locks": ["foo"], "starts_at": at(1) }),
+                Some((exclusive, "foo")),
+            ),
+            // Shared locks only conflict with exclusive ones.
+            (
+                json!({ "components": ["bar"], "starts_at": at(1), "ends_at": at(3) }),
+                None,
+            ),
+            (
+                json!({ "components": ["bar"], "locks": ["bar"], "starts_at": at(1) }),
+                Some((shared, "bar")),
+            ),
+            // Operations without an end time hold their locks indefinitely.
+            (
+                json!({ "components": ["baz"], "starts_at": at(1000), "ends_at": at(1001) }),
+                Some((open_ended, "baz")),
+            ),
+            (
+                json!({ "components": ["baz"], "starts_at": at(0), "ends_at": at(10) }),
+                None,
+            ),
+            (
+                json!({ "components": ["qux"], "starts_at": at(1), "ends_at": at(2) }),
+                Some((running, "qux")),
+            ),
+            (json!({ "components": ["qux"], "starts_at": at(5) }), None),
+            // Operations without a start time are not scheduled.
+            (json!({ "components": ["foo", "baz", "qux"] }), None),
+        ];
+        for (fields, conflict) in cases {
+            let (status, body) = server
+                .post("/operations", &alice, new_operation(fields.clone()))
+                .await;
+            if let Some((operation, component)) = conflict {
+                assert_eq!(status, StatusCode::CONFLICT, "{fields}: {body}");
+                assert_eq!(
+                    body["error"],
+                    format!(
+                        "schedule conflicts with operation {operation} on exclusively locked \
+                         component {component}"
+                    ),
+                    "{fields}"
+                );
+            } else {
+                assert_eq!(status, StatusCode::CREATED, "{fields}: {body}");
+                // Remove the operation so that it does not conflict with the
+                // following ones.
+                server
+                    .transition(&alice, body["id"].as_u64().unwrap(), "canceled")
+                    .await;
+            }
+        }
+
+        // Finished operations no longer hold locks.
+        server.transition(&alice, running, "completed").await;
+        let (status, body) = create(json!({
+            "components": ["qux"], "locks": ["qux"], "starts_at": at(1), "ends_at": at(2),
+        }))
+        .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+    }
+
+    #[tokio::test]
+    async fn rejects_starting_before_dependencies_end() {
+        let server = TestServer::new();
+        let alice = server.login("alice");
+        server.create_component(&alice, "foo", &["alice"]).await;
+        let at = |hours| {
+            let start: DateTime<Utc> = "2100-01-01T00:00:00Z".parse().unwrap();
+            start + TimeDelta::hours(hours)
+        };
+        let (status, body) = server
+            .post(
+                "/operations",
+                &alice,
+                new_operation(json!({ "starts_at": at(0), "ends_at": at(2) })),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let dependency = body["id"].as_u64().unwrap();
+
+        let (status, body) = server
+            .post(
+                "/operations",
+                &alice,
+                new_operation(json!({ "depends_on": [dependency], "starts_at": at(1) })),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CONFLICT, "{body}");
+        let dependent = dependency + 1;
+        assert_eq!(
+            body["error"],
+            format!(
+                "operation {dependent} cannot be scheduled because it cannot meet the \
+                 dependency on operation {dependency}"
+            )
+        );
+
+        let (status, body) = server
+            .post(
+                "/operations",
+                &alice,
+                new_operation(json!({ "depends_on": [dependency], "starts_at": at(2) })),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let dependent = body["id"].as_u64().unwrap();
+
+        // Moving the dependency past the start of the dependent is rejected
+        // too.
+        let (status, body) = server
+            .patch(
+                &format!("/operations/{dependency}"),
+                &alice,
+                json!({ "url": null, "ends_at": at(3) }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CONFLICT, "{body}");
+        assert_eq!(
+            body["error"],
+            format!(
+                "operation {dependent} cannot be scheduled because it cannot meet the \
+                 dependency on operation {dependency}"
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_dependency_cycles() {
+        let server = TestServer::new();
+        let alice = server.login("alice");
+        server.create_component(&alice, "foo", &["alice"]).await;
+        let a = server.create_operation(&alice, &["foo"]).await;
+        let create_dependent = |depends_on: Vec<u64>| {
+            server.post(
+                "/operations",
+                &alice,
+                new_operation(json!({ "depends_on": depends_on })),
+            )
+        };
+        let (status, body) = create_dependent(vec![a]).await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let b = body["id"].as_u64().unwrap();
+        let (status, body) = create_dependent(vec![b]).await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let c = body["id"].as_u64().unwrap();
+        // Depending on the same operation through several paths is not a
+        // cycle.
+        let (status, body) = create_dependent(vec![a, b, c]).await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+
+        for (id, depends_on, cycle) in [
+            (a, vec![a], format!("{a} -> {a}")),
+            (a, vec![b], format!("{a} -> {b} -> {a}")),
+            (b, vec![c], format!("{b} -> {c} -> {b}")),
+            (a, vec![c], format!("{a} -> {c} -> {b} -> {a}")),
+        ] {
+            let (status, body) = server
+                .patch(
+                    &format!("/operations/{id}"),
+                    &alice,
+                    json!({ "url": null, "depends_on": depends_on }),
+                )
+                .await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+            assert_eq!(body["error"], format!("dependency cycle detected: {cycle}"));
+        }
+        let (_, body) = server.get(&format!("/operations/{a}"), &alice).await;
+        assert_eq!(body["depends_on"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn changing_the_url_while_starting_requires_new_approvals() {
+        let server = TestServer::new();
+        let alice = server.login("alice");
+        let bob = server.login("bob");
+        server.create_component(&alice, "foo", &["alice"]).await;
+        let (status, body) = server
+            .patch(
+                "/components/foo",
+                &alice,
+                json!({ "required_approvals": 1 }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let id = server.create_operation(&alice, &["foo"]).await;
+        let (status, body) = server
+            .post(&format!("/operations/{id}/approvals"), &bob, json!(null))
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+
+        let (status, body) = server
+            .patch(
+                &format!("/operations/{id}"),
+                &alice,
+                json!({ "url": "https://example.com/other", "status": "in_progress" }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+
+        let (status, body) = server.get(&format!("/operations/{id}"), &alice).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["status"], "planned");
+        assert_eq!(body["url"], "https://example.com/");
+        assert_eq!(body["approved_by"], json!(["bob"]));
+    }
+
+    #[tokio::test]
+    async fn material_edits_invalidate_approvals() {
+        let server = TestServer::new();
+        let alice = server.login("alice");
+        let bob = server.login("bob");
+        server.create_component(&alice, "foo", &["alice"]).await;
+        server.login("carol");
+        let dependency = server.create_operation(&alice, &["foo"]).await;
+        let starts_at: DateTime<Utc> = "2100-01-01T00:00:00Z".parse().unwrap();
+
+        for (edit, invalidates) in [
+            (json!({ "title": "Renamed", "purpose": "Other" }), false),
+            (json!({ "annotations": { "ticket": "123" } }), false),
+            (json!({ "url": "https://example.com/other" }), true),
+            (json!({ "locks": ["foo"] }), true),
+            (json!({ "operators": ["alice", "carol"] }), true),
+            (json!({ "depends_on": [dependency] }), true),
+            (json!({ "starts_at": starts_at }), true),
+            (json!({ "ends_at": starts_at + TimeDelta::hours(1) }), true),
+        ] {
+            let id = server.create_operation(&alice, &["foo"]).await;
+            let path = format!("/operations/{id}");
+            let (status, body) = server
+                .post(&format!("{path}/approvals"), &bob, json!(null))
+                .await;
+            assert_eq!(status, StatusCode::CREATED, "{body}");
+
+            let mut request = json!({ "url": null });
+            request
+                .as_object_mut()
+                .unwrap()
+                .extend(edit.as_object().unwrap().clone());
+            let (status, body) = server.patch(&path, &alice, request).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            let expected = if invalidates {
+                json!([])
+            } else {
+                json!(["bob"])
+            };
+            assert_eq!(body["approved_by"], expected, "{edit}");
+
+            let (_, body) = server.get(&format!("{path}/history"), &alice).await;
+            let last = body["events"].as_array().unwrap().last().unwrap();
+            let kind = if invalidates {
+                "approvals_invalidated"
+            } else {
+                "edited"
+            };
+            assert_eq!(last["kind"], kind, "{edit}");
+        }
+    }
+
+    #[tokio::test]
+    async fn invalidating_approvals_while_starting_is_recorded() {
+        let server = TestServer::new();
+        let alice = server.login("alice");
+        let bob = server.login("bob");
+        server.create_component(&alice, "foo", &["alice"]).await;
+        let id = server.create_operation(&alice, &["foo"]).await;
+        let path = format!("/operations/{id}");
+        let (status, body) = server
+            .post(&format!("{path}/approvals"), &bob, json!(null))
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+
+        let (status, body) = server
+            .patch(
+                &path,
+                &alice,
+                json!({ "url": "https://example.com/other", "status": "in_progress" }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["approved_by"], json!([]));
+
+        let (_, body) = server.get(&format!("{path}/history"), &alice).await;
+        let events = body["events"].as_array().unwrap();
+        let kinds: Vec<_> = events.iter().map(|event| &event["kind"]).collect();
+        assert_eq!(
+            kinds,
+            [
+                "created",
+                "approved",
+                "approvals_invalidated",
+                "transitioned"
+            ]
+        );
+        assert_eq!(events[2]["operation"]["status"], "planned");
+        assert_eq!(
+            events[2]["changes"]["url"]["new"],
+            "https://example.com/other"
+        );
+        assert_eq!(events[3]["changes"]["status"]["new"], "in_progress");
+        assert!(events[3]["changes"].get("approved_by").is_none());
+    }
+
+    #[tokio::test]
+    async fn approvers_revoke_their_approvals() {
+        let server = TestServer::new();
+        let alice = server.login("alice");
+        let bob = server.login("bob");
+        let carol = server.login("carol");
+        server.create_component(&alice, "foo", &["alice"]).await;
+        let id = server.create_operation(&alice, &["foo"]).await;
+        let approvals = format!("/operations/{id}/approvals");
+        for approver in [&bob, &carol] {
+            let (status, body) = server.post(&approvals, approver, json!(null)).await;
+            assert_eq!(status, StatusCode::CREATED, "{body}");
+        }
+
+        let (status, body) = server.request(Method::DELETE, &approvals, &bob, None).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["approved_by"], json!(["carol"]));
+        let (status, body) = server.request(Method::DELETE, &approvals, &bob, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{body}");
+
+        let (_, body) = server
+            .get(&format!("/operations/{id}/history"), &alice)
+            .await;
+        let last = body["events"].as_array().unwrap().last().unwrap();
+        assert_eq!(last["kind"], "approval_revoked");
+        assert_eq!(last["actor"], "bob");
+
+        server.transition(&alice, id, "in_progress").await;
+        let (status, body) = server
+            .request(Method::DELETE, &approvals, &carol, None)
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+    }
+
+    #[tokio::test]
+    async fn users_cannot_approve_operations_created_on_their_behalf() {
+        let server = TestServer::with_admins(&["alice"]);
+        let alice = server.login("alice");
+        let bob = server.login("bob");
+        server.create_component(&alice, "foo", &["alice"]).await;
+        let (status, body) = server
+            .post("/service-accounts", &alice, json!({ "name": "ci" }))
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let (status, body) = server
+            .post(
+                "/service-accounts/ci/tokens",
+                &alice,
+                json!({ "scopes": ["operations:write"] }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let ci = body["token"].as_str().unwrap().to_owned();
+        let (status, body) = server
+            .post(
+                "/operations",
+                &ci,
+                new_operation(json!({ "on_behalf_of": "bob", "operators": ["ci"] })),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let approvals = format!("/operations/{}/approvals", body["id"]);
+
+        let (status, body) = server.post(&approvals, &bob, json!(null)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+        let (status, body) = server.post(&approvals, &alice, json!(null)).await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+    }
 }
@@ -0,0 +1,163 @@
+use crate::{Result, SharedState};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{delete, get, post},
+    Json, Router,
+};
+use smokestack::{
+    api::{
+        ApiResponse, CreateApiTokenRequest, CreateApiTokenResponse, CreateServiceAccountRequest,
+        ListApiTokensResponse, ListServiceAccountsResponse,
+    },
+    model::{ApiToken, Claims, ServiceAccount},
+};
+
+pub fn root() -> Router<SharedState> {
+    Router::new()
+        .route("/", post(create_service_account))
+        .route("/", get(list_service_accounts))
+        .route("/:name", get(get_service_account))
+        .route("/:name/tokens", post(create_api_token))
+        .route("/:name/tokens", get(list_api_tokens))
+        .route("/:name/tokens/:id", delete(revoke_api_token))
+}
+
+async fn list_service_accounts(
+    claims: Claims,
+    State(state): State<SharedState>,
+) -> Result<Json<ApiResponse<ListServiceAccountsResponse>>> {
+    let state = state.read().unwrap();
+    state.ensure_admin(&claims.username)?;
+    Ok(Json(ApiResponse::Ok(ListServiceAccountsResponse {
+        accounts: state.service_accounts()?,
+    })))
+}
+
+async fn create_service_account(
+    claims: Claims,
+    State(state): State<SharedState>,
+    Json(req): Json<CreateServiceAccountRequest>,
+) -> Result<(StatusCode, Json<ApiResponse<ServiceAccount>>)> {
+    let mut state = state.write().unwrap();
+    state.ensure_admin(&claims.username)?;
+    let account = state.create_service_account(ServiceAccount {
+        name: req.name,
+        description: req.description,
+    })?;
+    state.commit()?;
+    Ok((StatusCode::CREATED, Json(ApiResponse::Ok(account))))
+}
+
+async fn get_service_account(
+    claims: Claims,
+    State(state): State<SharedState>,
+    Path(name): Path<String>,
+) -> Result<Json<ApiResponse<ServiceAccount>>> {
+    let state = state.read().unwrap();
+    state.ensure_admin(&claims.username)?;
+    Ok(Json(ApiResponse::Ok(state.service_account(&name)?)))
+}
+
+async fn list_api_tokens(
+    claims: Claims,
+    State(state): State<SharedState>,
+    Path(name): Path<String>,
+) -> Result<Json<ApiResponse<ListApiTokensResponse>>> {
+    let state = state.read().unwrap();
+    state.ensure_admin(&claims.username)?;
+    Ok(Json(ApiResponse::Ok(ListApiTokensResponse {
+        tokens: state
+            .api_tokens(&name)?
+            .into_iter()
+            .map(ApiToken::redacted)
+            .collect(),
+    })))
+}
+
+async fn create_api_token(
+    claims: Claims,
+    State(state): State<SharedState>,
+    Path(name): Path<String>,
+    Json(req): Json<CreateApiTokenRequest>,
+) -> Result<(StatusCode, Json<ApiResponse<CreateApiTokenResponse>>)> {
+    let mut state = state.write().unwrap();
+    state.ensure_admin(&claims.username)?;
+    let (info, token) =
+        state.create_api_token(&claims.username, &name, req.description, req.scopes)?;
+    state.commit()?;
+    Ok((
+        StatusCode::CREATED,
+        Json(ApiResponse::Ok(CreateApiTokenResponse {
+            info: info.redacted(),
+            token,
+        })),
+    ))
+}
+
+async fn revoke_api_token(
+    claims: Claims,
+    State(state): State<SharedState>,
+    Path((name, id)): Path<(String, u64)>,
+) -> Result<Json<ApiResponse<()>>> {
+    let mut state = state.write().unwrap();
+    state.ensure_admin(&claims.username)?;
+    state.revoke_api_token(&name, id)?;
+    state.commit()?;
+    Ok(Json(ApiResponse::Ok(())))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TestServer;
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn never_returns_secret_hashes_or_reuses_ids() {
+        let server = TestServer::with_admins(&["alice"]);
+        let alice = server.login("alice");
+        let (status, body) = server
+            .post("/service-accounts", &alice, json!({ "name": "ci" }))
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let request = json!({ "scopes": ["operations:read"] });
+        let mut tokens = Vec::new();
+        for id in 1..=2 {
+            let (status, body) = server
+                .post("/service-accounts/ci/tokens", &alice, request.clone())
+                .await;
+            assert_eq!(status, StatusCode::CREATED, "{body}");
+            assert_eq!(body["id"], id);
+            assert!(body.get("secret_hash").is_none(), "{body}");
+            tokens.push(body["token"].as_str().unwrap().to_owned());
+        }
+        let (status, body) = server.get("/operations", &tokens[1]).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        let (status, body) = server
+            .request(
+                Method::DELETE,
+                "/service-accounts/ci/tokens/2",
+                &alice,
+                None,
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let (status, _) = server.get("/operations", &tokens[1]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) = server
+            .post("/service-accounts/ci/tokens", &alice, request)
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        assert_eq!(body["id"], 3);
+        let (status, body) = server.get("/service-accounts/ci/tokens", &alice).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let tokens = body["tokens"].as_array().unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert!(tokens
+            .iter()
+            .all(|token| token.get("secret_hash").is_none()));
+    }
+}
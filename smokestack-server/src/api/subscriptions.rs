@@ -1,24 +1,34 @@
-use crate::{Result, SharedState};
+use crate::{EventReceiver, Result, SharedState};
 use axum::{
     extract::{
         ws::{self, WebSocket},
-        State, WebSocketUpgrade,
+        Query, State, WebSocketUpgrade,
     },
-    http::StatusCode,
-    response::IntoResponse,
-    routing::{get, post},
+    http::{HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+    routing::{delete, get, post},
     Json, Router,
 };
+use futures_util::{Stream, StreamExt};
 use smokestack::{
-    api::{ApiResponse, CreateSubscriptionRequest, ListSubscriptionResponse},
-    model::Claims,
+    api::{
+        ApiResponse, CreateSubscriptionRequest, DeleteSubscriptionQuery, ListSubscriptionResponse,
+        SubscriptionEntry, WatchQuery,
+    },
+    model::{Claims, OperationEvent, SubscriptionFilter, SubscriptionSet},
 };
+use std::collections::HashMap;
 
 pub fn root() -> Router<SharedState> {
     Router::new()
         .route("/", post(create_subscription))
         .route("/", get(list_subscriptions))
+        .route("/", delete(delete_subscription))
         .route("/watch", get(watch))
+        .route("/events", get(stream_events))
 }
 
 async fn create_subscription(
@@ -26,69 +36,450 @@ async fn create_subscription(
     State(state): State<SharedState>,
     Json(req): Json<CreateSubscriptionRequest>,
 ) -> Result<(StatusCode, Json<ApiResponse<()>>)> {
-    state
-        .write()
-        .unwrap()
-        .subscribe(&claims.username, req.operation, req.component, req.tag)?;
+    let mut state = state.write().unwrap();
+    state.subscribe(
+        &claims.username,
+        req.operation,
+        req.component,
+        req.tag,
+        req.filter,
+    )?;
+    state.commit()?;
     Ok((StatusCode::CREATED, Json(ApiResponse::Ok(()))))
 }
 
+async fn delete_subscription(
+    claims: Claims,
+    State(state): State<SharedState>,
+    Query(query): Query<DeleteSubscriptionQuery>,
+) -> Result<Json<ApiResponse<()>>> {
+    let mut state = state.write().unwrap();
+    state.unsubscribe(
+        &claims.username,
+        query.operation,
+        query.component.as_deref(),
+        query.tag.as_deref(),
+    )?;
+    state.commit()?;
+    Ok(Json(ApiResponse::Ok(())))
+}
+
 async fn list_subscriptions(
     claims: Claims,
     State(state): State<SharedState>,
 ) -> Result<Json<ApiResponse<ListSubscriptionResponse>>> {
     let state = state.read().unwrap();
     let user = state.user(&claims.username)?;
-    let subscriptions = &user.subscriptions;
-    let mut response = ListSubscriptionResponse {
-        operations: subscriptions.operations.iter().copied().collect(),
-        components: subscriptions.components.iter().cloned().collect(),
-        tags: subscriptions.tags.iter().cloned().collect(),
-    };
-    response.operations.sort_unstable();
-    response.components.sort_unstable();
-    response.tags.sort_unstable();
-    Ok(Json(ApiResponse::Ok(response)))
+    Ok(Json(ApiResponse::Ok(list_subscriptions_of(
+        &user.subscriptions,
+    ))))
+}
+
+/// Lists the subscriptions sorted by the subscribed entities.
+pub fn list_subscriptions_of(subscriptions: &SubscriptionSet) -> ListSubscriptionResponse {
+    fn entries<T: Clone + Ord>(
+        subscriptions: &HashMap<T, SubscriptionFilter>,
+    ) -> Vec<SubscriptionEntry<T>> {
+        let mut entries: Vec<_> = subscriptions
+            .iter()
+            .map(|(target, filter)| SubscriptionEntry {
+                target: target.clone(),
+                filter: filter.clone(),
+            })
+            .collect();
+        entries.sort_unstable_by(|a, b| a.target.cmp(&b.target));
+        entries
+    }
+
+    ListSubscriptionResponse {
+        operations: entries(&subscriptions.operations),
+        components: entries(&subscriptions.components),
+        tags: entries(&subscriptions.tags),
+    }
 }
 
 async fn watch(
     claims: Claims,
     State(state): State<SharedState>,
+    Query(query): Query<WatchQuery>,
     ws: WebSocketUpgrade,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(claims, state, socket))
+) -> Result<impl IntoResponse> {
+    let feed = EventFeed::new(state, claims.username, query.since)?;
+    Ok(ws.on_upgrade(move |socket| handle_socket(feed, socket)))
 }
 
-async fn handle_socket(claims: Claims, state: SharedState, mut socket: WebSocket) {
-    let (subscriptions, mut rx) = {
-        let state = state.read().unwrap();
-        let Ok(user) = state.user(&claims.username) else {
-            return;
-        };
-        let subscriptions = user.subscriptions.clone();
-        let rx = state.operation_tx.subscribe();
-        (subscriptions, rx)
-    };
+async fn handle_socket(mut feed: EventFeed, mut socket: WebSocket) {
     #[allow(clippy::redundant_pub_crate)]
     loop {
         tokio::select! {
-            Ok(operation) = rx.recv() => {
-                if !subscriptions.is_match(&operation) {
-                    continue;
-                }
-                let msg = match serde_json::to_string(&operation) {
-                    Ok(msg) => ws::Message::Text(msg),
-                    Err(e) => {
-                        tracing::warn!("failed to serialize operation: {}", e);
+            events = feed.next() => {
+                let Some(events) = events else {
+                    // Tell the client that it may reconnect later.
+                    let frame = ws::CloseFrame {
+                        code: ws::close_code::AWAY,
+                        reason: "event stream ended".into(),
+                    };
+                    let _ = socket.send(ws::Message::Close(Some(frame))).await;
+                    return;
+                };
+                for event in events {
+                    let msg = match serde_json::to_string(&event) {
+                        Ok(msg) => ws::Message::Text(msg),
+                        Err(e) => {
+                            tracing::warn!("failed to serialize operation event: {}", e);
+                            return;
+                        }
+                    };
+                    if socket.send(msg).await.is_err() {
                         return;
                     }
-                };
-                if socket.send(msg).await.is_err() {
-                    return;
                 }
             }
-            Some(_) = socket.recv() => (),
-            else => return,
+            msg = socket.recv() => match msg {
+                Some(Ok(ws::Message::Close(_)) | Err(_)) | None => return,
+                Some(Ok(_)) => (),
+            },
+        }
+    }
+}
+
+/// Streams events as Server-Sent Events.
+///
+/// The stream resumes after the event whose ID is given in the
+/// `Last-Event-ID` header or in the `since` query parameter.
+async fn stream_events(
+    claims: Claims,
+    State(state): State<SharedState>,
+    Query(query): Query<WatchQuery>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = std::result::Result<Event, axum::Error>>>> {
+    let since = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok()?.parse().ok())
+        .or(query.since);
+    let feed = EventFeed::new(state, claims.username, since)?;
+    let stream = futures_util::stream::unfold(feed, |mut feed| async move {
+        let events = feed.next().await?;
+        Some((futures_util::stream::iter(events), feed))
+    })
+    .flatten()
+    .map(|event| {
+        Event::default()
+            .id(event.id.to_string())
+            .event(event.kind.to_string())
+            .json_data(&event)
+    });
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// Events matching the current subscriptions of a user.
+///
+/// Subscriptions are looked up every time so that changes made while the
+/// feed is open take effect immediately.
+struct EventFeed {
+    state: SharedState,
+    username: String,
+    receiver: EventReceiver,
+
+    /// Events to be replayed before new events
+    backlog: Vec<OperationEvent>,
+}
+
+impl EventFeed {
+    /// Creates a feed of new events, preceded by the events after the event
+    /// with the ID `since` if given.
+    fn new(state: SharedState, username: String, since: Option<u64>) -> Result<Self> {
+        let (receiver, backlog) = since.map_or_else(
+            || Ok((EventReceiver::new(state.clone())?, Vec::new())),
+            |since| EventReceiver::resume(state.clone(), since),
+        )?;
+        Ok(Self {
+            state,
+            username,
+            receiver,
+            backlog,
+        })
+    }
+
+    /// Returns the next events matching the subscriptions, or `None` if the
+    /// feed has ended.
+    async fn next(&mut self) -> Option<Vec<OperationEvent>> {
+        loop {
+            let events = if self.backlog.is_empty() {
+                self.receiver.recv().await?
+            } else {
+                std::mem::take(&mut self.backlog)
+            };
+            let state = self.state.read().unwrap();
+            let subscriptions = &state.user(&self.username).ok()?.subscriptions;
+            let events: Vec<_> = events
+                .into_iter()
+                .filter(|event| subscriptions.is_match(event))
+                .collect();
+            if !events.is_empty() {
+                return Some(events);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TestServer;
+    use axum::{
+        body::Body,
+        http::{header, Method, Request, StatusCode},
+    };
+    use chrono::TimeDelta;
+    use futures_util::StreamExt;
+    use serde_json::{json, Value};
+
+    async fn subscribed_operations(server: &TestServer, token: &str) -> Value {
+        let (status, body) = server.get("/subscriptions", token).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        body["operations"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["target"].clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn subscribes_operators_to_their_operations_and_dependencies() {
+        let server = TestServer::new();
+        let alice = server.login("alice");
+        let bob = server.login("bob");
+        let carol = server.login("carol");
+        server.create_component(&alice, "foo", &["alice"]).await;
+        let first = server.create_operation(&alice, &["foo"]).await;
+        let second = server.create_operation(&alice, &["foo"]).await;
+
+        let (status, body) = server
+            .post(
+                "/operations",
+                &alice,
+                json!({
+                    "title": "Test",
+                    "purpose": "Testing",
+                    "url": "https://example.com/",
+                    "components": ["foo"],
+                    "depends_on": [first],
+                    "operators": ["alice", "bob"],
+                }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let id = body["id"].as_u64().unwrap();
+        let path = format!("/operations/{id}");
+        assert_eq!(
+            subscribed_operations(&server, &alice).await,
+            json!([first, second, id])
+        );
+        assert_eq!(
+            subscribed_operations(&server, &bob).await,
+            json!([first, id])
+        );
+
+        // Newly added operators and dependencies are subscribed to.
+        let (status, body) = server
+            .patch(
+                &path,
+                &alice,
+                json!({
+                    "url": null,
+                    "depends_on": [first, second],
+                    "operators": ["alice", "bob", "carol"],
+                }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(
+            subscribed_operations(&server, &bob).await,
+            json!([first, second, id])
+        );
+        assert_eq!(
+            subscribed_operations(&server, &carol).await,
+            json!([first, second, id])
+        );
+
+        // Users who unsubscribed are not subscribed again by unrelated edits.
+        for operation in [first, second, id] {
+            let (status, body) = server
+                .request(
+                    Method::DELETE,
+                    &format!("/subscriptions?operation={operation}"),
+                    &bob,
+                    None,
+                )
+                .await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+        }
+        let (status, body) = server
+            .patch(&path, &alice, json!({ "url": null, "title": "Renamed" }))
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(subscribed_operations(&server, &bob).await, json!([]));
+    }
+
+    #[tokio::test]
+    async fn unsubscribes() {
+        let server = TestServer::new();
+        let alice = server.login("alice");
+        server.create_component(&alice, "foo", &["alice"]).await;
+        let id = server.create_operation(&alice, &["foo"]).await;
+        let (status, body) = server
+            .post("/subscriptions", &alice, json!({ "component": "foo" }))
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+
+        for (query, expected) in [
+            ("component=foo", StatusCode::OK),
+            ("component=foo", StatusCode::NOT_FOUND),
+            (&format!("operation={id}"), StatusCode::OK),
+            ("tag=bar", StatusCode::NOT_FOUND),
+            (
+                &format!("operation={id}&component=foo"),
+                StatusCode::BAD_REQUEST,
+            ),
+        ] {
+            let (status, body) = server
+                .request(
+                    Method::DELETE,
+                    &format!("/subscriptions?{query}"),
+                    &alice,
+                    None,
+                )
+                .await;
+            assert_eq!(status, expected, "{query}: {body}");
+        }
+        let (status, body) = server.get("/subscriptions", &alice).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(
+            body,
+            json!({ "ok": true, "operations": [], "components": [], "tags": [] })
+        );
+    }
+
+    #[tokio::test]
+    async fn prunes_subscriptions_to_finished_operations() {
+        let server = TestServer::new();
+        let alice = server.login("alice");
+        server.create_component(&alice, "foo", &["alice"]).await;
+        let finished = server.create_operation(&alice, &["foo"]).await;
+        let running = server.create_operation(&alice, &["foo"]).await;
+        server.transition(&alice, finished, "in_progress").await;
+        server.transition(&alice, finished, "completed").await;
+        server.transition(&alice, running, "in_progress").await;
+        let (status, body) = server
+            .post("/subscriptions", &alice, json!({ "component": "foo" }))
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+
+        let prune = |retention| {
+            let mut state = server.state.write().unwrap();
+            state.prune_subscriptions(retention).unwrap();
+            state.commit().unwrap();
+        };
+        prune(TimeDelta::days(1));
+        assert_eq!(
+            subscribed_operations(&server, &alice).await,
+            json!([finished, running])
+        );
+        prune(TimeDelta::zero());
+        assert_eq!(
+            subscribed_operations(&server, &alice).await,
+            json!([running])
+        );
+        let (_, body) = server.get("/subscriptions", &alice).await;
+        assert_eq!(body["components"][0]["target"], "foo");
+    }
+
+    #[tokio::test]
+    async fn applies_subscriptions_made_while_watching() {
+        let server = TestServer::new();
+        let alice = server.login("alice");
+        let bob = server.login("bob");
+        server.create_component(&alice, "foo", &["alice"]).await;
+        let mut socket = server.watch(&bob).await;
+        server.wait_for_receivers(1).await;
+
+        let (status, body) = server
+            .post("/subscriptions", &bob, json!({ "component": "foo" }))
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let id = server.create_operation(&alice, &["foo"]).await;
+
+        let msg = socket.next().await.unwrap().unwrap();
+        let event: Value = serde_json::from_str(msg.to_text().unwrap()).unwrap();
+        assert_eq!(event["kind"], "created");
+        assert_eq!(event["operation"]["id"], id);
+    }
+
+    #[tokio::test]
+    async fn streams_server_sent_events_resuming_after_the_last_event_id() {
+        let server = TestServer::new();
+        let alice = server.login("alice");
+        server.create_component(&alice, "foo", &["alice"]).await;
+        server.create_operation(&alice, &["foo"]).await;
+        let first = server
+            .state
+            .read()
+            .unwrap()
+            .storage
+            .last_event_id()
+            .unwrap()
+            .unwrap();
+        let replayed = server.create_operation(&alice, &["foo"]).await;
+
+        let request = Request::builder()
+            .uri("/api/v1/subscriptions/events")
+            .header(header::AUTHORIZATION, format!("Bearer {alice}"))
+            .header("last-event-id", first.to_string())
+            .body(Body::empty())
+            .unwrap();
+        let response = server.respond(request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/event-stream"
+        );
+        let mut body = response.into_body().into_data_stream();
+        server.wait_for_receivers(1).await;
+        let live = server.create_operation(&alice, &["foo"]).await;
+
+        // Each event is framed with its ID, its kind and its JSON data.
+        let mut text = String::new();
+        let mut frames = Vec::new();
+        while frames.len() < 2 {
+            let chunk = body.next().await.unwrap().unwrap();
+            text.push_str(std::str::from_utf8(&chunk).unwrap());
+            while let Some(end) = text.find("\n\n") {
+                let frame: String = text.drain(..end + 2).collect();
+                // Keep-alive comments start with a colon.
+                if !frame.starts_with(':') {
+                    frames.push(frame);
+                }
+            }
+        }
+        for (frame, (id, operation)) in frames
+            .iter()
+            .zip([(first + 1, replayed), (first + 2, live)])
+        {
+            let fields: Vec<_> = frame
+                .lines()
+                .filter_map(|line| line.split_once(": "))
+                .collect();
+            let id = id.to_string();
+            assert_eq!(
+                fields[..2],
+                [("id", id.as_str()), ("event", "created")],
+                "{frame}"
+            );
+            assert_eq!(fields[2].0, "data");
+            let data: Value = serde_json::from_str(fields[2].1).unwrap();
+            assert_eq!(data["id"].to_string(), id);
+            assert_eq!(data["operation"]["id"], operation);
         }
     }
 }
@@ -2,12 +2,11 @@ use crate::{Result, SharedState};
 use axum::{
     extract::{Path, State},
     http::StatusCode,
-    response::IntoResponse,
-    routing::{get, post},
+    routing::{get, patch, post},
     Json, Router,
 };
 use smokestack::{
-    api::{ApiResponse, CreateTagRequest, ListTagsResponse},
+    api::{ApiResponse, CreateTagRequest, ListTagsResponse, UpdateTagRequest},
     model::{Claims, Tag},
 };
 
@@ -16,13 +15,17 @@ pub fn root() -> Router<SharedState> {
         .route("/", post(create_tag))
         .route("/", get(list_tags))
         .route("/:name", get(get_tag))
+        .route("/:name", patch(update_tag))
 }
 
-async fn list_tags(_claims: Claims, State(state): State<SharedState>) -> impl IntoResponse {
+async fn list_tags(
+    _claims: Claims,
+    State(state): State<SharedState>,
+) -> Result<Json<ApiResponse<ListTagsResponse>>> {
     let state = state.read().unwrap();
-    Json(ApiResponse::Ok(ListTagsResponse {
-        tags: state.tags().cloned().collect::<Vec<_>>(),
-    }))
+    Ok(Json(ApiResponse::Ok(ListTagsResponse {
+        tags: state.tags()?,
+    })))
 }
 
 async fn create_tag(
@@ -34,8 +37,10 @@ async fn create_tag(
     let tag = Tag {
         name: req.name,
         description: req.description,
+        approval: req.approval,
     };
     let tag = state.create_tag(tag)?;
+    state.commit()?;
     Ok((StatusCode::CREATED, Json(ApiResponse::Ok(tag))))
 }
 
@@ -45,5 +50,65 @@ async fn get_tag(
     Path(name): Path<String>,
 ) -> Result<Json<ApiResponse<Tag>>> {
     let state = state.read().unwrap();
-    Ok(Json(ApiResponse::Ok(state.tag(&name)?.clone())))
+    Ok(Json(ApiResponse::Ok(state.tag(&name)?)))
+}
+
+async fn update_tag(
+    claims: Claims,
+    State(state): State<SharedState>,
+    Path(name): Path<String>,
+    Json(req): Json<UpdateTagRequest>,
+) -> Result<Json<ApiResponse<Tag>>> {
+    let mut state = state.write().unwrap();
+    let mut tag = state.tag(&name)?;
+    if let Some(description) = req.description {
+        tag.description = description;
+    }
+    if let Some(requires_approval_by) = req.requires_approval_by {
+        tag.approval.requires_approval_by = requires_approval_by;
+    }
+    if let Some(required_approvals) = req.required_approvals {
+        tag.approval.required_approvals = required_approvals;
+    }
+    let tag = state.update_tag(&claims.username, tag)?;
+    state.commit()?;
+    Ok(Json(ApiResponse::Ok(tag)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TestServer;
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn only_admins_change_approvals() {
+        let server = TestServer::with_admins(&["carol"]);
+        let alice = server.login("alice");
+        let carol = server.login("carol");
+        let (status, body) = server
+            .post(
+                "/tags",
+                &alice,
+                json!({ "name": "db", "description": "DB" }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+
+        let (status, body) = server
+            .patch("/tags/db", &alice, json!({ "required_approvals": 1 }))
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+        let (status, body) = server
+            .patch("/tags/db", &alice, json!({ "description": "Databases" }))
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["required_approvals"], 0);
+
+        let (status, body) = server
+            .patch("/tags/db", &carol, json!({ "required_approvals": 1 }))
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["required_approvals"], 1);
+    }
 }
@@ -0,0 +1,39 @@
+use crate::{Result, SharedState};
+use axum::{
+    extract::State,
+    routing::{get, patch},
+    Json, Router,
+};
+use smokestack::{
+    api::{ApiResponse, UpdateUserRequest},
+    model::{Claims, User},
+};
+
+pub fn root() -> Router<SharedState> {
+    Router::new()
+        .route("/me", get(get_me))
+        .route("/me", patch(update_me))
+}
+
+async fn get_me(
+    claims: Claims,
+    State(state): State<SharedState>,
+) -> Result<Json<ApiResponse<User>>> {
+    let state = state.read().unwrap();
+    Ok(Json(ApiResponse::Ok(state.user(&claims.username)?)))
+}
+
+async fn update_me(
+    claims: Claims,
+    State(state): State<SharedState>,
+    Json(req): Json<UpdateUserRequest>,
+) -> Result<Json<ApiResponse<User>>> {
+    let mut state = state.write().unwrap();
+    let mut user = state.user(&claims.username)?;
+    if let Some(email) = req.email {
+        user.email = email;
+    }
+    let user = state.update_user(user)?;
+    state.commit()?;
+    Ok(Json(ApiResponse::Ok(user)))
+}
@@ -0,0 +1,162 @@
+use crate::{webhook, Result, SharedState};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{delete, get, post},
+    Json, Router,
+};
+use smokestack::{
+    api::{ApiResponse, CreateWebhookRequest, ListFailedDeliveriesResponse, ListWebhooksResponse},
+    model::{Claims, Webhook},
+};
+
+pub fn root() -> Router<SharedState> {
+    Router::new()
+        .route("/", post(create_webhook))
+        .route("/", get(list_webhooks))
+        .route("/:id", get(get_webhook))
+        .route("/:id", delete(delete_webhook))
+        .route("/:id/failures", get(list_failed_deliveries))
+        .route(
+            "/:id/failures/:failure_id/redeliver",
+            post(redeliver_failed_delivery),
+        )
+}
+
+async fn list_webhooks(
+    claims: Claims,
+    State(state): State<SharedState>,
+) -> Result<Json<ApiResponse<ListWebhooksResponse>>> {
+    let state = state.read().unwrap();
+    state.ensure_admin(&claims.username)?;
+    Ok(Json(ApiResponse::Ok(ListWebhooksResponse {
+        webhooks: state
+            .webhooks()?
+            .into_iter()
+            .map(Webhook::redacted)
+            .collect(),
+    })))
+}
+
+async fn create_webhook(
+    claims: Claims,
+    State(state): State<SharedState>,
+    Json(req): Json<CreateWebhookRequest>,
+) -> Result<(StatusCode, Json<ApiResponse<Webhook>>)> {
+    let mut state = state.write().unwrap();
+    state.ensure_admin(&claims.username)?;
+    let webhook = Webhook {
+        id: 0, // assigned by create_webhook
+        url: req.url,
+        secret: req.secret,
+        components: req.components,
+        tags: req.tags,
+        statuses: req.statuses,
+    };
+    let webhook = state.create_webhook(webhook)?;
+    state.commit()?;
+    Ok((StatusCode::CREATED, Json(ApiResponse::Ok(webhook))))
+}
+
+async fn get_webhook(
+    claims: Claims,
+    State(state): State<SharedState>,
+    Path(id): Path<u64>,
+) -> Result<Json<ApiResponse<Webhook>>> {
+    let state = state.read().unwrap();
+    state.ensure_admin(&claims.username)?;
+    Ok(Json(ApiResponse::Ok(state.webhook(id)?.redacted())))
+}
+
+async fn delete_webhook(
+    claims: Claims,
+    State(state): State<SharedState>,
+    Path(id): Path<u64>,
+) -> Result<Json<ApiResponse<()>>> {
+    let mut state = state.write().unwrap();
+    state.ensure_admin(&claims.username)?;
+    state.delete_webhook(id)?;
+    state.commit()?;
+    Ok(Json(ApiResponse::Ok(())))
+}
+
+async fn list_failed_deliveries(
+    claims: Claims,
+    State(state): State<SharedState>,
+    Path(id): Path<u64>,
+) -> Result<Json<ApiResponse<ListFailedDeliveriesResponse>>> {
+    let state = state.read().unwrap();
+    state.ensure_admin(&claims.username)?;
+    state.webhook(id)?;
+    Ok(Json(ApiResponse::Ok(ListFailedDeliveriesResponse {
+        deliveries: state.failed_deliveries(id)?,
+    })))
+}
+
+/// Makes a single attempt to deliver the event again.
+///
+/// On success, the failed delivery is removed. On failure, it is kept with
+/// the new error.
+async fn redeliver_failed_delivery(
+    claims: Claims,
+    State(state): State<SharedState>,
+    Path((id, failure_id)): Path<(u64, u64)>,
+) -> Result<Json<ApiResponse<()>>> {
+    let (webhook, delivery) = {
+        let state = state.read().unwrap();
+        state.ensure_admin(&claims.username)?;
+        let webhook = state.webhook(id)?;
+        let delivery = state.failed_delivery(id, failure_id)?;
+        (webhook, delivery)
+    };
+    let result = webhook::deliver(&webhook::client(), &webhook, &delivery.event).await;
+    let mut state = state.write().unwrap();
+    state.settle_redelivery(failure_id, result.as_ref().err().map(ToString::to_string))?;
+    state.commit()?;
+    result?;
+    Ok(Json(ApiResponse::Ok(())))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TestServer;
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn returns_the_secret_only_on_creation() {
+        let server = TestServer::with_admins(&["alice"]);
+        let alice = server.login("alice");
+        let webhook = json!({ "url": "https://example.com/hook", "secret": "s3cret" });
+        let (status, body) = server.post("/webhooks", &alice, webhook).await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        assert_eq!(body["secret"], "s3cret");
+
+        let (status, body) = server.get("/webhooks/1", &alice).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert!(body.get("secret").is_none(), "{body}");
+        let (status, body) = server.get("/webhooks", &alice).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert!(body["webhooks"][0].get("secret").is_none(), "{body}");
+    }
+
+    #[tokio::test]
+    async fn does_not_reuse_ids_of_deleted_webhooks() {
+        let server = TestServer::with_admins(&["alice"]);
+        let alice = server.login("alice");
+        let webhook = json!({ "url": "https://example.com/hook", "secret": "s3cret" });
+        for id in 1..=2 {
+            let (status, body) = server.post("/webhooks", &alice, webhook.clone()).await;
+            assert_eq!(status, StatusCode::CREATED, "{body}");
+            assert_eq!(body["id"], id);
+        }
+        let (status, body) = server
+            .request(Method::DELETE, "/webhooks/2", &alice, None)
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        let (status, body) = server.post("/webhooks", &alice, webhook).await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        assert_eq!(body["id"], 3);
+    }
+}
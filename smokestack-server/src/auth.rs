@@ -0,0 +1,551 @@
+use crate::{Error, Result};
+use axum::http::{HeaderMap, HeaderName, Method};
+use chrono::{TimeDelta, Utc};
+use hmac::{Hmac, Mac};
+use jsonwebtoken::{
+    errors::ErrorKind, jwk::JwkSet, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use rand::RngCore;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use smokestack::model::{Claims, RefreshToken, Scope};
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::sync::RwLock;
+
+/// Checks the credentials presented by a user logging in.
+pub trait CredentialCheck: Send + Sync {
+    /// Returns whether the credentials prove that the request comes from the
+    /// user.
+    fn check(
+        &self,
+        username: &str,
+        password: Option<&str>,
+        headers: &HeaderMap,
+    ) -> anyhow::Result<bool>;
+}
+
+/// Checks passwords against a file of `username:hash` lines, as written by
+/// `htpasswd -B`.
+///
+/// Only bcrypt hashes are supported; users with other hashes cannot log in.
+/// The file is read on every login so that changes take effect without
+/// restarting the server.
+pub struct Htpasswd(pub PathBuf);
+
+impl CredentialCheck for Htpasswd {
+    fn check(
+        &self,
+        username: &str,
+        password: Option<&str>,
+        _headers: &HeaderMap,
+    ) -> anyhow::Result<bool> {
+        let Some(password) = password else {
+            return Ok(false);
+        };
+        let content = std::fs::read_to_string(&self.0)?;
+        let Some(hash) = content.lines().find_map(|line| {
+            let (name, hash) = line.split_once(':')?;
+            (name == username).then_some(hash.trim())
+        }) else {
+            return Ok(false);
+        };
+        match bcrypt::verify(password, hash) {
+            Ok(valid) => Ok(valid),
+            Err(e) => {
+                tracing::warn!(
+                    "unsupported password hash of {} in {}: {}",
+                    username,
+                    self.0.display(),
+                    e
+                );
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// Trusts a header set by a reverse proxy that has already authenticated the
+/// user (e.g. `X-Forwarded-User`).
+///
+/// The proxy must strip the header from incoming requests, or anyone can
+/// impersonate any user.
+pub struct TrustedHeader(pub HeaderName);
+
+impl CredentialCheck for TrustedHeader {
+    fn check(
+        &self,
+        username: &str,
+        _password: Option<&str>,
+        headers: &HeaderMap,
+    ) -> anyhow::Result<bool> {
+        Ok(headers
+            .get(&self.0)
+            .is_some_and(|value| value.as_bytes() == username.as_bytes()))
+    }
+}
+
+/// Accepts any user without credentials. Only for development.
+pub struct AllowAny;
+
+impl CredentialCheck for AllowAny {
+    fn check(&self, _: &str, _: Option<&str>, _: &HeaderMap) -> anyhow::Result<bool> {
+        Ok(true)
+    }
+}
+
+/// Authenticates users and issues tokens to them.
+pub struct Auth {
+    /// Credentials are accepted if any of the checks accepts them.
+    checks: Vec<Box<dyn CredentialCheck>>,
+
+    /// Key signing access tokens. Refresh tokens are random and stored, so
+    /// that they can be revoked.
+    access_key: Vec<u8>,
+
+    access_token_ttl: Duration,
+    refresh_token_ttl: Duration,
+
+    /// Verifies tokens issued by an OpenID Connect provider, if configured
+    pub oidc: Option<Oidc>,
+}
+
+impl Auth {
+    /// Creates an authenticator signing access tokens with a key derived from
+    /// the key in `key_file`.
+    ///
+    /// If `key_file` is not specified, a random key is used, so tokens are
+    /// invalidated when the server restarts.
+    pub fn new(
+        checks: Vec<Box<dyn CredentialCheck>>,
+        key_file: Option<&Path>,
+        access_token_ttl: Duration,
+        refresh_token_ttl: Duration,
+        oidc: Option<Oidc>,
+    ) -> anyhow::Result<Self> {
+        let key = if let Some(path) = key_file {
+            let key = std::fs::read(path)?;
+            let key = key.trim_ascii();
+            anyhow::ensure!(!key.is_empty(), "{} is empty", path.display());
+            if key.len() < 32 {
+                tracing::warn!("{} is shorter than 32 bytes", path.display());
+            }
+            key.to_vec()
+        } else {
+            tracing::warn!(
+                "no signing key file is specified; tokens will be invalidated on restart"
+            );
+            let mut key = vec![0; 32];
+            rand::thread_rng().fill_bytes(&mut key);
+            key
+        };
+        let derive = |purpose: &[u8]| {
+            let mut mac = Hmac::<Sha256>::new_from_slice(&key).expect("any key length is valid");
+            mac.update(purpose);
+            mac.finalize().into_bytes().to_vec()
+        };
+        Ok(Self {
+            checks,
+            access_key: derive(b"access"),
+            access_token_ttl,
+            refresh_token_ttl,
+            oidc,
+        })
+    }
+
+    /// Checks the credentials of a user logging in.
+    pub fn authenticate(
+        &self,
+        username: &str,
+        password: Option<&str>,
+        headers: &HeaderMap,
+    ) -> Result<()> {
+        for check in &self.checks {
+            match check.check(username, password, headers) {
+                Ok(true) => return Ok(()),
+                Ok(false) => {}
+                Err(e) => {
+                    tracing::error!("failed to check credentials: {:#}", e);
+                    return Err(Error::Internal);
+                }
+            }
+        }
+        Err(Error::InvalidCredentials)
+    }
+
+    /// Issues an access token to the user.
+    pub fn issue_access_token(&self, username: &str) -> Result<String> {
+        let claims = Claims {
+            exp: SystemTime::now()
+                .checked_add(self.access_token_ttl)
+                .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .ok_or(Error::Internal)?
+                .as_secs(),
+            username: username.to_owned(),
+            scopes: None,
+        };
+        jsonwebtoken::encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(&self.access_key),
+        )
+        .map_err(|_| Error::Internal)
+    }
+
+    /// Verifies an access token.
+    pub fn verify_access_token(&self, token: &str) -> Result<Claims> {
+        jsonwebtoken::decode(
+            token,
+            &DecodingKey::from_secret(&self.access_key),
+            &Validation::default(),
+        )
+        .map(|token_data| token_data.claims)
+        .map_err(token_error)
+    }
+
+    /// Generates a refresh token for the user, and returns it along with the
+    /// record to be stored.
+    ///
+    /// The token continues `session` if specified, or starts a new session.
+    pub fn new_refresh_token(
+        &self,
+        username: &str,
+        session: Option<String>,
+    ) -> Result<(String, RefreshToken)> {
+        let token = random_hex(32);
+        let ttl = TimeDelta::from_std(self.refresh_token_ttl).map_err(|_| Error::Internal)?;
+        let record = RefreshToken {
+            hash: hash_refresh_token(&token),
+            username: username.to_owned(),
+            session: session.unwrap_or_else(|| random_hex(16)),
+            expires_at: Utc::now() + ttl,
+            used: false,
+        };
+        Ok((token, record))
+    }
+}
+
+/// Returns the hash under which the refresh token is stored.
+pub fn hash_refresh_token(token: &str) -> String {
+    hash_secret(token)
+}
+
+fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+fn token_error(e: jsonwebtoken::errors::Error) -> Error {
+    match e.kind() {
+        ErrorKind::ExpiredSignature => Error::ExpiredToken,
+        _ => Error::InvalidToken,
+    }
+}
+
+/// Minimum time between reloads of the JWKS, which happen when a token is
+/// signed with an unknown key
+const JWKS_RELOAD_INTERVAL: Duration = Duration::from_mins(1);
+
+/// Where the JSON Web Key Set of an OpenID Connect provider is loaded from.
+#[derive(Debug, Clone)]
+pub enum JwksSource {
+    Url(reqwest::Url),
+    File(PathBuf),
+}
+
+impl FromStr for JwksSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.starts_with("http://") || s.starts_with("https://") {
+            s.parse()
+                .map(Self::Url)
+                .map_err(|e| format!("invalid URL: {e}"))
+        } else {
+            Ok(Self::File(s.into()))
+        }
+    }
+}
+
+impl JwksSource {
+    async fn load(&self) -> anyhow::Result<JwkSet> {
+        match self {
+            Self::Url(url) => Ok(reqwest::Client::new()
+                .get(url.clone())
+                .timeout(Duration::from_secs(10))
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?),
+            Self::File(path) => Ok(serde_json::from_slice(&tokio::fs::read(path).await?)?),
+        }
+    }
+}
+
+/// A user authenticated by an OpenID Connect provider.
+pub struct OidcIdentity {
+    pub username: String,
+
+    /// Groups the user is a member of, if the provider is configured to tell
+    pub groups: Option<Vec<String>>,
+
+    pub exp: u64,
+}
+
+/// Verifies ID tokens issued by an OpenID Connect provider with RS256 or
+/// ES256.
+pub struct Oidc {
+    issuer: String,
+    audience: String,
+
+    /// Claim holding the username
+    username_claim: String,
+
+    /// Claim holding the names of the groups the user is a member of
+    groups_claim: Option<String>,
+
+    jwks_source: JwksSource,
+
+    /// The keys, and when they were loaded
+    jwks: RwLock<(JwkSet, Instant)>,
+}
+
+impl Oidc {
+    /// Creates a verifier, loading the JWKS of the provider.
+    pub async fn new(
+        issuer: String,
+        audience: String,
+        username_claim: String,
+        groups_claim: Option<String>,
+        jwks_source: JwksSource,
+    ) -> anyhow::Result<Self> {
+        let jwks = jwks_source.load().await?;
+        anyhow::ensure!(!jwks.keys.is_empty(), "JWKS contains no keys");
+        Ok(Self {
+            issuer,
+            audience,
+            username_claim,
+            groups_claim,
+            jwks_source,
+            jwks: RwLock::new((jwks, Instant::now())),
+        })
+    }
+
+    /// Returns whether the token is signed with an algorithm used by the
+    /// provider, rather than by this server.
+    pub fn accepts(token: &str) -> bool {
+        jsonwebtoken::decode_header(token)
+            .is_ok_and(|header| matches!(header.alg, Algorithm::RS256 | Algorithm::ES256))
+    }
+
+    pub async fn verify(&self, token: &str) -> Result<OidcIdentity> {
+        let header = jsonwebtoken::decode_header(token).map_err(|_| Error::InvalidToken)?;
+        if !matches!(header.alg, Algorithm::RS256 | Algorithm::ES256) {
+            return Err(Error::InvalidToken);
+        }
+        let key = self.key(header.kid.as_deref()).await?;
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+        let claims: Map<String, Value> = jsonwebtoken::decode(token, &key, &validation)
+            .map_err(token_error)?
+            .claims;
+
+        let username = claims
+            .get(&self.username_claim)
+            .and_then(Value::as_str)
+            .filter(|username| !username.is_empty())
+            .ok_or(Error::InvalidToken)?
+            .to_owned();
+        let groups = self
+            .groups_claim
+            .as_ref()
+            .map(|claim| match claims.get(claim) {
+                Some(Value::Array(groups)) => groups
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_owned)
+                    .collect(),
+                Some(Value::String(group)) => vec![group.clone()],
+                _ => Vec::new(),
+            });
+        let exp = claims
+            .get("exp")
+            .and_then(Value::as_u64)
+            .ok_or(Error::InvalidToken)?;
+        Ok(OidcIdentity {
+            username,
+            groups,
+            exp,
+        })
+    }
+
+    /// Returns the key with the ID, reloading the JWKS in case the provider
+    /// has rotated its keys.
+    ///
+    /// Tokens without a key ID can only be verified if the JWKS has exactly
+    /// one key.
+    async fn key(&self, kid: Option<&str>) -> Result<DecodingKey> {
+        if let Some(key) = find_key(&self.jwks.read().await.0, kid) {
+            return Ok(key);
+        }
+        let mut jwks = self.jwks.write().await;
+        if jwks.1.elapsed() >= JWKS_RELOAD_INTERVAL {
+            match self.jwks_source.load().await {
+                Ok(keys) => *jwks = (keys, Instant::now()),
+                Err(e) => {
+                    tracing::warn!("failed to reload JWKS: {:#}", e);
+                    jwks.1 = Instant::now();
+                }
+            }
+        }
+        find_key(&jwks.0, kid).ok_or(Error::InvalidToken)
+    }
+}
+
+fn find_key(jwks: &JwkSet, kid: Option<&str>) -> Option<DecodingKey> {
+    let jwk = match kid {
+        Some(kid) => jwks.find(kid)?,
+        None if jwks.keys.len() == 1 => &jwks.keys[0],
+        None => return None,
+    };
+    DecodingKey::from_jwk(jwk).ok()
+}
+
+/// Prefix of API tokens, which tells them apart from access tokens
+pub const API_TOKEN_PREFIX: &str = "sst_";
+
+/// Generates a new API token with the ID.
+///
+/// Returns the token and the hash of its secret, which is stored instead of
+/// the token.
+pub fn generate_api_token(id: u64) -> (String, String) {
+    let secret = random_hex(32);
+    let hash = hash_secret(&secret);
+    (format!("{API_TOKEN_PREFIX}{id}_{secret}"), hash)
+}
+
+/// Splits an API token into its ID and the hash of its secret.
+pub fn parse_api_token(token: &str) -> Option<(u64, String)> {
+    let (id, secret) = token.strip_prefix(API_TOKEN_PREFIX)?.split_once('_')?;
+    Some((id.parse().ok()?, hash_secret(secret)))
+}
+
+fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret))
+}
+
+/// Returns the scope that an API token needs to make a request to the route,
+/// or `None` if the route cannot be used with API tokens.
+pub fn required_scope(method: &Method, route: &str) -> Option<Scope> {
+    let resource = route.strip_prefix("/api/v1/")?.split('/').next()?;
+    let read = method == Method::GET;
+    match resource {
+        "operations" | "history" | "components" | "tags" | "subscriptions" if read => {
+            Some(Scope::OperationsRead)
+        }
+        "operations" => Some(Scope::OperationsWrite),
+        "subscriptions" => Some(Scope::SubscriptionsWrite),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(name: &str, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_bytes(name.as_bytes()).unwrap(),
+            value.parse().unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn htpasswd_accepts_only_the_password_of_the_user() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("htpasswd");
+        let hash = bcrypt::hash("s3cret", 4).unwrap();
+        std::fs::write(&path, format!("alice:{hash}\nbob:{hash}\n")).unwrap();
+        let htpasswd = Htpasswd(path);
+        let check = |username, password| {
+            htpasswd
+                .check(username, password, &HeaderMap::new())
+                .unwrap()
+        };
+
+        assert!(check("alice", Some("s3cret")));
+        assert!(check("bob", Some("s3cret")));
+        assert!(!check("alice", Some("wrong")));
+        assert!(!check("alice", None));
+        assert!(!check("carol", Some("s3cret")));
+    }
+
+    #[test]
+    fn htpasswd_rejects_unsupported_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("htpasswd");
+        // Written by `htpasswd -m` (MD5) and `htpasswd -s` (SHA-1)
+        std::fs::write(
+            &path,
+            "alice:$apr1$r31.....$HqJZimcKQFAMYayBlzkrA/\n\
+             bob:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\n",
+        )
+        .unwrap();
+        let htpasswd = Htpasswd(path);
+
+        for username in ["alice", "bob"] {
+            let valid = htpasswd
+                .check(username, Some("password"), &HeaderMap::new())
+                .unwrap();
+            assert!(!valid, "{username}");
+        }
+    }
+
+    #[test]
+    fn trusted_header_must_name_the_user() {
+        let check = TrustedHeader(HeaderName::from_static("x-forwarded-user"));
+
+        assert!(check
+            .check("alice", None, &headers("x-forwarded-user", "alice"))
+            .unwrap());
+        assert!(!check
+            .check("alice", None, &headers("x-forwarded-user", "bob"))
+            .unwrap());
+        assert!(!check
+            .check("alice", None, &headers("x-remote-user", "alice"))
+            .unwrap());
+        assert!(!check.check("alice", None, &HeaderMap::new()).unwrap());
+    }
+
+    #[test]
+    fn authenticates_if_any_check_accepts() {
+        let auth = Auth::new(
+            vec![
+                Box::new(TrustedHeader(HeaderName::from_static("x-forwarded-user"))),
+                Box::new(TrustedHeader(HeaderName::from_static("x-remote-user"))),
+            ],
+            None,
+            Duration::from_mins(1),
+            Duration::from_mins(1),
+            None,
+        )
+        .unwrap();
+
+        assert!(auth
+            .authenticate("alice", None, &headers("x-remote-user", "alice"))
+            .is_ok());
+        assert!(matches!(
+            auth.authenticate("alice", None, &HeaderMap::new()),
+            Err(Error::InvalidCredentials)
+        ));
+    }
+}
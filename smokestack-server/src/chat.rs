@@ -0,0 +1,160 @@
+use crate::{webhook, EventReceiver, SharedState};
+use smokestack::model::{Channel, ChatFlavor, EventKind, Operation, OperationEvent};
+
+/// Posts operation events to the subscribed chat channels.
+pub async fn run(state: SharedState) {
+    let client = webhook::client();
+    let mut receiver = match EventReceiver::new(state.clone()) {
+        Ok(receiver) => receiver,
+        Err(e) => {
+            tracing::error!("failed to start chat notifications: {}", e);
+            return;
+        }
+    };
+    while let Some(events) = receiver.recv().await {
+        for event in events {
+            let channels = {
+                let state = state.read().unwrap();
+                match state.subscribed_channels(&event.operation) {
+                    // Channels set up before their host was disallowed are
+                    // left alone.
+                    Ok(channels) => channels
+                        .into_iter()
+                        .filter(|channel| state.is_allowed_chat_webhook(&channel.webhook_url))
+                        .collect::<Vec<_>>(),
+                    Err(e) => {
+                        tracing::error!("failed to look up subscribed channels: {}", e);
+                        continue;
+                    }
+                }
+            };
+            for channel in channels
+                .into_iter()
+                .filter(|channel| channel.subscriptions.is_match(&event))
+            {
+                let client = client.clone();
+                let text = format_event(channel.flavor, &event);
+                tokio::spawn(async move {
+                    if let Err(e) = post(&client, &channel, text).await {
+                        tracing::warn!(
+                            "failed to post event {} to channel {}: {}",
+                            event.id,
+                            channel.name,
+                            e
+                        );
+                    }
+                });
+            }
+        }
+    }
+}
+
+async fn post(client: &reqwest::Client, channel: &Channel, text: String) -> reqwest::Result<()> {
+    client
+        .post(channel.webhook_url.to_string())
+        .json(&serde_json::json!({ "text": text }))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+fn format_event(flavor: ChatFlavor, event: &OperationEvent) -> String {
+    let operation = &event.operation;
+    let what = match event.kind {
+        EventKind::Created => "created".to_owned(),
+        EventKind::Edited => "edited".to_owned(),
+        EventKind::Transitioned => format!("moved to `{}`", operation.status),
+        EventKind::Approved => "approved".to_owned(),
+        EventKind::ApprovalRevoked => "approval revoked".to_owned(),
+        EventKind::ApprovalsInvalidated => "approvals invalidated".to_owned(),
+    };
+    format!(
+        "{} {} by {}",
+        format_operation_link(flavor, operation),
+        what,
+        event.actor
+    )
+}
+
+/// Formats the operation as a single line of a list.
+pub fn format_operation(flavor: ChatFlavor, operation: &Operation) -> String {
+    format!(
+        "{} `{}`",
+        format_operation_link(flavor, operation),
+        operation.status
+    )
+}
+
+fn format_operation_link(flavor: ChatFlavor, operation: &Operation) -> String {
+    let text = format!("#{} {}", operation.id, operation.title);
+    let url = operation.url.to_string();
+    match flavor {
+        ChatFlavor::Slack => format!("*<{}|{}>*", escape_slack(&url), escape_slack(&text)),
+        ChatFlavor::Mattermost => format!(
+            "**[{}]({})**",
+            text.replace('[', "\\[").replace(']', "\\]"),
+            url.replace(')', "%29")
+        ),
+    }
+}
+
+/// Escapes control characters of Slack's mrkdwn.
+fn escape_slack(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TestServer;
+    use axum::{http::StatusCode, routing::post, Json, Router};
+    use serde_json::Value;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn posts_events_to_subscribed_channels() {
+        let (tx, mut posted) = mpsc::unbounded_channel();
+        let handler = move |Json(body): Json<Value>| async move {
+            tx.send(body).unwrap();
+            StatusCode::OK
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let app = Router::new().route("/hook", post(handler));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let server = TestServer::with_chat(&["127.0.0.1"]);
+        let alice = server.login("alice");
+        server.create_component(&alice, "foo", &["alice"]).await;
+        server.create_component(&alice, "bar", &["alice"]).await;
+        for command in [
+            format!("setup {url}"),
+            "subscribe --component foo".to_owned(),
+        ] {
+            let (status, body) = server.chat_command("s3cret", &command).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+        }
+
+        tokio::spawn(super::run(server.state.clone()));
+        server.wait_for_receivers(1).await;
+        server.create_operation(&alice, &["bar"]).await;
+        let id = server.create_operation(&alice, &["foo"]).await;
+
+        let body = tokio::time::timeout(Duration::from_secs(10), posted.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            body["text"],
+            format!("*<https://example.com/|#{id} Test>* created by alice")
+        );
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), posted.recv())
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,307 @@
+use crate::{EventReceiver, SharedState};
+use anyhow::Context as _;
+use lettre::{
+    message::{Mailbox, MultiPart},
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use smokestack::model::{OperationEvent, User};
+use std::{collections::HashMap, path::Path, time::Duration};
+use tokio::time::Instant;
+
+const SUBJECT_TEMPLATE: &str = "subject.txt";
+const TEXT_TEMPLATE: &str = "body.txt";
+const HTML_TEMPLATE: &str = "body.html";
+
+const DEFAULT_TEMPLATES: [(&str, &str); 3] = [
+    (SUBJECT_TEMPLATE, include_str!("../templates/subject.txt")),
+    (TEXT_TEMPLATE, include_str!("../templates/body.txt")),
+    (HTML_TEMPLATE, include_str!("../templates/body.html")),
+];
+
+/// Sends email notifications to subscribers.
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    templates: tera::Tera,
+
+    /// How long to wait for more events before sending an email.
+    /// Events that occur in the meantime are sent together in a single email.
+    batch_delay: Duration,
+}
+
+impl Mailer {
+    /// Creates a mailer that sends emails through the SMTP server at `url`
+    /// (e.g. `smtp://localhost:25`).
+    ///
+    /// Templates in `template_dir` override the default ones.
+    pub fn new(
+        url: &str,
+        from: Mailbox,
+        template_dir: Option<&Path>,
+        batch_delay: Duration,
+    ) -> anyhow::Result<Self> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::from_url(url)?.build();
+        let mut templates = tera::Tera::default();
+        templates.add_raw_templates(DEFAULT_TEMPLATES)?;
+        if let Some(dir) = template_dir {
+            for (name, _) in DEFAULT_TEMPLATES {
+                let path = dir.join(name);
+                if path.exists() {
+                    templates
+                        .add_template_file(&path, Some(name))
+                        .with_context(|| format!("failed to load {}", path.display()))?;
+                }
+            }
+        }
+        Ok(Self {
+            transport,
+            from,
+            templates,
+            batch_delay,
+        })
+    }
+
+    /// Emails operation events to the users subscribed to them.
+    pub async fn run(self, state: SharedState) {
+        let mut receiver = match EventReceiver::new(state.clone()) {
+            Ok(receiver) => receiver,
+            Err(e) => {
+                tracing::error!("failed to start email notifications: {}", e);
+                return;
+            }
+        };
+        let mut batches: HashMap<String, Batch> = HashMap::new();
+        loop {
+            let deadline = batches.values().map(|batch| batch.deadline).min();
+            tokio::select! {
+                events = receiver.recv() => {
+                    let Some(events) = events else {
+                        break;
+                    };
+                    for event in events {
+                        let users = match state.read().unwrap().subscribed_users(&event.operation) {
+                            Ok(users) => users,
+                            Err(e) => {
+                                tracing::error!("failed to look up subscribers: {}", e);
+                                continue;
+                            }
+                        };
+                        for user in &users {
+                            if user.email.is_none()
+                                || !user.subscriptions.is_match(&event)
+                            {
+                                continue;
+                            }
+                            batches
+                                .entry(user.name.clone())
+                                .or_insert_with(|| Batch {
+                                    deadline: Instant::now() + self.batch_delay,
+                                    events: Vec::new(),
+                                })
+                                .events
+                                .push(event.clone());
+                        }
+                    }
+                }
+                () = sleep_until(deadline) => {
+                    let now = Instant::now();
+                    let due: Vec<_> = batches
+                        .iter()
+                        .filter(|(_, batch)| batch.deadline <= now)
+                        .map(|(username, _)| username.clone())
+                        .collect();
+                    for username in due {
+                        let batch = batches.remove(&username).unwrap();
+                        // The user may have changed their address in the meantime.
+                        let user = state.read().unwrap().user(&username);
+                        if let Ok(user) = user {
+                            self.send(&user, &batch.events).await;
+                        }
+                    }
+                }
+            }
+        }
+
+        // Send pending notifications before exiting.
+        for (username, batch) in batches {
+            let user = state.read().unwrap().user(&username);
+            if let Ok(user) = user {
+                self.send(&user, &batch.events).await;
+            }
+        }
+    }
+
+    async fn send(&self, user: &User, events: &[OperationEvent]) {
+        let Some(email) = &user.email else {
+            return;
+        };
+        if let Err(e) = self.try_send(user, email, events).await {
+            tracing::warn!("failed to send email to {}: {:#}", user.name, e);
+        }
+    }
+
+    async fn try_send(
+        &self,
+        user: &User,
+        email: &str,
+        events: &[OperationEvent],
+    ) -> anyhow::Result<()> {
+        let mut context = tera::Context::new();
+        context.insert("user", user);
+        context.insert("events", events);
+        let subject = self.templates.render(SUBJECT_TEMPLATE, &context)?;
+        let text = self.templates.render(TEXT_TEMPLATE, &context)?;
+        let html = self.templates.render(HTML_TEMPLATE, &context)?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(Mailbox::new(Some(user.name.clone()), email.parse()?))
+            .subject(subject.trim())
+            .multipart(MultiPart::alternative_plain_html(text, html))?;
+        self.transport.send(message).await?;
+        tracing::debug!("sent email with {} events to {}", events.len(), user.name);
+        Ok(())
+    }
+}
+
+/// Events waiting to be sent to a user
+struct Batch {
+    deadline: Instant,
+    events: Vec<OperationEvent>,
+}
+
+/// Sleeps until the deadline, or forever if there is no deadline.
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestServer;
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+        sync::mpsc,
+    };
+
+    /// A message received by the SMTP sink
+    struct Received {
+        recipients: Vec<String>,
+        data: String,
+    }
+
+    /// Starts an SMTP server accepting any message, and returns its port and
+    /// the messages it receives.
+    async fn smtp_sink() -> (u16, mpsc::UnboundedReceiver<Received>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    let (reader, mut writer) = stream.into_split();
+                    let mut lines = BufReader::new(reader).lines();
+                    writer.write_all(b"220 localhost ESMTP\r\n").await?;
+                    let mut recipients = Vec::new();
+                    while let Some(line) = lines.next_line().await? {
+                        let reply: &[u8] = if let Some(to) = line.strip_prefix("RCPT TO:") {
+                            recipients.push(to.to_owned());
+                            b"250 OK\r\n"
+                        } else if line == "DATA" {
+                            writer
+                                .write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n")
+                                .await?;
+                            let mut data = String::new();
+                            while let Some(line) = lines.next_line().await? {
+                                if line == "." {
+                                    break;
+                                }
+                                data.push_str(&line);
+                                data.push('\n');
+                            }
+                            let recipients = std::mem::take(&mut recipients);
+                            let _ = tx.send(Received { recipients, data });
+                            b"250 OK\r\n"
+                        } else if line == "QUIT" {
+                            b"221 Bye\r\n"
+                        } else {
+                            b"250 OK\r\n"
+                        };
+                        writer.write_all(reply).await?;
+                    }
+                    std::io::Result::Ok(())
+                });
+            }
+        });
+        (port, rx)
+    }
+
+    #[tokio::test]
+    async fn emails_events_to_subscribers() {
+        let server = TestServer::new();
+        let alice = server.login("alice");
+        let bob = server.login("bob");
+        let carol = server.login("carol");
+        server.create_component(&alice, "foo", &["alice"]).await;
+        for (token, email) in [
+            (&alice, "alice@example.com"),
+            (&bob, "bob@example.com"),
+            (&carol, "carol@example.com"),
+        ] {
+            let (status, body) = server
+                .patch("/users/me", token, json!({ "email": email }))
+                .await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+        }
+        let (status, body) = server
+            .post("/subscriptions", &alice, json!({ "component": "foo" }))
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+
+        let (port, mut received) = smtp_sink().await;
+        let mailer = Mailer::new(
+            &format!("smtp://127.0.0.1:{port}"),
+            "smokestack <noreply@example.com>".parse().unwrap(),
+            None,
+            Duration::from_millis(10),
+        )
+        .unwrap();
+        tokio::spawn(mailer.run(server.state.clone()));
+        server.wait_for_receivers(1).await;
+        // Bob is subscribed to the operation as its operator, and Alice to
+        // its component. Carol is not subscribed.
+        let id = server.create_operation(&bob, &["foo"]).await;
+
+        let mut messages = Vec::new();
+        while let Ok(message) =
+            tokio::time::timeout(Duration::from_millis(500), received.recv()).await
+        {
+            messages.push(message.unwrap());
+        }
+        messages.sort_by(|a, b| a.recipients.cmp(&b.recipients));
+        assert_eq!(messages.len(), 2);
+        for (message, name) in messages.iter().zip(["alice", "bob"]) {
+            assert_eq!(message.recipients, [format!("<{name}@example.com>")]);
+            let data = &message.data;
+            assert!(
+                data.contains(&format!("To: {name} <{name}@example.com>")),
+                "{data}"
+            );
+            assert!(
+                data.contains("Subject: [smokestack] Test (planned)"),
+                "{data}"
+            );
+            assert!(data.contains(&format!("Hello {name},")), "{data}");
+            assert!(
+                data.contains(&format!("Operation {id}: created by bob")),
+                "{data}"
+            );
+        }
+    }
+}
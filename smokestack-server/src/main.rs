@@ -1,9 +1,16 @@
 mod api;
+mod auth;
+mod chat;
+mod email;
+mod storage;
+#[cfg(test)]
+mod testing;
+mod webhook;
 
 use axum::{
     async_trait,
-    extract::FromRequestParts,
-    http::{request::Parts, Response, StatusCode},
+    extract::{FromRequestParts, MatchedPath},
+    http::{request::Parts, Response, StatusCode, Uri},
     response::IntoResponse,
     Json, RequestPartsExt, Router,
 };
@@ -11,29 +18,157 @@ use axum_extra::{
     headers::{authorization::Bearer, Authorization},
     TypedHeader,
 };
-use clap::Parser;
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
 use smokestack::{
-    api::ApiResponse,
-    model::{Claims, Component, Operation, OperationState, SubscriptionSet, Tag, User},
+    api::{ApiResponse, AuthResponse, HistoryQuery, ListOperationsQuery},
+    model::{
+        ApiToken, ApprovalRequirement, Channel, ChatFlavor, Claims, Component, EventKind,
+        FailedDelivery, FieldChange, Group, Operation, OperationEvent, OperationState, Scope,
+        ServiceAccount, SubscriptionFilter, SubscriptionSet, Tag, User, Webhook,
+    },
 };
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, HashMap, HashSet},
+    future::Future,
     net::SocketAddr,
-    path::PathBuf,
-    sync::{Arc, RwLock},
+    path::{Path, PathBuf},
+    sync::{Arc, LockResult, PoisonError, RwLock, RwLockWriteGuard},
+};
+use storage::{JsonStorage, SqliteStorage, Storage, Subject};
+use subtle::ConstantTimeEq;
+use tokio::{
+    net::TcpListener,
+    sync::{broadcast, watch},
 };
-use tokio::{net::TcpListener, sync::broadcast};
 use tower_http::trace::TraceLayer;
 
 #[derive(Debug, Parser)]
 #[clap(version)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[arg(short, long, default_value = "0.0.0.0:3000")]
     addr: SocketAddr,
 
     #[arg(short, long, default_value = "state.json")]
     state_file: PathBuf,
+
+    /// SQLite database to store the data in, instead of the JSON state file
+    #[arg(long, conflicts_with = "state_file")]
+    sqlite: Option<PathBuf>,
+
+    /// User or group allowed to change the owners and approval requirements
+    /// of any component or tag, and to manage groups, webhooks and service
+    /// accounts. Without admins, groups are managed by their members. Can be
+    /// specified multiple times.
+    #[arg(long = "admin", name = "USER_OR_GROUP")]
+    admins: Vec<String>,
+
+    /// URL of the SMTP server used to send email notifications
+    /// (e.g. `smtp://localhost:25`). If not specified, no emails are sent.
+    #[arg(long, requires = "smtp_from")]
+    smtp_url: Option<String>,
+
+    /// Sender of email notifications (e.g. "smokestack <noreply@example.com>")
+    #[arg(long)]
+    smtp_from: Option<lettre::message::Mailbox>,
+
+    /// Directory containing templates overriding the default ones for email
+    /// notifications (subject.txt, body.txt and body.html)
+    #[arg(long)]
+    email_template_dir: Option<PathBuf>,
+
+    /// Seconds to wait for more events before sending an email notification
+    #[arg(long, default_value_t = 30)]
+    email_batch_delay: u64,
+
+    /// Verification token of slash commands sent by Slack or Mattermost.
+    /// If not specified, slash commands are rejected.
+    #[arg(long)]
+    chat_token: Option<String>,
+
+    /// Host that incoming webhooks set up by slash commands may post to.
+    /// Can be specified multiple times.
+    #[arg(
+        long = "chat-webhook-host",
+        name = "HOST",
+        default_value = "hooks.slack.com"
+    )]
+    chat_webhook_hosts: Vec<String>,
+
+    /// Days to keep subscriptions to operations after they are finished
+    #[arg(long, default_value_t = 7)]
+    subscription_retention: u32,
+
+    /// File containing the key used to sign tokens. If not specified, a
+    /// random key is generated, and tokens are invalidated on restart.
+    #[arg(long)]
+    jwt_key_file: Option<PathBuf>,
+
+    /// File of `username:bcrypt-hash` lines to check passwords against
+    /// (e.g. created with `htpasswd -B`)
+    #[arg(long)]
+    htpasswd_file: Option<PathBuf>,
+
+    /// Header set by a reverse proxy to the name of the authenticated user
+    /// (e.g. `X-Forwarded-User`). The proxy must strip it from incoming
+    /// requests.
+    #[arg(long)]
+    trusted_user_header: Option<axum::http::HeaderName>,
+
+    /// Let anyone log in as any user without credentials. Only for
+    /// development.
+    #[arg(long)]
+    allow_any_user: bool,
+
+    /// Seconds until access tokens expire
+    #[arg(long, default_value_t = 15 * 60)]
+    access_token_ttl: u64,
+
+    /// Seconds until refresh tokens expire
+    #[arg(long, default_value_t = 30 * 24 * 60 * 60)]
+    refresh_token_ttl: u64,
+
+    /// Issuer of OpenID Connect ID tokens to accept. Users presenting a valid
+    /// ID token are created on first sight, without logging in.
+    #[arg(long, requires_all = ["oidc_audience", "oidc_jwks"])]
+    oidc_issuer: Option<String>,
+
+    /// Audience that ID tokens must be issued for (usually the client ID)
+    #[arg(long, requires = "oidc_issuer")]
+    oidc_audience: Option<String>,
+
+    /// URL or path of the JSON Web Key Set used to verify ID tokens
+    #[arg(long, requires = "oidc_issuer")]
+    oidc_jwks: Option<auth::JwksSource>,
+
+    /// Claim of ID tokens holding the username
+    #[arg(long, default_value = "sub")]
+    oidc_username_claim: String,
+
+    /// Claim of ID tokens holding the groups the user is a member of. If
+    /// specified, the memberships of users authenticated with ID tokens in
+    /// existing groups are kept in sync with the claim.
+    #[arg(long)]
+    oidc_groups_claim: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Copy the data in the JSON state file to a new SQLite database
+    ConvertToSqlite {
+        /// Path of the SQLite database to create
+        database: PathBuf,
+    },
+
+    /// Upgrade the JSON state file to the current version
+    Migrate {
+        /// Report what would change without writing anything
+        #[arg(long)]
+        check: bool,
+    },
 }
 
 #[tokio::main]
@@ -43,73 +178,235 @@ async fn main() -> anyhow::Result<()> {
         .init();
 
     let cli = Cli::parse();
-    let database = if let Ok(serialized) = std::fs::read(&cli.state_file) {
-        tracing::info!("loading state from {}", cli.state_file.display());
-        serde_json::from_slice(&serialized)?
+    match &cli.command {
+        Some(Command::ConvertToSqlite { database }) => {
+            return convert_to_sqlite(&cli.state_file, database);
+        }
+        Some(Command::Migrate { check }) => return migrate(&cli.state_file, *check),
+        None => {}
+    }
+
+    let mut checks: Vec<Box<dyn auth::CredentialCheck>> = Vec::new();
+    if let Some(path) = cli.htpasswd_file {
+        checks.push(Box::new(auth::Htpasswd(path)));
+    }
+    if let Some(name) = cli.trusted_user_header {
+        checks.push(Box::new(auth::TrustedHeader(name)));
+    }
+    if cli.allow_any_user {
+        tracing::warn!("any user can log in without credentials");
+        checks.push(Box::new(auth::AllowAny));
+    }
+    let oidc = if let (Some(issuer), Some(audience), Some(jwks)) =
+        (cli.oidc_issuer, cli.oidc_audience, cli.oidc_jwks)
+    {
+        tracing::info!("accepting ID tokens issued by {}", issuer);
+        Some(
+            auth::Oidc::new(
+                issuer,
+                audience,
+                cli.oidc_username_claim,
+                cli.oidc_groups_claim,
+                jwks,
+            )
+            .await?,
+        )
     } else {
-        Database::default()
+        None
     };
-    let (operation_tx, _) = broadcast::channel(1024);
-    let mut state = AppState {
-        database,
-        locks: LockTable::default(),
-        operation_tx,
+    anyhow::ensure!(
+        !checks.is_empty() || oidc.is_some(),
+        "no way to log in is configured; specify --htpasswd-file, --trusted-user-header, --allow-any-user or --oidc-issuer"
+    );
+    let auth = auth::Auth::new(
+        checks,
+        cli.jwt_key_file.as_deref(),
+        tokio::time::Duration::from_secs(cli.access_token_ttl),
+        tokio::time::Duration::from_secs(cli.refresh_token_ttl),
+        oidc,
+    )?;
+
+    let storage: Box<dyn Storage> = if let Some(path) = &cli.sqlite {
+        tracing::info!("using SQLite database {}", path.display());
+        Box::new(SqliteStorage::open(path)?)
+    } else {
+        Box::new(JsonStorage::open(&cli.state_file)?)
     };
-    for operation in state.database.operations.values() {
-        if !matches!(
-            operation.status,
-            OperationState::InProgress | OperationState::Paused
-        ) {
-            continue;
-        }
-        for lock in &operation.locks {
-            state.locks.lock(lock, ComponentLock::Exclusive).unwrap();
-        }
-        for component in &operation.components {
-            if operation.locks.contains(component) {
-                state.locks.lock(component, ComponentLock::Shared).unwrap();
+    let state = AppState::new(
+        storage,
+        auth,
+        cli.admins,
+        cli.chat_token,
+        cli.chat_webhook_hosts,
+    )?;
+    let state = SharedState(Arc::new(RwLock::new(state)));
+
+    tokio::spawn({
+        let state = state.clone();
+        async move {
+            loop {
+                tokio::time::sleep(tokio::time::Duration::from_mins(1)).await;
+                if let Err(e) = state.write().unwrap().storage.compact() {
+                    tracing::error!("failed to compact storage: {:#}", e);
+                }
             }
         }
-    }
-    let state = SharedState(Arc::new(RwLock::new(state)));
+    });
 
-    // We don't care about losing some data in PoC.
     tokio::spawn({
         let state = state.clone();
+        let subscription_retention = chrono::TimeDelta::days(cli.subscription_retention.into());
         async move {
             loop {
-                tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
-                let serialized = {
-                    let state = state.read().unwrap();
-                    let db = &state.database;
-                    tracing::debug!(
-                        "saving state: users={}, operations={}, components={}, tags={}",
-                        db.users.len(),
-                        db.operations.len(),
-                        db.components.len(),
-                        db.tags.len(),
-                    );
-                    serde_json::to_string(&db).unwrap()
-                };
-                std::fs::write(&cli.state_file, serialized).unwrap();
+                tokio::time::sleep(tokio::time::Duration::from_mins(1)).await;
+                let mut state = state.write().unwrap();
+                if let Err(e) = state.expire_schedules() {
+                    tracing::warn!("failed to expire schedules: {}", e);
+                }
+                if let Err(e) = state
+                    .prune_subscriptions(subscription_retention)
+                    .and_then(|()| state.commit())
+                {
+                    tracing::warn!("failed to prune subscriptions: {}", e);
+                }
             }
         }
     });
 
-    let routes =
-        Router::new()
-            .nest("/api/v1", api::root())
-            .layer(TraceLayer::new_for_http().make_span_with(
-                tower_http::trace::DefaultMakeSpan::default().include_headers(true),
-            ))
-            .with_state(state);
+    tokio::spawn(webhook::run(state.clone()));
+    tokio::spawn(chat::run(state.clone()));
+
+    if let (Some(url), Some(from)) = (&cli.smtp_url, cli.smtp_from) {
+        let mailer = email::Mailer::new(
+            url,
+            from,
+            cli.email_template_dir.as_deref(),
+            tokio::time::Duration::from_secs(cli.email_batch_delay),
+        )?;
+        tokio::spawn(mailer.run(state.clone()));
+    }
 
     let listener = TcpListener::bind(cli.addr).await?;
     tracing::info!("listening on {}", listener.local_addr()?);
-    axum::serve(listener, routes).await?;
+    serve(listener, state, shutdown_signal()).await
+}
+
+/// Time to wait for event receivers to finish after requests are drained
+const SHUTDOWN_TIMEOUT: tokio::time::Duration = tokio::time::Duration::from_secs(30);
+
+/// Serves the API until `signal` completes, and then shuts down gracefully.
+///
+/// Once requests are drained and event receivers have finished, the storage
+/// is compacted so that the server starts quickly next time.
+async fn serve(
+    listener: TcpListener,
+    state: SharedState,
+    signal: impl Future<Output = ()> + Send + 'static,
+) -> anyhow::Result<()> {
+    let shutdown = state.read().unwrap().shutdown.clone();
+    let routes = app(state.clone()).layer(
+        TraceLayer::new_for_http()
+            .make_span_with(tower_http::trace::DefaultMakeSpan::default().include_headers(true)),
+    );
+    axum::serve(listener, routes)
+        .with_graceful_shutdown({
+            let shutdown = shutdown.clone();
+            async move {
+                signal.await;
+                tracing::info!("shutting down");
+                shutdown.send_replace(true);
+            }
+        })
+        .await?;
+
+    // Event receivers finish by closing watchers, sending pending
+    // notifications and recording the webhook deliveries left to retry.
+    if tokio::time::timeout(SHUTDOWN_TIMEOUT, shutdown.closed())
+        .await
+        .is_err()
+    {
+        tracing::warn!("timed out waiting for event receivers to finish");
+    }
+    state.write().unwrap().storage.compact()?;
+    tracing::info!("shut down");
+    Ok(())
+}
+
+/// Waits for SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                tracing::warn!("failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    #[allow(clippy::redundant_pub_crate)]
+    {
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => {
+                if let Err(e) = result {
+                    tracing::warn!("failed to listen for SIGINT: {}", e);
+                    std::future::pending::<()>().await;
+                }
+            }
+            () = terminate => {}
+        }
+    }
+}
+
+/// Upgrades the JSON state file to the current version.
+///
+/// If `check` is true, only reports what would change.
+fn migrate(state_file: &Path, check: bool) -> anyhow::Result<()> {
+    let changes = JsonStorage::check_migrations(state_file)?;
+    if changes.is_empty() {
+        println!("{} is up to date", state_file.display());
+        return Ok(());
+    }
+    if check {
+        println!("{} would be migrated:", state_file.display());
+    } else {
+        JsonStorage::open(state_file)?;
+        println!("{} was migrated:", state_file.display());
+    }
+    for change in changes {
+        println!("  {change}");
+    }
+    Ok(())
+}
+
+/// Copies the data in the JSON state file to a new SQLite database.
+fn convert_to_sqlite(state_file: &Path, database: &Path) -> anyhow::Result<()> {
+    let from = JsonStorage::open_read_only(state_file)?;
+    let mut to = SqliteStorage::open(database)?;
+    anyhow::ensure!(
+        to.is_empty()?,
+        "{} already contains data",
+        database.display()
+    );
+    storage::copy(&from, &mut to)?;
+    tracing::info!("copied {} to {}", state_file.display(), database.display());
     Ok(())
 }
 
+/// Routes requests to the API.
+fn app(state: SharedState) -> Router {
+    Router::new().nest("/api/v1", api::root()).with_state(state)
+}
+
+/// The actor recorded in the history for changes made by the server itself.
+const SYSTEM_ACTOR: &str = "smokestack";
+
 type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug, thiserror::Error)]
@@ -120,12 +417,30 @@ enum Error {
     #[error("invalid token")]
     InvalidToken,
 
+    #[error("token expired")]
+    ExpiredToken,
+
+    #[error("invalid username or credentials")]
+    InvalidCredentials,
+
+    #[error("permission denied")]
+    Forbidden,
+
+    #[error("the token is not allowed to make this request")]
+    MissingScope,
+
+    #[error("{0} is a service account")]
+    ServiceAccount(String),
+
     #[error("{} {} already exists", .entity, .id)]
     AlreadyExists { entity: &'static str, id: String },
 
     #[error("{} {} not found", .entity, .id)]
     NotFound { entity: &'static str, id: String },
 
+    #[error("{} {} is in use", .entity, .id)]
+    InUse { entity: &'static str, id: String },
+
     #[error("at least one {0} is required")]
     MissingItem(&'static str),
 
@@ -135,40 +450,99 @@ enum Error {
     #[error("url should have http or https scheme")]
     InvalidUrlScheme,
 
+    #[error("webhook url should point to one of: {0}")]
+    DisallowedWebhookHost(String),
+
+    #[error("invalid email address")]
+    InvalidEmailAddress,
+
     #[error("locked component must be one of the affected components")]
     LockingNonAffectedComponent,
 
-    #[error("failed to acquire lock on component {0}")]
-    LockFailed(String),
+    #[error("component {component} is locked by operation {holder}")]
+    LockFailed { component: String, holder: u64 },
 
     #[error("Dependent operations must be completed before starting this operation")]
     UnmetDependency,
 
+    #[error("end time must not be earlier than start time")]
+    InvalidSchedule,
+
+    #[error(
+        "schedule conflicts with operation {operation} on exclusively locked component {component}"
+    )]
+    ScheduleConflict { operation: u64, component: String },
+
+    #[error("operation {dependent} cannot be scheduled because it cannot meet the dependency on operation {dependency}")]
+    UnmeetableDependency { dependent: u64, dependency: u64 },
+
+    #[error(
+        "dependency cycle detected: {}",
+        .0.iter().map(ToString::to_string).collect::<Vec<_>>().join(" -> ")
+    )]
+    DependencyCycle(Vec<u64>),
+
+    #[error("operation cannot start because it does not have enough approvals required by the {entity} {name}")]
+    InsufficientApprovals { entity: &'static str, name: String },
+
+    #[error("approvals can only be given or revoked before the operation starts")]
+    ChangingApprovalsAfterStart,
+
+    #[error("operators and users on whose behalf an operation was created cannot approve it")]
+    SelfApproval,
+
     #[error("invalid state transition")]
     InvalidStateTransition,
 
     #[error("exactly one of operation, component, or tag must be specified")]
     SubscribingMultipleEntities,
 
+    #[error("delivery failed: {0}")]
+    DeliveryFailed(String),
+
     #[error("internal error")]
     Internal,
 }
 
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        tracing::error!("storage error: {:#}", e);
+        Self::Internal
+    }
+}
+
 impl IntoResponse for Error {
     fn into_response(self) -> Response<axum::body::Body> {
         let status = match self {
-            Self::MissingToken => StatusCode::UNAUTHORIZED,
+            Self::MissingToken | Self::ExpiredToken | Self::InvalidCredentials => {
+                StatusCode::UNAUTHORIZED
+            }
             Self::InvalidToken
             | Self::AlreadyExists { .. }
             | Self::MissingItem(_)
             | Self::BlankItem(_)
             | Self::InvalidUrlScheme
+            | Self::DisallowedWebhookHost(_)
+            | Self::InvalidEmailAddress
             | Self::LockingNonAffectedComponent
+            | Self::DependencyCycle(_)
+            | Self::InvalidSchedule
+            | Self::ChangingApprovalsAfterStart
             | Self::InvalidStateTransition
             | Self::SubscribingMultipleEntities => StatusCode::BAD_REQUEST,
             Self::NotFound { .. } => StatusCode::NOT_FOUND,
+            Self::InUse { .. } => StatusCode::CONFLICT,
+            Self::Forbidden
+            | Self::MissingScope
+            | Self::ServiceAccount(_)
+            | Self::InsufficientApprovals { .. }
+            | Self::SelfApproval => StatusCode::FORBIDDEN,
             Self::UnmetDependency => StatusCode::FAILED_DEPENDENCY,
-            Self::LockFailed(_) => StatusCode::LOCKED,
+            Self::LockFailed { .. } => StatusCode::LOCKED,
+            Self::ScheduleConflict { .. } | Self::UnmeetableDependency { .. } => {
+                StatusCode::CONFLICT
+            }
+            Self::DeliveryFailed(_) => StatusCode::BAD_GATEWAY,
             Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
         };
         (status, Json(ApiResponse::err(self))).into_response()
@@ -186,6 +560,146 @@ impl std::ops::Deref for SharedState {
     }
 }
 
+impl SharedState {
+    /// Locks the state for writing, starting a transaction.
+    fn write(&self) -> LockResult<WriteGuard<'_>> {
+        self.0
+            .write()
+            .map(WriteGuard)
+            .map_err(|e| PoisonError::new(WriteGuard(e.into_inner())))
+    }
+}
+
+/// Exclusive access to the state.
+///
+/// Changes must be committed with `AppState::commit`. Changes left when the
+/// guard is dropped, such as those of a handler failing half-way, are rolled
+/// back.
+struct WriteGuard<'a>(RwLockWriteGuard<'a, AppState>);
+
+impl std::ops::Deref for WriteGuard<'_> {
+    type Target = AppState;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl std::ops::DerefMut for WriteGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl Drop for WriteGuard<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.0.rollback() {
+            // The state no longer reflects the storage, so poison the lock
+            // to stop serving requests from it.
+            tracing::error!("failed to roll back changes: {:#}", e);
+            assert!(std::thread::panicking(), "failed to roll back changes");
+        }
+    }
+}
+
+/// Receives operation events in order.
+///
+/// Events missed because the receiver lagged behind are recovered from the
+/// history.
+struct EventReceiver {
+    state: SharedState,
+    rx: broadcast::Receiver<OperationEvent>,
+    last_id: Option<u64>,
+    shutdown: watch::Receiver<bool>,
+}
+
+impl EventReceiver {
+    /// Creates a receiver of the events recorded from now on.
+    fn new(state: SharedState) -> Result<Self> {
+        let (rx, shutdown, last_id) = {
+            let state = state.read().unwrap();
+            (
+                state.operation_tx.subscribe(),
+                state.shutdown.subscribe(),
+                state.storage.last_event_id()?,
+            )
+        };
+        // Events up to the last one were recorded before the receiver was
+        // created, so they must not be recovered if the receiver lags.
+        Ok(Self {
+            state,
+            rx,
+            last_id,
+            shutdown,
+        })
+    }
+
+    /// Creates a receiver that resumes after the event with the ID `since`.
+    ///
+    /// Returns the receiver and the events after `since` in the history,
+    /// which the receiver will not return.
+    fn resume(state: SharedState, since: u64) -> Result<(Self, Vec<OperationEvent>)> {
+        let (rx, shutdown, backlog) = {
+            let state = state.read().unwrap();
+            let backlog = state.events(&HistoryQuery {
+                after: Some(since),
+                ..Default::default()
+            })?;
+            (
+                state.operation_tx.subscribe(),
+                state.shutdown.subscribe(),
+                backlog,
+            )
+        };
+        let last_id = backlog.last().map_or(since, |event| event.id);
+        let receiver = Self {
+            state,
+            rx,
+            last_id: Some(last_id),
+            shutdown,
+        };
+        Ok((receiver, backlog))
+    }
+
+    /// Returns the next events, or `None` if no more events will be sent.
+    ///
+    /// Once the server starts shutting down, `None` is returned.
+    async fn recv(&mut self) -> Option<Vec<OperationEvent>> {
+        #[allow(clippy::redundant_pub_crate)]
+        let result = tokio::select! {
+            result = self.rx.recv() => result,
+            _ = self.shutdown.wait_for(|shutdown| *shutdown) => return None,
+        };
+        let events = match result {
+            Ok(event) => vec![event],
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!("event receiver lagged behind by {} events", skipped);
+                let query = HistoryQuery {
+                    after: self.last_id,
+                    ..Default::default()
+                };
+                match self.state.read().unwrap().events(&query) {
+                    Ok(events) => events,
+                    Err(e) => {
+                        tracing::error!("failed to recover missed events: {}", e);
+                        return None;
+                    }
+                }
+            }
+            Err(broadcast::error::RecvError::Closed) => return None,
+        };
+        let last_id = self.last_id;
+        let events: Vec<_> = events
+            .into_iter()
+            .filter(|event| last_id.is_none_or(|last_id| event.id > last_id))
+            .collect();
+        if let Some(event) = events.last() {
+            self.last_id = Some(event.id);
+        }
+        Some(events)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum ComponentLock {
     /// The component is locked for shared access.
@@ -202,90 +716,365 @@ enum ComponentLock {
     Exclusive,
 }
 
+/// Locks held on components, keyed by component name and then by the ID of
+/// the operation holding the lock.
+type Locks = HashMap<String, HashMap<u64, ComponentLock>>;
+
 #[derive(Default)]
-struct LockTable(HashMap<String, ComponentLock>);
+struct LockTable {
+    locks: Locks,
+
+    /// Locks as of the last commit, saved before they first change after it
+    committed: Option<Locks>,
+}
 
 impl LockTable {
-    fn lock(&mut self, component: &str, lock: ComponentLock) -> Result<()> {
-        match self.0.entry(component.to_string()) {
-            std::collections::hash_map::Entry::Vacant(entry) => {
-                entry.insert(lock);
-            }
-            std::collections::hash_map::Entry::Occupied(mut entry) => {
-                if lock == ComponentLock::Exclusive || *entry.get() == ComponentLock::Exclusive {
-                    return Err(Error::LockFailed(component.to_string()));
+    /// Acquires all the locks needed by the operation.
+    ///
+    /// Either all the locks are acquired or none of them are.
+    /// Locks already held by the operation but no longer needed are released.
+    fn lock(&mut self, operation: &Operation) -> Result<()> {
+        let needed: HashMap<&str, ComponentLock> = operation
+            .components
+            .iter()
+            .map(|component| {
+                let lock = if operation.locks.contains(component) {
+                    ComponentLock::Exclusive
+                } else {
+                    ComponentLock::Shared
+                };
+                (component.as_str(), lock)
+            })
+            .collect();
+        for (component, lock) in &needed {
+            let Some(holders) = self.locks.get(*component) else {
+                continue;
+            };
+            for (holder, held) in holders {
+                if *holder == operation.id {
+                    continue;
+                }
+                if *lock == ComponentLock::Exclusive || *held == ComponentLock::Exclusive {
+                    return Err(Error::LockFailed {
+                        component: (*component).to_owned(),
+                        holder: *holder,
+                    });
                 }
-                *entry.get_mut() = lock;
             }
         }
+        self.unlock(operation.id);
+        for (component, lock) in needed {
+            self.locks
+                .entry(component.to_owned())
+                .or_default()
+                .insert(operation.id, lock);
+        }
         Ok(())
     }
 
-    #[allow(unused)]
-    fn unlock(&mut self, component: &str) {
-        self.0.remove(component).unwrap();
+    /// Releases all the locks held by the operation.
+    fn unlock(&mut self, operation_id: u64) {
+        self.committed.get_or_insert_with(|| self.locks.clone());
+        self.locks.retain(|_, holders| {
+            holders.remove(&operation_id);
+            !holders.is_empty()
+        });
+    }
+
+    fn commit(&mut self) {
+        self.committed = None;
+    }
+
+    /// Restores the locks as of the last commit.
+    fn rollback(&mut self) {
+        if let Some(locks) = self.committed.take() {
+            self.locks = locks;
+        }
     }
 }
 
 struct AppState {
-    database: Database,
     locks: LockTable,
-    operation_tx: broadcast::Sender<Operation>,
+    operation_tx: broadcast::Sender<OperationEvent>,
+
+    /// Events recorded since the last commit, broadcast once committed
+    pending_events: Vec<OperationEvent>,
+
+    /// Set to `true` when the server starts shutting down
+    shutdown: watch::Sender<bool>,
+
+    /// Users and groups allowed to perform administrative tasks
+    admins: Vec<String>,
+
+    /// Verification token of slash commands
+    chat_token: Option<String>,
+
+    /// Hosts that chat channels may be set up to post to
+    chat_webhook_hosts: Vec<String>,
+
+    /// Time before which operations finished and had their subscriptions
+    /// pruned
+    subscriptions_pruned_until: Option<DateTime<Utc>>,
+
+    auth: Arc<auth::Auth>,
+
+    storage: Box<dyn Storage>,
 }
 
 impl AppState {
-    fn next_id(&mut self) -> u64 {
-        let id = self.database.next_id;
-        self.database.next_id += 1;
-        id
+    fn new(
+        storage: Box<dyn Storage>,
+        auth: auth::Auth,
+        admins: Vec<String>,
+        chat_token: Option<String>,
+        chat_webhook_hosts: Vec<String>,
+    ) -> Result<Self> {
+        let (operation_tx, _) = broadcast::channel(1024);
+        let (shutdown, _) = watch::channel(false);
+        let mut state = Self {
+            locks: LockTable::default(),
+            operation_tx,
+            pending_events: Vec::new(),
+            shutdown,
+            admins,
+            chat_token,
+            chat_webhook_hosts,
+            subscriptions_pruned_until: None,
+            auth: Arc::new(auth),
+            storage,
+        };
+        for operation in state.operations(&ListOperationsQuery::default())? {
+            if operation.status.holds_locks() {
+                state.locks.lock(&operation)?;
+            }
+        }
+        state.locks.commit();
+        Ok(state)
     }
 
-    fn user(&self, username: &str) -> Result<&User> {
-        self.database.users.get(username).ok_or(Error::NotFound {
+    fn next_id(&mut self) -> Result<u64> {
+        let id = self.storage.next_id()?;
+        self.storage.set_next_id(id + 1)?;
+        Ok(id)
+    }
+
+    /// Makes the changes made so far durable, and then broadcasts the events
+    /// recorded along with them.
+    ///
+    /// The changes must be committed before they are acknowledged.
+    fn commit(&mut self) -> Result<()> {
+        self.storage.commit()?;
+        self.locks.commit();
+        for event in self.pending_events.drain(..) {
+            if let Err(e) = self.operation_tx.send(event) {
+                tracing::warn!("failed to broadcast operation event: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Discards the changes made since the last commit.
+    fn rollback(&mut self) -> anyhow::Result<()> {
+        self.pending_events.clear();
+        self.locks.rollback();
+        self.storage.rollback()
+    }
+
+    fn user(&self, username: &str) -> Result<User> {
+        self.storage.user(username)?.ok_or_else(|| Error::NotFound {
             entity: "user",
             id: username.to_string(),
         })
     }
 
-    fn user_mut(&mut self, username: &str) -> Result<&mut User> {
-        self.database
-            .users
-            .get_mut(username)
-            .ok_or(Error::NotFound {
-                entity: "user",
-                id: username.to_string(),
-            })
+    /// Returns the users subscribed to the operation, or to any of its
+    /// components or tags.
+    fn subscribed_users(&self, operation: &Operation) -> Result<Vec<User>> {
+        Ok(self.storage.subscribed_users(&Subject::of(operation))?)
     }
 
     fn create_user(&mut self, username: String) -> Result<User> {
+        if username == SYSTEM_ACTOR
+            || self.storage.group(&username)?.is_some()
+            || self.storage.user(&username)?.is_some()
+        {
+            return Err(Error::AlreadyExists {
+                entity: "user",
+                id: username,
+            });
+        }
         let user = User {
-            name: username.clone(),
+            name: username,
+            email: None,
             subscriptions: SubscriptionSet::default(),
         };
-        match self.database.users.entry(username) {
-            std::collections::hash_map::Entry::Vacant(entry) => {
-                entry.insert(user.clone());
-                Ok(user)
+        self.storage.put_user(&user)?;
+        Ok(user)
+    }
+
+    /// Returns the user logging in, creating it if it does not exist yet.
+    ///
+    /// Service accounts cannot log in.
+    fn ensure_user(&mut self, username: String) -> Result<User> {
+        if self.is_service_account(&username)? {
+            return Err(Error::ServiceAccount(username));
+        }
+        if let Some(user) = self.storage.user(&username)? {
+            return Ok(user);
+        }
+        self.create_user(username)
+    }
+
+    /// Issues an access token and a refresh token to the user.
+    ///
+    /// The refresh token continues `session` if specified, or starts a new
+    /// session. Expired refresh tokens of the user are deleted.
+    fn issue_tokens(&mut self, username: &str, session: Option<String>) -> Result<AuthResponse> {
+        let now = Utc::now();
+        for token in self.storage.refresh_tokens(username)? {
+            if token.expires_at <= now {
+                self.storage.delete_refresh_token(&token.hash)?;
+            }
+        }
+        let (refresh_token, record) = self.auth.new_refresh_token(username, session)?;
+        self.storage.put_refresh_token(&record)?;
+        Ok(AuthResponse {
+            token: self.auth.issue_access_token(username)?,
+            refresh_token,
+        })
+    }
+
+    /// Exchanges a refresh token for a new pair of tokens. Each refresh token
+    /// can only be used once.
+    ///
+    /// A token used again has likely been stolen, so its whole session is
+    /// revoked, and the revocation is committed even though an error is
+    /// returned.
+    fn refresh(&mut self, refresh_token: &str) -> Result<AuthResponse> {
+        let mut token = self
+            .storage
+            .refresh_token(&auth::hash_refresh_token(refresh_token))?
+            .ok_or(Error::InvalidToken)?;
+        if token.used {
+            tracing::warn!(
+                "a used refresh token of {} was presented; revoking the session",
+                token.username
+            );
+            self.revoke_session(&token.username, &token.session)?;
+            self.commit()?;
+            return Err(Error::InvalidToken);
+        }
+        if token.expires_at <= Utc::now() {
+            return Err(Error::ExpiredToken);
+        }
+        self.user(&token.username)?;
+        token.used = true;
+        self.storage.put_refresh_token(&token)?;
+        self.issue_tokens(&token.username, Some(token.session))
+    }
+
+    /// Revokes the session of the refresh token. Unknown tokens are ignored,
+    /// as they cannot be used anyway.
+    fn revoke(&mut self, refresh_token: &str) -> Result<()> {
+        if let Some(token) = self
+            .storage
+            .refresh_token(&auth::hash_refresh_token(refresh_token))?
+        {
+            self.revoke_session(&token.username, &token.session)?;
+        }
+        Ok(())
+    }
+
+    fn revoke_session(&mut self, username: &str, session: &str) -> Result<()> {
+        for token in self.storage.refresh_tokens(username)? {
+            if token.session == session {
+                self.storage.delete_refresh_token(&token.hash)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns whether the user authenticated by the identity provider exists
+    /// and is a member of exactly the groups the provider says.
+    fn is_provisioned(&self, identity: &auth::OidcIdentity) -> Result<bool> {
+        if self.storage.user(&identity.username)?.is_none() {
+            return Ok(false);
+        }
+        let Some(groups) = &identity.groups else {
+            return Ok(true);
+        };
+        let memberships = self.storage.groups_of(&identity.username)?;
+        if memberships
+            .iter()
+            .any(|group| !groups.contains(&group.name))
+        {
+            return Ok(false);
+        }
+        for name in groups {
+            if !memberships.iter().any(|group| group.name == *name)
+                && self.storage.group(name)?.is_some()
+            {
+                return Ok(false);
             }
-            std::collections::hash_map::Entry::Occupied(_) => Err(Error::AlreadyExists {
-                entity: "user",
-                id: user.name,
-            }),
         }
+        Ok(true)
     }
 
-    fn operation(&self, id: u64) -> Result<&Operation> {
-        self.database.operations.get(&id).ok_or(Error::NotFound {
+    /// Creates the user authenticated by the identity provider if it does
+    /// not exist yet, and updates its memberships of existing groups to match
+    /// the provider.
+    fn provision_user(&mut self, identity: &auth::OidcIdentity) -> Result<()> {
+        let username = &identity.username;
+        if self.storage.user(username)?.is_none() {
+            tracing::info!("provisioning user {}", username);
+            self.ensure_user(username.clone())?;
+        }
+        let Some(groups) = &identity.groups else {
+            return Ok(());
+        };
+        for mut group in self.storage.groups_of(username)? {
+            if !groups.contains(&group.name) {
+                group.members.retain(|member| member != username);
+                self.storage.put_group(&group)?;
+            }
+        }
+        for name in groups {
+            let Some(mut group) = self.storage.group(name)? else {
+                continue;
+            };
+            if !group.members.contains(username) {
+                group.members.push(username.clone());
+                group.members.sort_unstable();
+                self.storage.put_group(&group)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn update_user(&mut self, mut user: User) -> Result<User> {
+        if let Some(email) = &mut user.email {
+            *email = email.trim().to_string();
+            if email.parse::<lettre::Address>().is_err() {
+                return Err(Error::InvalidEmailAddress);
+            }
+        }
+        self.user(&user.name)?;
+        self.storage.put_user(&user)?;
+        Ok(user)
+    }
+
+    fn operation(&self, id: u64) -> Result<Operation> {
+        self.storage.operation(id)?.ok_or_else(|| Error::NotFound {
             entity: "operation",
             id: id.to_string(),
         })
     }
 
-    fn operations(&self) -> impl Iterator<Item = &Operation> {
-        self.database.operations.values()
+    fn operations(&self, query: &ListOperationsQuery) -> Result<Vec<Operation>> {
+        Ok(self.storage.operations(query)?)
     }
 
-    fn upsert_operation(&mut self, mut operation: Operation) -> Result<Operation> {
+    fn upsert_operation(&mut self, actor: &str, mut operation: Operation) -> Result<Operation> {
         operation.title = operation.title.trim().to_string();
         if operation.title.is_empty() {
             return Err(Error::BlankItem("title"));
@@ -299,7 +1088,7 @@ impl AppState {
         if operation
             .url
             .scheme_str()
-            .map_or(true, |scheme| !matches!(scheme, "http" | "https"))
+            .is_none_or(|scheme| !matches!(scheme, "http" | "https"))
         {
             return Err(Error::InvalidUrlScheme);
         }
@@ -341,6 +1130,9 @@ impl AppState {
         for depends_on in &operation.depends_on {
             self.operation(*depends_on)?;
         }
+        if let Some(cycle) = self.find_dependency_cycle(&operation) {
+            return Err(Error::DependencyCycle(cycle));
+        }
 
         if operation.operators.is_empty() {
             return Err(Error::MissingItem("operator"));
@@ -359,49 +1151,367 @@ impl AppState {
                 if !current.status.can_transition_to(operation.status) {
                     return Err(Error::InvalidStateTransition);
                 }
+
+                // Approvers approved what the operation was, so material changes
+                // require new approvals, including when starting it at once.
+                if current.status == OperationState::Planned
+                    && changes_approved_content(&current, &operation)
+                {
+                    operation.approved_by.clear();
+                }
+
                 if operation.status == OperationState::InProgress {
                     for depends_on in &operation.depends_on {
                         if self.operation(*depends_on)?.status != OperationState::Completed {
                             return Err(Error::UnmetDependency);
                         }
                     }
+                    if current.status == OperationState::Planned {
+                        self.check_approvals(&operation)?;
+                    }
+                }
+
+                // Actual start and end times cannot be edited once recorded.
+                if current.status != OperationState::Planned {
+                    operation.starts_at = current.starts_at;
+                }
+                if current.status.is_finished() {
+                    operation.ends_at = current.ends_at;
+                }
+                if current.status != operation.status {
+                    match operation.status {
+                        OperationState::InProgress if current.status == OperationState::Planned => {
+                            operation.starts_at = Some(Utc::now());
+                        }
+                        OperationState::Completed | OperationState::Aborted => {
+                            operation.ends_at = Some(Utc::now());
+                        }
+                        _ => (),
+                    }
                 }
-                // TODO: lock/unlock components
             }
             Err(Error::NotFound { .. }) => assert_eq!(operation.status, OperationState::Planned),
             Err(e) => return Err(e),
         }
 
-        let prev = self
-            .database
-            .operations
-            .insert(operation.id, operation.clone());
-        if prev.map_or(true, |prev| prev != operation) {
-            if let Err(e) = self.operation_tx.send(operation.clone()) {
-                tracing::warn!("failed to broadcast operation: {}", e);
+        // Operations running past their scheduled end time are considered to
+        // continue indefinitely.
+        if operation.status.holds_locks() && operation.ends_at.is_some_and(|t| t <= Utc::now()) {
+            operation.ends_at = None;
+        }
+        if let (Some(starts_at), Some(ends_at)) = (operation.starts_at, operation.ends_at) {
+            if ends_at < starts_at {
+                return Err(Error::InvalidSchedule);
             }
         }
+        self.check_schedule(&operation)?;
+
+        if operation.status.holds_locks() {
+            self.locks.lock(&operation)?;
+        } else {
+            self.locks.unlock(operation.id);
+        }
+
+        let prev = self.storage.operation(operation.id)?;
+        self.storage.put_operation(&operation)?;
+        self.auto_subscribe(prev.as_ref(), &operation)?;
+        let (kind, changes) = match prev {
+            None => (EventKind::Created, BTreeMap::new()),
+            Some(prev) if prev == operation => return Ok(operation),
+            Some(prev)
+                if prev.status != operation.status && prev.approved_by != operation.approved_by =>
+            {
+                // Record the edit invalidating the approvals separately, so
+                // that it is not mistaken for a mere transition.
+                let edited = Operation {
+                    status: prev.status,
+                    starts_at: prev.starts_at,
+                    ends_at: prev.ends_at,
+                    ..operation.clone()
+                };
+                let changes = diff_operations(&prev, &edited)?;
+                self.record_event(
+                    actor,
+                    EventKind::ApprovalsInvalidated,
+                    changes,
+                    edited.clone(),
+                )?;
+                (
+                    EventKind::Transitioned,
+                    diff_operations(&edited, &operation)?,
+                )
+            }
+            Some(prev) => {
+                let kind = if prev.status != operation.status {
+                    EventKind::Transitioned
+                } else if prev.approved_by != operation.approved_by {
+                    EventKind::ApprovalsInvalidated
+                } else {
+                    EventKind::Edited
+                };
+                (kind, diff_operations(&prev, &operation)?)
+            }
+        };
+        self.record_event(actor, kind, changes, operation.clone())?;
         Ok(operation)
     }
 
-    fn component(&self, name: &str) -> Result<&Component> {
-        self.database.components.get(name).ok_or(Error::NotFound {
-            entity: "component",
-            id: name.to_string(),
-        })
+    /// Subscribes operators to their operations and to the operations their
+    /// operations depend on.
+    ///
+    /// Only newly added operators and dependencies are subscribed to, so that
+    /// users can unsubscribe from them.
+    fn auto_subscribe(&mut self, prev: Option<&Operation>, operation: &Operation) -> Result<()> {
+        for operator in &operation.operators {
+            let Some(mut user) = self.storage.user(operator)? else {
+                continue;
+            };
+            let subscriptions = &mut user.subscriptions;
+            match prev {
+                Some(prev) if prev.operators.contains(operator) => {
+                    for depends_on in &operation.depends_on {
+                        if !prev.depends_on.contains(depends_on) {
+                            subscriptions.operations.entry(*depends_on).or_default();
+                        }
+                    }
+                }
+                _ => {
+                    for id in std::iter::once(&operation.id).chain(&operation.depends_on) {
+                        subscriptions.operations.entry(*id).or_default();
+                    }
+                }
+            }
+            self.storage.put_user(&user)?;
+        }
+        Ok(())
     }
 
-    fn components(&self) -> impl Iterator<Item = &Component> {
-        self.database.components.values()
+    /// Returns a cycle of dependencies going through the operation, if any.
+    ///
+    /// The returned path starts and ends with the ID of the operation.
+    fn find_dependency_cycle(&self, operation: &Operation) -> Option<Vec<u64>> {
+        fn visit(
+            state: &AppState,
+            target: u64,
+            depends_on: &[u64],
+            path: &mut Vec<u64>,
+            visited: &mut HashSet<u64>,
+        ) -> bool {
+            for &id in depends_on {
+                path.push(id);
+                if id == target {
+                    return true;
+                }
+                if visited.insert(id) {
+                    if let Ok(dependency) = state.operation(id) {
+                        if visit(state, target, &dependency.depends_on, path, visited) {
+                            return true;
+                        }
+                    }
+                }
+                path.pop();
+            }
+            false
+        }
+
+        let mut path = vec![operation.id];
+        let mut visited = HashSet::new();
+        visit(
+            self,
+            operation.id,
+            &operation.depends_on,
+            &mut path,
+            &mut visited,
+        )
+        .then_some(path)
     }
 
-    fn create_component(&mut self, mut component: Component) -> Result<Component> {
-        component.name = component.name.trim().to_string();
-        if component.name.is_empty() {
-            return Err(Error::BlankItem("name"));
+    /// Checks that the schedule of a planned operation does not conflict with
+    /// exclusive locks or dependencies of other operations.
+    fn check_schedule(&self, operation: &Operation) -> Result<()> {
+        if operation.status != OperationState::Planned {
+            return Ok(());
         }
+        let Some(starts_at) = operation.starts_at else {
+            return Ok(());
+        };
 
-        component.description = component.description.trim().to_string();
+        for other in &self
+            .storage
+            .scheduled_operations(starts_at, operation.ends_at)?
+        {
+            if other.id == operation.id {
+                continue;
+            }
+            let conflict = operation
+                .locks
+                .iter()
+                .find(|lock| other.components.contains(lock))
+                .or_else(|| {
+                    other
+                        .locks
+                        .iter()
+                        .find(|lock| operation.components.contains(lock))
+                });
+            if let Some(component) = conflict {
+                return Err(Error::ScheduleConflict {
+                    operation: other.id,
+                    component: component.clone(),
+                });
+            }
+        }
+
+        // A dependency must end before the dependent starts.
+        let mut pairs = Vec::new();
+        for id in &operation.depends_on {
+            if let Some(dependency) = self.storage.operation(*id)? {
+                pairs.push((operation.clone(), dependency));
+            }
+        }
+        for dependent in self.storage.dependents(operation.id)? {
+            if !operation.depends_on.contains(&dependent.id) {
+                pairs.push((dependent, operation.clone()));
+            }
+        }
+        for (dependent, dependency) in pairs {
+            if dependent.id == dependency.id
+                || dependency.status.is_finished()
+                || dependent.status != OperationState::Planned
+            {
+                continue;
+            }
+            let Some(dependent_starts_at) = dependent.starts_at else {
+                continue;
+            };
+            if dependency
+                .ends_at
+                .or(dependency.starts_at)
+                .is_some_and(|t| dependent_starts_at < t)
+            {
+                return Err(Error::UnmeetableDependency {
+                    dependent: dependent.id,
+                    dependency: dependency.id,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Clears the end time of operations running past their scheduled end
+    /// time.
+    ///
+    /// An operation that fails to be updated is skipped, so that it does not
+    /// hold back the others.
+    fn expire_schedules(&mut self) -> Result<()> {
+        let now = Utc::now();
+        let query = ListOperationsQuery {
+            statuses: vec![OperationState::InProgress, OperationState::Paused],
+            ..Default::default()
+        };
+        let overdue = self.operations(&query)?.into_iter().filter(|operation| {
+            operation.status.holds_locks() && operation.ends_at.is_some_and(|t| t <= now)
+        });
+        for mut operation in overdue {
+            let id = operation.id;
+            operation.ends_at = None;
+            if let Err(e) = self
+                .upsert_operation(SYSTEM_ACTOR, operation)
+                .and_then(|_| self.commit())
+            {
+                tracing::warn!("failed to expire the schedule of operation {}: {}", id, e);
+                self.rollback()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn history(&self, operation_id: u64) -> Result<Vec<OperationEvent>> {
+        self.operation(operation_id)?;
+        Ok(self
+            .storage
+            .events(Some(operation_id), &HistoryQuery::default())?)
+    }
+
+    fn events(&self, query: &HistoryQuery) -> Result<Vec<OperationEvent>> {
+        Ok(self.storage.events(None, query)?)
+    }
+
+    fn record_event(
+        &mut self,
+        actor: &str,
+        kind: EventKind,
+        changes: BTreeMap<String, FieldChange>,
+        operation: Operation,
+    ) -> Result<()> {
+        let event = OperationEvent {
+            id: self.storage.last_event_id()?.map_or(1, |id| id + 1),
+            timestamp: Utc::now(),
+            actor: actor.to_owned(),
+            kind,
+            changes,
+            operation,
+        };
+        self.storage.append_event(&event)?;
+        self.pending_events.push(event);
+        Ok(())
+    }
+
+    fn component(&self, name: &str) -> Result<Component> {
+        self.storage
+            .component(name)?
+            .ok_or_else(|| Error::NotFound {
+                entity: "component",
+                id: name.to_string(),
+            })
+    }
+
+    fn components(&self) -> Result<Vec<Component>> {
+        Ok(self.storage.components()?)
+    }
+
+    fn create_component(&mut self, mut component: Component) -> Result<Component> {
+        self.validate_component(&mut component)?;
+        if self.storage.component(&component.name)?.is_some() {
+            return Err(Error::AlreadyExists {
+                entity: "component",
+                id: component.name,
+            });
+        }
+        self.storage.put_component(&component)?;
+        Ok(component)
+    }
+
+    fn update_component(&mut self, actor: &str, mut component: Component) -> Result<Component> {
+        self.validate_component(&mut component)?;
+        let current = self.component(&component.name)?;
+        // Owners and approval requirements guard operations on the component,
+        // so only those responsible for it may change them.
+        if component.owners != current.owners || component.approval != current.approval {
+            self.ensure_owner(actor, &current)?;
+        }
+        self.storage.put_component(&component)?;
+        Ok(component)
+    }
+
+    /// Ensures that the user owns the component, directly or through a group,
+    /// or is an admin.
+    fn ensure_owner(&self, username: &str, component: &Component) -> Result<()> {
+        if self
+            .expand_principals(&component.owners)?
+            .contains(username)
+        {
+            return Ok(());
+        }
+        self.ensure_admin(username)
+    }
+
+    fn validate_component(&self, component: &mut Component) -> Result<()> {
+        component.name = component.name.trim().to_string();
+        if component.name.is_empty() {
+            return Err(Error::BlankItem("name"));
+        }
+
+        component.description = component.description.trim().to_string();
         if component.description.is_empty() {
             return Err(Error::BlankItem("description"));
         }
@@ -415,33 +1525,47 @@ impl AppState {
         component.owners.sort_unstable();
         component.owners.dedup();
         for owner in &component.owners {
-            self.user(owner)?;
+            self.principal(owner)?;
         }
 
-        match self.database.components.entry(component.name.clone()) {
-            std::collections::hash_map::Entry::Vacant(entry) => {
-                entry.insert(component.clone());
-                Ok(component)
-            }
-            std::collections::hash_map::Entry::Occupied(_) => Err(Error::AlreadyExists {
-                entity: "component",
-                id: component.name,
-            }),
-        }
+        self.validate_approval_requirement(&mut component.approval)
     }
 
-    fn tag(&self, name: &str) -> Result<&Tag> {
-        self.database.tags.get(name).ok_or(Error::NotFound {
+    fn tag(&self, name: &str) -> Result<Tag> {
+        self.storage.tag(name)?.ok_or_else(|| Error::NotFound {
             entity: "tag",
             id: name.to_string(),
         })
     }
 
-    fn tags(&self) -> impl Iterator<Item = &Tag> {
-        self.database.tags.values()
+    fn tags(&self) -> Result<Vec<Tag>> {
+        Ok(self.storage.tags()?)
     }
 
     fn create_tag(&mut self, mut tag: Tag) -> Result<Tag> {
+        self.validate_tag(&mut tag)?;
+        if self.storage.tag(&tag.name)?.is_some() {
+            return Err(Error::AlreadyExists {
+                entity: "tag",
+                id: tag.name,
+            });
+        }
+        self.storage.put_tag(&tag)?;
+        Ok(tag)
+    }
+
+    fn update_tag(&mut self, actor: &str, mut tag: Tag) -> Result<Tag> {
+        self.validate_tag(&mut tag)?;
+        // Tags have no owners, so only admins may change what operations
+        // tagged with them require.
+        if tag.approval != self.tag(&tag.name)?.approval {
+            self.ensure_admin(actor)?;
+        }
+        self.storage.put_tag(&tag)?;
+        Ok(tag)
+    }
+
+    fn validate_tag(&self, tag: &mut Tag) -> Result<()> {
         tag.name = tag.name.trim().to_string();
         if tag.name.is_empty() {
             return Err(Error::BlankItem("name"));
@@ -452,16 +1576,230 @@ impl AppState {
             return Err(Error::BlankItem("description"));
         }
 
-        match self.database.tags.entry(tag.name.clone()) {
-            std::collections::hash_map::Entry::Vacant(entry) => {
-                entry.insert(tag.clone());
-                Ok(tag)
+        self.validate_approval_requirement(&mut tag.approval)
+    }
+
+    fn validate_approval_requirement(&self, approval: &mut ApprovalRequirement) -> Result<()> {
+        for approver in &mut approval.requires_approval_by {
+            *approver = approver.trim().to_string();
+        }
+        approval.requires_approval_by.sort_unstable();
+        approval.requires_approval_by.dedup();
+        for approver in &approval.requires_approval_by {
+            self.principal(approver)?;
+        }
+        if !approval.requires_approval_by.is_empty() {
+            approval.required_approvals = approval.required_approvals.max(1);
+        }
+        Ok(())
+    }
+
+    fn group(&self, name: &str) -> Result<Group> {
+        self.storage.group(name)?.ok_or_else(|| Error::NotFound {
+            entity: "group",
+            id: name.to_string(),
+        })
+    }
+
+    fn groups(&self) -> Result<Vec<Group>> {
+        Ok(self.storage.groups()?)
+    }
+
+    fn create_group(&mut self, actor: &str, mut group: Group) -> Result<Group> {
+        if !self.admins.is_empty() {
+            self.ensure_admin(actor)?;
+        }
+        self.validate_group(&mut group)?;
+        if self.storage.user(&group.name)?.is_some() {
+            return Err(Error::AlreadyExists {
+                entity: "user",
+                id: group.name,
+            });
+        }
+        if self.storage.group(&group.name)?.is_some() {
+            return Err(Error::AlreadyExists {
+                entity: "group",
+                id: group.name,
+            });
+        }
+        self.storage.put_group(&group)?;
+        Ok(group)
+    }
+
+    fn update_group(&mut self, actor: &str, mut group: Group) -> Result<Group> {
+        self.ensure_group_manager(actor, &self.group(&group.name)?)?;
+        self.validate_group(&mut group)?;
+        self.storage.put_group(&group)?;
+        Ok(group)
+    }
+
+    fn validate_group(&self, group: &mut Group) -> Result<()> {
+        group.name = group.name.trim().to_string();
+        if group.name.is_empty() {
+            return Err(Error::BlankItem("name"));
+        }
+
+        group.description = group.description.trim().to_string();
+        if group.description.is_empty() {
+            return Err(Error::BlankItem("description"));
+        }
+
+        for member in &mut group.members {
+            *member = member.trim().to_string();
+        }
+        group.members.sort_unstable();
+        group.members.dedup();
+        for member in &group.members {
+            self.user(member)?;
+        }
+        Ok(())
+    }
+
+    fn delete_group(&mut self, actor: &str, name: &str) -> Result<()> {
+        self.ensure_group_manager(actor, &self.group(name)?)?;
+        if !self.storage.components_referring_to(name)?.is_empty()
+            || !self.storage.tags_referring_to(name)?.is_empty()
+        {
+            return Err(Error::InUse {
+                entity: "group",
+                id: name.to_owned(),
+            });
+        }
+        self.storage.delete_group(name)?;
+        Ok(())
+    }
+
+    /// Ensures that the name refers to an existing user or group.
+    fn principal(&self, name: &str) -> Result<()> {
+        if self.storage.group(name)?.is_some() {
+            return Ok(());
+        }
+        self.user(name).map(|_| ())
+    }
+
+    /// Returns the names of users referred to by the user and group names.
+    fn expand_principals(&self, names: &[String]) -> Result<HashSet<String>> {
+        let mut users = HashSet::new();
+        for name in names {
+            if let Some(group) = self.storage.group(name)? {
+                users.extend(group.members);
+            } else {
+                users.insert(name.clone());
             }
-            std::collections::hash_map::Entry::Occupied(_) => Err(Error::AlreadyExists {
-                entity: "tag",
-                id: tag.name,
-            }),
         }
+        Ok(users)
+    }
+
+    /// Ensures that the user may change the group. Groups grant ownership of
+    /// components and approval rights, so they are managed by admins, or by
+    /// their own members when no admins are configured.
+    fn ensure_group_manager(&self, username: &str, group: &Group) -> Result<()> {
+        if !self.admins.is_empty() {
+            return self.ensure_admin(username);
+        }
+        if group.members.iter().any(|member| member == username) {
+            Ok(())
+        } else {
+            Err(Error::Forbidden)
+        }
+    }
+
+    fn ensure_admin(&self, username: &str) -> Result<()> {
+        if self.expand_principals(&self.admins)?.contains(username) {
+            Ok(())
+        } else {
+            Err(Error::Forbidden)
+        }
+    }
+
+    fn approve_operation(&mut self, approver: &str, id: u64) -> Result<Operation> {
+        let operation = self.operation(id)?;
+        if operation.status != OperationState::Planned {
+            return Err(Error::ChangingApprovalsAfterStart);
+        }
+        // The user on whose behalf the operation was created is as
+        // responsible for it as its operators.
+        if operation
+            .operators
+            .iter()
+            .chain(&operation.on_behalf_of)
+            .any(|operator| operator == approver)
+        {
+            return Err(Error::SelfApproval);
+        }
+        if operation.approved_by.iter().any(|user| user == approver) {
+            return Err(Error::AlreadyExists {
+                entity: "approval by",
+                id: approver.to_owned(),
+            });
+        }
+        let mut approved_by = operation.approved_by;
+        approved_by.push(approver.to_owned());
+        approved_by.sort_unstable();
+        self.set_approvals(approver, EventKind::Approved, id, approved_by)
+    }
+
+    fn revoke_approval(&mut self, approver: &str, id: u64) -> Result<Operation> {
+        let operation = self.operation(id)?;
+        if operation.status != OperationState::Planned {
+            return Err(Error::ChangingApprovalsAfterStart);
+        }
+        if !operation.approved_by.iter().any(|user| user == approver) {
+            return Err(Error::NotFound {
+                entity: "approval by",
+                id: approver.to_owned(),
+            });
+        }
+        let mut approved_by = operation.approved_by;
+        approved_by.retain(|user| user != approver);
+        self.set_approvals(approver, EventKind::ApprovalRevoked, id, approved_by)
+    }
+
+    fn set_approvals(
+        &mut self,
+        actor: &str,
+        kind: EventKind,
+        id: u64,
+        approved_by: Vec<String>,
+    ) -> Result<Operation> {
+        let operation = self.operation(id)?;
+        let mut updated = operation.clone();
+        updated.approved_by = approved_by;
+        let changes = diff_operations(&operation, &updated)?;
+        self.storage.put_operation(&updated)?;
+        self.record_event(actor, kind, changes, updated.clone())?;
+        Ok(updated)
+    }
+
+    /// Checks that the operation has all the approvals required by its
+    /// components and tags.
+    fn check_approvals(&self, operation: &Operation) -> Result<()> {
+        let is_satisfied = |approval: &ApprovalRequirement| -> Result<bool> {
+            let approvers = self.expand_principals(&approval.requires_approval_by)?;
+            let approvals = operation
+                .approved_by
+                .iter()
+                .filter(|user| approvers.is_empty() || approvers.contains(user.as_str()))
+                .count();
+            Ok(approvals >= approval.required_approvals as usize)
+        };
+        for name in &operation.components {
+            if !is_satisfied(&self.component(name)?.approval)? {
+                return Err(Error::InsufficientApprovals {
+                    entity: "component",
+                    name: name.clone(),
+                });
+            }
+        }
+        for name in &operation.tags {
+            if !is_satisfied(&self.tag(name)?.approval)? {
+                return Err(Error::InsufficientApprovals {
+                    entity: "tag",
+                    name: name.clone(),
+                });
+            }
+        }
+        Ok(())
     }
 
     fn subscribe(
@@ -470,6 +1808,81 @@ impl AppState {
         operation: Option<u64>,
         component: Option<String>,
         tag: Option<String>,
+        filter: SubscriptionFilter,
+    ) -> Result<()> {
+        self.validate_subscription(operation, component.as_deref(), tag.as_deref())?;
+        let mut user = self.user(username)?;
+        add_subscription(&mut user.subscriptions, operation, component, tag, filter);
+        self.storage.put_user(&user)?;
+        Ok(())
+    }
+
+    fn unsubscribe(
+        &mut self,
+        username: &str,
+        operation: Option<u64>,
+        component: Option<&str>,
+        tag: Option<&str>,
+    ) -> Result<()> {
+        let mut user = self.user(username)?;
+        remove_subscription(&mut user.subscriptions, operation, component, tag)?;
+        self.storage.put_user(&user)?;
+        Ok(())
+    }
+
+    /// Drops subscriptions to operations that finished more than `retention`
+    /// ago.
+    fn prune_subscriptions(&mut self, retention: chrono::TimeDelta) -> Result<()> {
+        let threshold = Utc::now() - retention;
+        // Only operations finished since the last pruning are looked at, so
+        // that the whole history is not scanned every time.
+        let since = self.subscriptions_pruned_until.replace(threshold);
+        let query = HistoryQuery {
+            statuses: vec![
+                OperationState::Completed,
+                OperationState::Aborted,
+                OperationState::Canceled,
+            ],
+            since,
+            until: Some(threshold),
+            ..Default::default()
+        };
+        let expired: HashSet<u64> = self
+            .events(&query)?
+            .into_iter()
+            .map(|event| event.operation.id)
+            .collect();
+        if expired.is_empty() {
+            return Ok(());
+        }
+        let prune = |subscriptions: &mut SubscriptionSet| {
+            let len = subscriptions.operations.len();
+            subscriptions
+                .operations
+                .retain(|id, _| !expired.contains(id));
+            subscriptions.operations.len() != len
+        };
+        let subjects: Vec<_> = expired.iter().map(|id| Subject::Operation(*id)).collect();
+        for mut user in self.storage.subscribed_users(&subjects)? {
+            if prune(&mut user.subscriptions) {
+                self.storage.put_user(&user)?;
+            }
+        }
+        for mut channel in self.storage.subscribed_channels(&subjects)? {
+            if prune(&mut channel.subscriptions) {
+                self.storage.put_channel(&channel)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Ensures that exactly one existing entity is specified as a
+    /// subscription target.
+    fn validate_subscription(
+        &self,
+        operation: Option<u64>,
+        component: Option<&str>,
+        tag: Option<&str>,
     ) -> Result<()> {
         let num_specified = usize::from(operation.is_some())
             + usize::from(component.is_some())
@@ -480,48 +1893,419 @@ impl AppState {
         if let Some(operation) = operation {
             self.operation(operation)?;
         }
-        if let Some(component) = &component {
+        if let Some(component) = component {
             self.component(component)?;
         }
-        if let Some(tag) = &tag {
+        if let Some(tag) = tag {
             self.tag(tag)?;
         }
-        let subscriptions = &mut self.user_mut(username)?.subscriptions;
-        if let Some(operation) = operation {
-            subscriptions.operations.insert(operation);
+        Ok(())
+    }
+
+    fn channel(&self, id: &str) -> Result<Channel> {
+        self.storage.channel(id)?.ok_or_else(|| Error::NotFound {
+            entity: "channel",
+            id: id.to_owned(),
+        })
+    }
+
+    /// Returns the channels subscribed to the operation, or to any of its
+    /// components or tags.
+    fn subscribed_channels(&self, operation: &Operation) -> Result<Vec<Channel>> {
+        Ok(self.storage.subscribed_channels(&Subject::of(operation))?)
+    }
+
+    /// Registers the channel, or updates where and how messages are posted
+    /// to it if it is already registered.
+    fn setup_channel(
+        &mut self,
+        id: String,
+        name: String,
+        webhook_url: Uri,
+        flavor: ChatFlavor,
+    ) -> Result<Channel> {
+        if webhook_url
+            .scheme_str()
+            .is_none_or(|scheme| !matches!(scheme, "http" | "https"))
+        {
+            return Err(Error::InvalidUrlScheme);
         }
-        if let Some(component) = component {
-            subscriptions.components.insert(component);
+        // Anyone able to run slash commands sets the URL, so the server must
+        // not be made to post to arbitrary hosts, such as internal services.
+        if !self.is_allowed_chat_webhook(&webhook_url) {
+            return Err(Error::DisallowedWebhookHost(
+                self.chat_webhook_hosts.join(", "),
+            ));
         }
-        if let Some(tag) = tag {
-            subscriptions.tags.insert(tag);
+        let mut channel = self.storage.channel(&id)?.unwrap_or_else(|| Channel {
+            id,
+            name: String::new(),
+            webhook_url: webhook_url.clone(),
+            flavor,
+            subscriptions: SubscriptionSet::default(),
+        });
+        channel.name = name;
+        channel.webhook_url = webhook_url;
+        channel.flavor = flavor;
+        self.storage.put_channel(&channel)?;
+        Ok(channel)
+    }
+
+    fn is_allowed_chat_webhook(&self, url: &Uri) -> bool {
+        url.host().is_some_and(|host| {
+            self.chat_webhook_hosts
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(host))
+        })
+    }
+
+    fn subscribe_channel(
+        &mut self,
+        channel_id: &str,
+        operation: Option<u64>,
+        component: Option<String>,
+        tag: Option<String>,
+        filter: SubscriptionFilter,
+    ) -> Result<()> {
+        self.validate_subscription(operation, component.as_deref(), tag.as_deref())?;
+        let mut channel = self.channel(channel_id)?;
+        add_subscription(
+            &mut channel.subscriptions,
+            operation,
+            component,
+            tag,
+            filter,
+        );
+        self.storage.put_channel(&channel)?;
+        Ok(())
+    }
+
+    fn unsubscribe_channel(
+        &mut self,
+        channel_id: &str,
+        operation: Option<u64>,
+        component: Option<&str>,
+        tag: Option<&str>,
+    ) -> Result<()> {
+        let mut channel = self.channel(channel_id)?;
+        remove_subscription(&mut channel.subscriptions, operation, component, tag)?;
+        self.storage.put_channel(&channel)?;
+        Ok(())
+    }
+
+    fn webhook(&self, id: u64) -> Result<Webhook> {
+        self.storage.webhook(id)?.ok_or_else(|| Error::NotFound {
+            entity: "webhook",
+            id: id.to_string(),
+        })
+    }
+
+    fn webhooks(&self) -> Result<Vec<Webhook>> {
+        Ok(self.storage.webhooks()?)
+    }
+
+    fn create_webhook(&mut self, mut webhook: Webhook) -> Result<Webhook> {
+        if webhook
+            .url
+            .scheme_str()
+            .is_none_or(|scheme| !matches!(scheme, "http" | "https"))
+        {
+            return Err(Error::InvalidUrlScheme);
+        }
+        if webhook.secret.is_empty() {
+            return Err(Error::BlankItem("secret"));
         }
+
+        for component in &mut webhook.components {
+            *component = component.trim().to_string();
+            self.component(component)?;
+        }
+        webhook.components.sort_unstable();
+        webhook.components.dedup();
+
+        for tag in &mut webhook.tags {
+            *tag = tag.trim().to_string();
+            self.tag(tag)?;
+        }
+        webhook.tags.sort_unstable();
+        webhook.tags.dedup();
+
+        dedup(&mut webhook.statuses);
+
+        webhook.id = self.storage.next_webhook_id()?;
+        self.storage.set_next_webhook_id(webhook.id + 1)?;
+        self.storage.put_webhook(&webhook)?;
+        Ok(webhook)
+    }
+
+    fn delete_webhook(&mut self, id: u64) -> Result<()> {
+        self.webhook(id)?;
+        self.storage.delete_webhook(id)?;
+        Ok(())
+    }
+
+    fn failed_delivery(&self, webhook_id: u64, id: u64) -> Result<FailedDelivery> {
+        self.storage
+            .failed_delivery(id)?
+            .filter(|delivery| delivery.webhook_id == webhook_id)
+            .ok_or_else(|| Error::NotFound {
+                entity: "delivery",
+                id: id.to_string(),
+            })
+    }
+
+    fn failed_deliveries(&self, webhook_id: u64) -> Result<Vec<FailedDelivery>> {
+        Ok(self.storage.failed_deliveries(webhook_id)?)
+    }
+
+    /// Updates the failed delivery with the result of a redelivery.
+    ///
+    /// A successfully redelivered event is no longer considered failed.
+    fn settle_redelivery(&mut self, id: u64, error: Option<String>) -> Result<()> {
+        let Some(error) = error else {
+            self.storage.delete_failed_delivery(id)?;
+            return Ok(());
+        };
+        if let Some(mut delivery) = self.storage.failed_delivery(id)? {
+            delivery.attempts += 1;
+            delivery.error = error;
+            delivery.failed_at = Utc::now();
+            self.storage.put_failed_delivery(&delivery)?;
+        }
+        Ok(())
+    }
+
+    /// Records a delivery that failed after all the attempts.
+    ///
+    /// If the webhook has been deleted in the meantime, the failure is
+    /// discarded.
+    fn record_failed_delivery(
+        &mut self,
+        webhook_id: u64,
+        event: OperationEvent,
+        attempts: u32,
+        error: String,
+    ) -> Result<()> {
+        if self.storage.webhook(webhook_id)?.is_none() {
+            return Ok(());
+        }
+        let id = self
+            .storage
+            .last_failed_delivery_id()?
+            .map_or(1, |id| id + 1);
+        self.storage.put_failed_delivery(&FailedDelivery {
+            id,
+            webhook_id,
+            event,
+            attempts,
+            error,
+            failed_at: Utc::now(),
+        })?;
+        Ok(())
+    }
+
+    fn service_account(&self, name: &str) -> Result<ServiceAccount> {
+        self.storage
+            .service_account(name)?
+            .ok_or_else(|| Error::NotFound {
+                entity: "service account",
+                id: name.to_string(),
+            })
+    }
+
+    fn is_service_account(&self, name: &str) -> Result<bool> {
+        Ok(self.storage.service_account(name)?.is_some())
+    }
+
+    fn service_accounts(&self) -> Result<Vec<ServiceAccount>> {
+        Ok(self.storage.service_accounts()?)
+    }
+
+    /// Creates a service account along with the user holding its
+    /// subscriptions.
+    fn create_service_account(&mut self, mut account: ServiceAccount) -> Result<ServiceAccount> {
+        account.name = account.name.trim().to_string();
+        if account.name.is_empty() {
+            return Err(Error::BlankItem("name"));
+        }
+        if self.is_service_account(&account.name)? {
+            return Err(Error::AlreadyExists {
+                entity: "service account",
+                id: account.name,
+            });
+        }
+        self.create_user(account.name.clone())?;
+        self.storage.put_service_account(&account)?;
+        Ok(account)
+    }
+
+    fn api_tokens(&self, account: &str) -> Result<Vec<ApiToken>> {
+        self.service_account(account)?;
+        Ok(self.storage.api_tokens(account)?)
+    }
+
+    /// Issues an API token to the service account.
+    ///
+    /// Returns the token along with its information, as only the hash of its
+    /// secret is stored.
+    fn create_api_token(
+        &mut self,
+        actor: &str,
+        account: &str,
+        description: String,
+        mut scopes: Vec<Scope>,
+    ) -> Result<(ApiToken, String)> {
+        self.service_account(account)?;
+        if scopes.is_empty() {
+            return Err(Error::MissingItem("scope"));
+        }
+        dedup(&mut scopes);
+        let id = self.storage.next_api_token_id()?;
+        self.storage.set_next_api_token_id(id + 1)?;
+        let (token, secret_hash) = auth::generate_api_token(id);
+        let info = ApiToken {
+            id,
+            account: account.to_owned(),
+            description: description.trim().to_string(),
+            scopes,
+            created_by: actor.to_owned(),
+            created_at: Utc::now(),
+            secret_hash,
+        };
+        self.storage.put_api_token(&info)?;
+        Ok((info, token))
+    }
+
+    fn revoke_api_token(&mut self, account: &str, id: u64) -> Result<()> {
+        self.storage
+            .api_token(id)?
+            .filter(|token| token.account == account)
+            .ok_or_else(|| Error::NotFound {
+                entity: "token",
+                id: id.to_string(),
+            })?;
+        self.storage.delete_api_token(id)?;
         Ok(())
     }
+
+    /// Returns the claims of the service account that the API token
+    /// authenticates as.
+    fn verify_api_token(&self, token: &str) -> Result<Claims> {
+        let (id, secret_hash) = auth::parse_api_token(token).ok_or(Error::InvalidToken)?;
+        let token = self
+            .storage
+            .api_token(id)?
+            .filter(|token| {
+                token
+                    .secret_hash
+                    .as_bytes()
+                    .ct_eq(secret_hash.as_bytes())
+                    .into()
+            })
+            .ok_or(Error::InvalidToken)?;
+        Ok(Claims {
+            // API tokens do not expire, but can be revoked.
+            exp: u64::MAX,
+            username: token.account,
+            scopes: Some(token.scopes),
+        })
+    }
+}
+
+/// Adds a subscription, replacing the filter of an existing subscription to
+/// the same entity.
+fn add_subscription(
+    subscriptions: &mut SubscriptionSet,
+    operation: Option<u64>,
+    component: Option<String>,
+    tag: Option<String>,
+    mut filter: SubscriptionFilter,
+) {
+    dedup(&mut filter.statuses);
+    dedup(&mut filter.kinds);
+    if let Some(operation) = operation {
+        subscriptions.operations.insert(operation, filter);
+    } else if let Some(component) = component {
+        subscriptions.components.insert(component, filter);
+    } else if let Some(tag) = tag {
+        subscriptions.tags.insert(tag, filter);
+    }
 }
 
-#[derive(Serialize, Deserialize)]
-struct Database {
-    next_id: u64,
-    users: HashMap<String, User>,
-    operations: BTreeMap<u64, Operation>,
-    components: HashMap<String, Component>,
-    tags: HashMap<String, Tag>,
+fn remove_subscription(
+    subscriptions: &mut SubscriptionSet,
+    operation: Option<u64>,
+    component: Option<&str>,
+    tag: Option<&str>,
+) -> Result<()> {
+    let (removed, id) = match (operation, component, tag) {
+        (Some(operation), None, None) => (
+            subscriptions.operations.remove(&operation),
+            format!("operation {operation}"),
+        ),
+        (None, Some(component), None) => (
+            subscriptions.components.remove(component),
+            format!("component {component}"),
+        ),
+        (None, None, Some(tag)) => (subscriptions.tags.remove(tag), format!("tag {tag}")),
+        _ => return Err(Error::SubscribingMultipleEntities),
+    };
+    removed.map(|_| ()).ok_or(Error::NotFound {
+        entity: "subscription to",
+        id,
+    })
 }
 
-impl Default for Database {
-    fn default() -> Self {
-        Self {
-            next_id: 1234,
-            users: HashMap::new(),
-            operations: BTreeMap::new(),
-            components: HashMap::new(),
-            tags: HashMap::new(),
+/// Removes duplicates while keeping the order.
+fn dedup<T: PartialEq>(items: &mut Vec<T>) {
+    let mut i = 0;
+    while i < items.len() {
+        if items[..i].contains(&items[i]) {
+            items.remove(i);
+        } else {
+            i += 1;
         }
     }
 }
 
-const JWT_SECRET: &[u8] = b"secret"; // hardcoded secret for PoC
+/// Returns whether the edit changes what approvers of the operation approved.
+fn changes_approved_content(old: &Operation, new: &Operation) -> bool {
+    old.url != new.url
+        || old.components != new.components
+        || old.locks != new.locks
+        || old.tags != new.tags
+        || old.depends_on != new.depends_on
+        || old.starts_at != new.starts_at
+        || old.ends_at != new.ends_at
+        || old.operators != new.operators
+}
+
+/// Returns the fields that differ between two versions of an operation.
+fn diff_operations(old: &Operation, new: &Operation) -> Result<BTreeMap<String, FieldChange>> {
+    let to_map = |operation| match serde_json::to_value(operation) {
+        Ok(serde_json::Value::Object(map)) => Ok(map),
+        _ => Err(Error::Internal),
+    };
+    let mut old = to_map(old)?;
+    let new = to_map(new)?;
+    let mut changes = BTreeMap::new();
+    for (field, new) in new {
+        let old = old.remove(&field).unwrap_or_default();
+        if old != new {
+            changes.insert(field, FieldChange { old, new });
+        }
+    }
+    for (field, old) in old {
+        changes.insert(
+            field,
+            FieldChange {
+                old,
+                new: serde_json::Value::Null,
+            },
+        );
+    }
+    Ok(changes)
+}
 
 #[async_trait]
 impl FromRequestParts<SharedState> for Claims {
@@ -534,14 +2318,541 @@ impl FromRequestParts<SharedState> for Claims {
                 Err(e) if e.is_missing() => return Err(Error::MissingToken),
                 Err(_) => return Err(Error::InvalidToken),
             };
-        let token_data = jsonwebtoken::decode(
-            bearer.token(),
-            &jsonwebtoken::DecodingKey::from_secret(JWT_SECRET),
-            &jsonwebtoken::Validation::default(),
-        )
-        .map_err(|_| Error::InvalidToken)?;
-        let claims: Self = token_data.claims;
+        let token = bearer.token();
+        let auth = state.read().unwrap().auth.clone();
+        let claims = if token.starts_with(auth::API_TOKEN_PREFIX) {
+            state.read().unwrap().verify_api_token(token)?
+        } else if let Some(oidc) = auth.oidc.as_ref().filter(|_| auth::Oidc::accepts(token)) {
+            let identity = oidc.verify(token).await?;
+            // Service accounts only authenticate with API tokens, which are
+            // limited to their scopes.
+            if state
+                .read()
+                .unwrap()
+                .is_service_account(&identity.username)?
+            {
+                return Err(Error::ServiceAccount(identity.username));
+            }
+            if !state.read().unwrap().is_provisioned(&identity)? {
+                let mut state = state.write().unwrap();
+                state.provision_user(&identity)?;
+                state.commit()?;
+            }
+            Self {
+                exp: identity.exp,
+                username: identity.username,
+                scopes: None,
+            }
+        } else {
+            auth.verify_access_token(token)?
+        };
+        if let Some(scopes) = &claims.scopes {
+            let route = parts
+                .extensions
+                .get::<MatchedPath>()
+                .map_or("", MatchedPath::as_str);
+            if !auth::required_scope(&parts.method, route).is_some_and(|s| scopes.contains(&s)) {
+                return Err(Error::MissingScope);
+            }
+        }
         state.read().unwrap().user(&claims.username)?;
         Ok(claims)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operation(id: u64, components: &[&str], locks: &[&str]) -> Operation {
+        Operation {
+            id,
+            title: format!("operation {id}"),
+            purpose: "testing".to_owned(),
+            url: "https://example.com/".parse().unwrap(),
+            components: components.iter().map(ToString::to_string).collect(),
+            locks: locks.iter().map(ToString::to_string).collect(),
+            tags: Vec::new(),
+            depends_on: Vec::new(),
+            starts_at: None,
+            ends_at: None,
+            operators: vec!["alice".to_owned()],
+            on_behalf_of: None,
+            approved_by: Vec::new(),
+            status: OperationState::InProgress,
+            annotations: HashMap::new(),
+        }
+    }
+
+    /// Returns a state with the user alice and the components foo and bar,
+    /// stored in the returned directory.
+    fn state() -> (tempfile::TempDir, AppState) {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = JsonStorage::open(&dir.path().join("state.json")).unwrap();
+        let mut state = AppState::new(
+            Box::new(storage),
+            testing::auth(None),
+            Vec::new(),
+            None,
+            Vec::new(),
+        )
+        .unwrap();
+        state.create_user("alice".to_owned()).unwrap();
+        for name in ["foo", "bar"] {
+            state
+                .storage
+                .put_component(&Component {
+                    name: name.to_owned(),
+                    description: name.to_owned(),
+                    owners: vec!["alice".to_owned()],
+                    approval: ApprovalRequirement::default(),
+                })
+                .unwrap();
+        }
+        state.commit().unwrap();
+        (dir, state)
+    }
+
+    #[test]
+    fn lock_conflicts() {
+        // (held lock, requested lock, whether the request succeeds)
+        let cases = [
+            (ComponentLock::Shared, ComponentLock::Shared, true),
+            (ComponentLock::Shared, ComponentLock::Exclusive, false),
+            (ComponentLock::Exclusive, ComponentLock::Shared, false),
+            (ComponentLock::Exclusive, ComponentLock::Exclusive, false),
+        ];
+        let with_lock = |id, lock| match lock {
+            ComponentLock::Shared => operation(id, &["foo"], &[]),
+            ComponentLock::Exclusive => operation(id, &["foo"], &["foo"]),
+        };
+        for (held, requested, succeeds) in cases {
+            let mut locks = LockTable::default();
+            locks.lock(&with_lock(1, held)).unwrap();
+            let result = locks.lock(&with_lock(2, requested));
+            if succeeds {
+                assert!(result.is_ok(), "{held:?} then {requested:?}");
+            } else {
+                assert!(
+                    matches!(
+                        result,
+                        Err(Error::LockFailed { ref component, holder: 1 }) if component == "foo"
+                    ),
+                    "{held:?} then {requested:?}: {result:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn locks_on_other_components_do_not_conflict() {
+        let mut locks = LockTable::default();
+        locks.lock(&operation(1, &["foo"], &["foo"])).unwrap();
+        locks.lock(&operation(2, &["bar"], &["bar"])).unwrap();
+    }
+
+    #[test]
+    fn failed_lock_acquires_nothing() {
+        let mut locks = LockTable::default();
+        locks.lock(&operation(1, &["bar"], &["bar"])).unwrap();
+        assert!(locks
+            .lock(&operation(2, &["foo", "bar"], &["foo"]))
+            .is_err());
+
+        // foo must not have been locked by the failed attempt.
+        locks.lock(&operation(3, &["foo"], &["foo"])).unwrap();
+    }
+
+    #[test]
+    fn relocking_releases_locks_no_longer_needed() {
+        let mut locks = LockTable::default();
+        locks
+            .lock(&operation(1, &["foo", "bar"], &["foo"]))
+            .unwrap();
+        locks.lock(&operation(1, &["bar"], &[])).unwrap();
+        locks.lock(&operation(2, &["foo"], &["foo"])).unwrap();
+        assert!(locks.lock(&operation(3, &["bar"], &["bar"])).is_err());
+    }
+
+    #[tokio::test]
+    async fn dropping_the_write_guard_rolls_back_uncommitted_changes() {
+        let server = testing::TestServer::new();
+        let alice = server.login("alice");
+        server.create_component(&alice, "foo", &["alice"]).await;
+        let mut receiver = server.state.read().unwrap().operation_tx.subscribe();
+
+        let id = {
+            let mut state = server.state.write().unwrap();
+            let id = state.next_id().unwrap();
+            let mut planned = operation(id, &["foo"], &["foo"]);
+            planned.status = OperationState::Planned;
+            state.upsert_operation("alice", planned).unwrap();
+            state
+                .locks
+                .lock(&operation(id, &["foo"], &["foo"]))
+                .unwrap();
+            id
+        };
+
+        {
+            let state = server.state.read().unwrap();
+            assert!(state.storage.operation(id).unwrap().is_none());
+            assert_eq!(state.storage.next_id().unwrap(), id);
+            assert!(state
+                .storage
+                .events(Some(id), &HistoryQuery::default())
+                .unwrap()
+                .is_empty());
+        }
+        assert!(receiver.try_recv().is_err());
+        let mut state = server.state.write().unwrap();
+        state
+            .locks
+            .lock(&operation(id + 1, &["foo"], &["foo"]))
+            .unwrap();
+    }
+
+    #[test]
+    fn convert_to_sqlite_copies_the_state_without_modifying_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_file = dir.path().join("state.json");
+        {
+            let mut storage = JsonStorage::open(&state_file).unwrap();
+            let mut subscriptions = SubscriptionSet::default();
+            subscriptions
+                .components
+                .insert("foo".to_owned(), SubscriptionFilter::default());
+            storage
+                .put_user(&User {
+                    name: "alice".to_owned(),
+                    email: None,
+                    subscriptions,
+                })
+                .unwrap();
+            storage
+                .put_group(&Group {
+                    name: "ops".to_owned(),
+                    description: "Operators".to_owned(),
+                    members: vec!["alice".to_owned()],
+                })
+                .unwrap();
+            storage
+                .put_component(&Component {
+                    name: "foo".to_owned(),
+                    description: "Foo".to_owned(),
+                    owners: vec!["ops".to_owned()],
+                    approval: ApprovalRequirement::default(),
+                })
+                .unwrap();
+            // Leave changes both in the snapshot and in the log.
+            storage.compact().unwrap();
+            let mut dependency = operation(1, &["foo"], &["foo"]);
+            dependency.status = OperationState::Planned;
+            dependency.starts_at = Some("2024-01-01T00:00:00.123456Z".parse().unwrap());
+            dependency.ends_at = Some("2024-01-02T00:00:00Z".parse().unwrap());
+            let mut dependent = operation(2, &["foo"], &[]);
+            dependent.depends_on = vec![1];
+            storage.put_operation(&dependency).unwrap();
+            storage.put_operation(&dependent).unwrap();
+            storage.commit().unwrap();
+        }
+        let wal = dir.path().join("state.json.wal");
+        let before = (
+            std::fs::read(&state_file).unwrap(),
+            std::fs::read(&wal).unwrap(),
+        );
+
+        let database = dir.path().join("state.db");
+        convert_to_sqlite(&state_file, &database).unwrap();
+
+        let after = (
+            std::fs::read(&state_file).unwrap(),
+            std::fs::read(&wal).unwrap(),
+        );
+        assert!(before == after, "the state file was modified");
+        let sqlite = SqliteStorage::open(&database).unwrap();
+        assert!(!sqlite.is_empty().unwrap());
+        let names = |users: Vec<User>| users.into_iter().map(|user| user.name).collect::<Vec<_>>();
+        assert_eq!(
+            names(
+                sqlite
+                    .subscribed_users(&[Subject::Component("foo")])
+                    .unwrap()
+            ),
+            ["alice"]
+        );
+        assert!(sqlite
+            .subscribed_users(&[Subject::Operation(1)])
+            .unwrap()
+            .is_empty());
+        assert_eq!(sqlite.groups_of("alice").unwrap()[0].name, "ops");
+        assert_eq!(
+            sqlite.components_referring_to("ops").unwrap()[0].name,
+            "foo"
+        );
+        assert_eq!(sqlite.dependents(1).unwrap()[0].id, 2);
+        let ids = |operations: Vec<Operation>| {
+            operations
+                .into_iter()
+                .map(|operation| operation.id)
+                .collect::<Vec<_>>()
+        };
+        let at = |t: &str| t.parse::<chrono::DateTime<Utc>>().unwrap();
+        assert_eq!(
+            ids(sqlite
+                .scheduled_operations(at("2024-01-01T00:00:00.123457Z"), None)
+                .unwrap()),
+            [1]
+        );
+        assert!(sqlite
+            .scheduled_operations(at("2024-01-02T00:00:00Z"), None)
+            .unwrap()
+            .is_empty());
+        assert!(sqlite
+            .scheduled_operations(
+                at("2023-12-31T00:00:00Z"),
+                Some(at("2024-01-01T00:00:00.123456Z"))
+            )
+            .unwrap()
+            .is_empty());
+
+        // A database that already contains data is not overwritten.
+        assert!(convert_to_sqlite(&state_file, &database).is_err());
+    }
+
+    #[tokio::test]
+    async fn identity_provider_cannot_authenticate_service_accounts() {
+        let server = testing::TestServer::with_identity_provider(&["alice"]).await;
+        let alice = server.login("alice");
+        let (status, body) = server
+            .post(
+                "/service-accounts",
+                &alice,
+                serde_json::json!({ "name": "ci" }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+
+        let bob = testing::TestServer::id_token("bob");
+        let (status, body) = server.get("/operations", &bob).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        let ci = testing::TestServer::id_token("ci");
+        let (status, body) = server.get("/operations", &ci).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+        let (status, body) = server
+            .post(
+                "/service-accounts",
+                &ci,
+                serde_json::json!({ "name": "ci2" }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+    }
+
+    #[tokio::test]
+    async fn lagging_receivers_recover_only_events_recorded_after_their_creation() {
+        let server = testing::TestServer::new();
+        let alice = server.login("alice");
+        server.create_component(&alice, "foo", &["alice"]).await;
+        server.create_operation(&alice, &["foo"]).await;
+        let mut receiver = EventReceiver::new(server.state.clone()).unwrap();
+        let id = server.create_operation(&alice, &["foo"]).await;
+
+        // Fill the channel so that the receiver misses the event.
+        {
+            let state = server.state.read().unwrap();
+            let event = state.events(&HistoryQuery::default()).unwrap()[0].clone();
+            for _ in 0..1024 {
+                state.operation_tx.send(event.clone()).unwrap();
+            }
+        }
+
+        let events = receiver.recv().await.unwrap();
+        let operations: Vec<_> = events.iter().map(|event| event.operation.id).collect();
+        assert_eq!(operations, [id]);
+    }
+
+    #[tokio::test]
+    async fn shutting_down_closes_watchers_before_returning() {
+        use futures_util::StreamExt;
+        use tokio_tungstenite::tungstenite::{protocol::frame::coding::CloseCode, Message};
+
+        let server = testing::TestServer::new();
+        let alice = server.login("alice");
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel();
+        let serving = tokio::spawn(serve(listener, server.state.clone(), async {
+            stopped.await.unwrap();
+        }));
+        let mut socket = testing::watch(addr, &alice).await;
+        let mut receiver = EventReceiver::new(server.state.clone()).unwrap();
+        server.wait_for_receivers(2).await;
+
+        stop.send(()).unwrap();
+        let timeout = tokio::time::Duration::from_secs(5);
+        let msg = tokio::time::timeout(timeout, socket.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let Message::Close(Some(frame)) = msg else {
+            panic!("expected a close frame, got {msg:?}");
+        };
+        assert_eq!(frame.code, CloseCode::Away);
+        assert!(receiver.recv().await.is_none());
+
+        // The server waits for every receiver to be dropped.
+        tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+        assert!(!serving.is_finished());
+        drop(receiver);
+        tokio::time::timeout(timeout, serving)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn resumed_receivers_replay_into_live_events_without_gaps_or_duplicates() {
+        let server = testing::TestServer::new();
+        let alice = server.login("alice");
+        server.create_component(&alice, "foo", &["alice"]).await;
+        for _ in 0..3 {
+            server.create_operation(&alice, &["foo"]).await;
+        }
+        let (first, last) = {
+            let events = server
+                .state
+                .read()
+                .unwrap()
+                .events(&HistoryQuery::default())
+                .unwrap();
+            (events[0].id, events.last().unwrap().id)
+        };
+        let (mut receiver, backlog) = EventReceiver::resume(server.state.clone(), first).unwrap();
+        for _ in 0..3 {
+            server.create_operation(&alice, &["foo"]).await;
+        }
+
+        // Make the receiver lag, so that live events overlapping the backlog
+        // are recovered from the history.
+        {
+            let state = server.state.read().unwrap();
+            let event = state.events(&HistoryQuery::default()).unwrap()[0].clone();
+            for _ in 0..1024 {
+                state.operation_tx.send(event.clone()).unwrap();
+            }
+        }
+        server.create_operation(&alice, &["foo"]).await;
+
+        let mut ids: Vec<_> = backlog.iter().map(|event| event.id).collect();
+        assert_eq!(ids.last(), Some(&last));
+        while ids.len() < 6 {
+            let events = receiver.recv().await.unwrap();
+            ids.extend(events.iter().map(|event| event.id));
+        }
+        let expected: Vec<_> = server
+            .state
+            .read()
+            .unwrap()
+            .events(&HistoryQuery::default())
+            .unwrap()
+            .into_iter()
+            .map(|event| event.id)
+            .filter(|&id| id > first)
+            .collect();
+        assert_eq!(ids, expected);
+    }
+
+    #[test]
+    fn unlock_releases_all_locks() {
+        let mut locks = LockTable::default();
+        locks
+            .lock(&operation(1, &["foo", "bar"], &["foo"]))
+            .unwrap();
+        locks.unlock(1);
+        locks
+            .lock(&operation(2, &["foo", "bar"], &["foo", "bar"]))
+            .unwrap();
+    }
+
+    #[test]
+    fn transitions_acquire_and_release_locks() {
+        use OperationState::{Aborted, Canceled, Completed, InProgress, Paused};
+
+        // (statuses the operation goes through after being planned, whether
+        // another operation can then lock its components)
+        let cases: [(&[OperationState], bool); 7] = [
+            (&[], true),
+            (&[InProgress], false),
+            (&[InProgress, Paused], false),
+            (&[InProgress, Paused, InProgress], false),
+            (&[InProgress, Completed], true),
+            (&[InProgress, Aborted], true),
+            (&[Canceled], true),
+        ];
+        for (statuses, can_lock) in cases {
+            let (_dir, mut state) = state();
+            let mut operation = operation(1, &["foo", "bar"], &["foo"]);
+            operation.status = OperationState::Planned;
+            state.upsert_operation("alice", operation.clone()).unwrap();
+            for &status in statuses {
+                operation.status = status;
+                state.upsert_operation("alice", operation.clone()).unwrap();
+            }
+
+            for other in [
+                self::operation(2, &["foo"], &[]),
+                self::operation(3, &["bar"], &["bar"]),
+            ] {
+                assert_eq!(
+                    state.locks.lock(&other).is_ok(),
+                    can_lock,
+                    "{statuses:?} then locking {:?}",
+                    other.components
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn starting_a_blocked_operation_acquires_no_locks() {
+        let (_dir, mut state) = state();
+        let mut running = operation(1, &["bar"], &["bar"]);
+        running.status = OperationState::Planned;
+        state.upsert_operation("alice", running.clone()).unwrap();
+        running.status = OperationState::InProgress;
+        state.upsert_operation("alice", running).unwrap();
+
+        let mut blocked = operation(2, &["foo", "bar"], &["foo"]);
+        blocked.status = OperationState::Planned;
+        state.upsert_operation("alice", blocked.clone()).unwrap();
+        blocked.status = OperationState::InProgress;
+        assert!(matches!(
+            state.upsert_operation("alice", blocked),
+            Err(Error::LockFailed { ref component, holder: 1 }) if component == "bar"
+        ));
+        assert_eq!(state.operation(2).unwrap().status, OperationState::Planned);
+        state.locks.lock(&operation(3, &["foo"], &["foo"])).unwrap();
+    }
+
+    #[test]
+    fn expire_schedules_skips_operations_failing_to_update() {
+        let (_dir, mut state) = state();
+        let ended = Utc::now() - chrono::TimeDelta::hours(1);
+        for (id, title) in [(1, ""), (2, "valid")] {
+            let mut operation = operation(id, &["foo"], &[]);
+            operation.title = title.to_owned();
+            operation.starts_at = Some(ended - chrono::TimeDelta::hours(1));
+            operation.ends_at = Some(ended);
+            state.storage.put_operation(&operation).unwrap();
+        }
+        state.commit().unwrap();
+
+        state.expire_schedules().unwrap();
+        assert_eq!(state.operation(1).unwrap().ends_at, Some(ended));
+        assert_eq!(state.operation(2).unwrap().ends_at, None);
+    }
+}
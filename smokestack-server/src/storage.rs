@@ -0,0 +1,223 @@
+mod json;
+mod sqlite;
+
+pub use json::JsonStorage;
+pub use sqlite::SqliteStorage;
+
+use chrono::{DateTime, Utc};
+use smokestack::{
+    api::{HistoryQuery, ListOperationsQuery},
+    model::{
+        ApiToken, Channel, Component, FailedDelivery, Group, Operation, OperationEvent,
+        RefreshToken, ServiceAccount, SubscriptionSet, Tag, User, Webhook,
+    },
+};
+
+/// ID given to the first operation
+const INITIAL_NEXT_ID: u64 = 1234;
+
+/// Where the data of the server is stored.
+///
+/// Changes are visible to subsequent reads as soon as they are made, but they
+/// are only guaranteed to survive a crash after `commit` returns.
+pub trait Storage: Send + Sync {
+    /// Returns the ID to be given to the next operation.
+    fn next_id(&self) -> anyhow::Result<u64>;
+    fn set_next_id(&mut self, next_id: u64) -> anyhow::Result<()>;
+
+    fn user(&self, name: &str) -> anyhow::Result<Option<User>>;
+    fn users(&self) -> anyhow::Result<Vec<User>>;
+
+    /// Returns the users subscribed to any of the subjects.
+    fn subscribed_users(&self, subjects: &[Subject]) -> anyhow::Result<Vec<User>>;
+    fn put_user(&mut self, user: &User) -> anyhow::Result<()>;
+
+    fn operation(&self, id: u64) -> anyhow::Result<Option<Operation>>;
+
+    /// Returns the operations matching the query in the order of their IDs.
+    fn operations(&self, query: &ListOperationsQuery) -> anyhow::Result<Vec<Operation>>;
+
+    /// Returns the unfinished operations scheduled to run at some point
+    /// between `starts_at` and `ends_at` (or indefinitely after `starts_at`),
+    /// in the order of their IDs.
+    fn scheduled_operations(
+        &self,
+        starts_at: DateTime<Utc>,
+        ends_at: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Vec<Operation>>;
+
+    /// Returns the operations depending on the operation, in the order of
+    /// their IDs.
+    fn dependents(&self, id: u64) -> anyhow::Result<Vec<Operation>>;
+    fn put_operation(&mut self, operation: &Operation) -> anyhow::Result<()>;
+
+    fn component(&self, name: &str) -> anyhow::Result<Option<Component>>;
+    fn components(&self) -> anyhow::Result<Vec<Component>>;
+
+    /// Returns the components owned by the user or group, or requiring its
+    /// approval.
+    fn components_referring_to(&self, principal: &str) -> anyhow::Result<Vec<Component>>;
+    fn put_component(&mut self, component: &Component) -> anyhow::Result<()>;
+
+    fn tag(&self, name: &str) -> anyhow::Result<Option<Tag>>;
+    fn tags(&self) -> anyhow::Result<Vec<Tag>>;
+
+    /// Returns the tags requiring the approval of the user or group.
+    fn tags_referring_to(&self, principal: &str) -> anyhow::Result<Vec<Tag>>;
+    fn put_tag(&mut self, tag: &Tag) -> anyhow::Result<()>;
+
+    fn group(&self, name: &str) -> anyhow::Result<Option<Group>>;
+    fn groups(&self) -> anyhow::Result<Vec<Group>>;
+
+    /// Returns the groups the user is a member of.
+    fn groups_of(&self, member: &str) -> anyhow::Result<Vec<Group>>;
+
+    fn put_group(&mut self, group: &Group) -> anyhow::Result<()>;
+    fn delete_group(&mut self, name: &str) -> anyhow::Result<()>;
+
+    /// Returns the events matching the query in the order they happened,
+    /// up to `query.limit` events.
+    ///
+    /// If `operation` is specified, only the events of the operation are
+    /// returned.
+    fn events(
+        &self,
+        operation: Option<u64>,
+        query: &HistoryQuery,
+    ) -> anyhow::Result<Vec<OperationEvent>>;
+    fn last_event_id(&self) -> anyhow::Result<Option<u64>>;
+
+    /// Appends the event to the history. Events are never modified once
+    /// recorded.
+    fn append_event(&mut self, event: &OperationEvent) -> anyhow::Result<()>;
+
+    /// Returns the ID to be given to the next webhook. IDs of deleted
+    /// webhooks are not reused.
+    fn next_webhook_id(&self) -> anyhow::Result<u64>;
+    fn set_next_webhook_id(&mut self, next_id: u64) -> anyhow::Result<()>;
+
+    fn webhook(&self, id: u64) -> anyhow::Result<Option<Webhook>>;
+    fn webhooks(&self) -> anyhow::Result<Vec<Webhook>>;
+    fn put_webhook(&mut self, webhook: &Webhook) -> anyhow::Result<()>;
+
+    /// Deletes the webhook along with its failed deliveries.
+    fn delete_webhook(&mut self, id: u64) -> anyhow::Result<()>;
+
+    fn failed_delivery(&self, id: u64) -> anyhow::Result<Option<FailedDelivery>>;
+    fn failed_deliveries(&self, webhook_id: u64) -> anyhow::Result<Vec<FailedDelivery>>;
+    fn last_failed_delivery_id(&self) -> anyhow::Result<Option<u64>>;
+    fn put_failed_delivery(&mut self, delivery: &FailedDelivery) -> anyhow::Result<()>;
+    fn delete_failed_delivery(&mut self, id: u64) -> anyhow::Result<()>;
+
+    fn channel(&self, id: &str) -> anyhow::Result<Option<Channel>>;
+    fn channels(&self) -> anyhow::Result<Vec<Channel>>;
+
+    /// Returns the channels subscribed to any of the subjects.
+    fn subscribed_channels(&self, subjects: &[Subject]) -> anyhow::Result<Vec<Channel>>;
+    fn put_channel(&mut self, channel: &Channel) -> anyhow::Result<()>;
+
+    /// Returns the refresh token whose hash is `hash`.
+    fn refresh_token(&self, hash: &str) -> anyhow::Result<Option<RefreshToken>>;
+
+    /// Returns the refresh tokens issued to the user.
+    fn refresh_tokens(&self, username: &str) -> anyhow::Result<Vec<RefreshToken>>;
+    fn put_refresh_token(&mut self, token: &RefreshToken) -> anyhow::Result<()>;
+    fn delete_refresh_token(&mut self, hash: &str) -> anyhow::Result<()>;
+
+    fn service_account(&self, name: &str) -> anyhow::Result<Option<ServiceAccount>>;
+    fn service_accounts(&self) -> anyhow::Result<Vec<ServiceAccount>>;
+    fn put_service_account(&mut self, account: &ServiceAccount) -> anyhow::Result<()>;
+
+    /// Returns the ID to be given to the next API token. IDs of revoked
+    /// tokens are not reused.
+    fn next_api_token_id(&self) -> anyhow::Result<u64>;
+    fn set_next_api_token_id(&mut self, next_id: u64) -> anyhow::Result<()>;
+
+    fn api_token(&self, id: u64) -> anyhow::Result<Option<ApiToken>>;
+
+    /// Returns the tokens of the service account in the order of their IDs.
+    fn api_tokens(&self, account: &str) -> anyhow::Result<Vec<ApiToken>>;
+    fn put_api_token(&mut self, token: &ApiToken) -> anyhow::Result<()>;
+    fn delete_api_token(&mut self, id: u64) -> anyhow::Result<()>;
+
+    /// Makes the changes made so far durable.
+    fn commit(&mut self) -> anyhow::Result<()>;
+
+    /// Discards the changes made since the last commit.
+    fn rollback(&mut self) -> anyhow::Result<()>;
+
+    /// Reclaims space taken by past changes. Called periodically.
+    fn compact(&mut self) -> anyhow::Result<()>;
+}
+
+/// Something users and channels subscribe to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subject<'a> {
+    Operation(u64),
+    Component(&'a str),
+    Tag(&'a str),
+}
+
+impl<'a> Subject<'a> {
+    /// Returns the subjects whose subscribers may be interested in the events
+    /// of the operation.
+    pub fn of(operation: &'a Operation) -> Vec<Self> {
+        let mut subjects = vec![Self::Operation(operation.id)];
+        subjects.extend(operation.components.iter().map(|c| Self::Component(c)));
+        subjects.extend(operation.tags.iter().map(|t| Self::Tag(t)));
+        subjects
+    }
+
+    /// Returns whether the subscriptions include the subject.
+    pub fn is_in(self, subscriptions: &SubscriptionSet) -> bool {
+        match self {
+            Self::Operation(id) => subscriptions.operations.contains_key(&id),
+            Self::Component(name) => subscriptions.components.contains_key(name),
+            Self::Tag(name) => subscriptions.tags.contains_key(name),
+        }
+    }
+}
+
+/// Copies all the data from one storage to another.
+pub fn copy(from: &dyn Storage, to: &mut dyn Storage) -> anyhow::Result<()> {
+    to.set_next_id(from.next_id()?)?;
+    for user in from.users()? {
+        to.put_user(&user)?;
+        for token in from.refresh_tokens(&user.name)? {
+            to.put_refresh_token(&token)?;
+        }
+    }
+    for operation in from.operations(&ListOperationsQuery::default())? {
+        to.put_operation(&operation)?;
+    }
+    for component in from.components()? {
+        to.put_component(&component)?;
+    }
+    for tag in from.tags()? {
+        to.put_tag(&tag)?;
+    }
+    for group in from.groups()? {
+        to.put_group(&group)?;
+    }
+    for event in from.events(None, &HistoryQuery::default())? {
+        to.append_event(&event)?;
+    }
+    to.set_next_webhook_id(from.next_webhook_id()?)?;
+    for webhook in from.webhooks()? {
+        to.put_webhook(&webhook)?;
+        for delivery in from.failed_deliveries(webhook.id)? {
+            to.put_failed_delivery(&delivery)?;
+        }
+    }
+    for channel in from.channels()? {
+        to.put_channel(&channel)?;
+    }
+    to.set_next_api_token_id(from.next_api_token_id()?)?;
+    for account in from.service_accounts()? {
+        to.put_service_account(&account)?;
+        for token in from.api_tokens(&account.name)? {
+            to.put_api_token(&token)?;
+        }
+    }
+    to.commit()
+}
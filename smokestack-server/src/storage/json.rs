@@ -0,0 +1,1264 @@
+use super::{Storage, Subject, INITIAL_NEXT_ID};
+use anyhow::Context as _;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use smokestack::{
+    api::{HistoryQuery, ListOperationsQuery},
+    model::{
+        ApiToken, Channel, Component, FailedDelivery, Group, Operation, OperationEvent,
+        RefreshToken, ServiceAccount, Tag, User, Webhook,
+    },
+};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Storage keeping all the data in memory.
+///
+/// The data is loaded from a JSON snapshot at startup. Changes are appended
+/// to a write-ahead log, which is folded into the snapshot by `compact`.
+pub struct JsonStorage {
+    database: Database,
+
+    /// `None` if the storage is read-only
+    wal: Option<Wal>,
+
+    /// Entries changed since the last commit
+    dirty: Vec<Key>,
+
+    /// Values of the entries changed since the last commit as of the commit
+    undo: HashMap<Key, Record>,
+}
+
+impl JsonStorage {
+    /// Opens the storage whose snapshot is at `path`.
+    ///
+    /// A snapshot written by an older version of the server is migrated to
+    /// the current version.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let (mut wal, mut database) = Wal::open(path)?;
+        let changes = migrate(&mut database)?;
+        for change in &changes {
+            tracing::info!("migrated {}: {}", path.display(), change);
+        }
+        let database = parse(database, path)?;
+        if !changes.is_empty() {
+            // The log must not contain records of different versions.
+            wal.snapshot(&database)?;
+        }
+        let mut storage = Self::new(database, Some(wal));
+        storage.compact()?;
+        Ok(storage)
+    }
+
+    /// Opens the storage whose snapshot is at `path` without modifying any
+    /// files. Changes cannot be committed.
+    ///
+    /// A snapshot written by an older version of the server is migrated in
+    /// memory.
+    pub fn open_read_only(path: &Path) -> anyhow::Result<Self> {
+        let mut database = load(path)?.database;
+        migrate(&mut database)?;
+        Ok(Self::new(parse(database, path)?, None))
+    }
+
+    /// Returns the changes that opening the storage whose snapshot is at
+    /// `path` would make by migrating it, without modifying any files.
+    pub fn check_migrations(path: &Path) -> anyhow::Result<Vec<String>> {
+        let mut database = load(path)?.database;
+        let changes = migrate(&mut database)?;
+        parse(database, path)?;
+        Ok(changes)
+    }
+
+    fn new(database: Database, wal: Option<Wal>) -> Self {
+        Self {
+            database,
+            wal,
+            dirty: Vec::new(),
+            undo: HashMap::new(),
+        }
+    }
+
+    /// Marks the entry as changed. Must be called before changing it, so
+    /// that the change can be rolled back.
+    fn touch(&mut self, key: Key) {
+        if !self.undo.contains_key(&key) {
+            let record = Record::of(&self.database, key.clone());
+            self.undo.insert(key.clone(), record);
+        }
+        self.dirty.push(key);
+    }
+}
+
+impl Storage for JsonStorage {
+    fn next_id(&self) -> anyhow::Result<u64> {
+        Ok(self.database.next_id)
+    }
+
+    fn set_next_id(&mut self, next_id: u64) -> anyhow::Result<()> {
+        self.touch(Key::NextId);
+        self.database.next_id = next_id;
+        Ok(())
+    }
+
+    fn user(&self, name: &str) -> anyhow::Result<Option<User>> {
+        Ok(self.database.users.get(name).cloned())
+    }
+
+    fn users(&self) -> anyhow::Result<Vec<User>> {
+        Ok(self.database.users.values().cloned().collect())
+    }
+
+    fn subscribed_users(&self, subjects: &[Subject]) -> anyhow::Result<Vec<User>> {
+        Ok(self
+            .database
+            .users
+            .values()
+            .filter(|user| subjects.iter().any(|s| s.is_in(&user.subscriptions)))
+            .cloned()
+            .collect())
+    }
+
+    fn put_user(&mut self, user: &User) -> anyhow::Result<()> {
+        self.touch(Key::User(user.name.clone()));
+        self.database.users.insert(user.name.clone(), user.clone());
+        Ok(())
+    }
+
+    fn operation(&self, id: u64) -> anyhow::Result<Option<Operation>> {
+        Ok(self.database.operations.get(&id).cloned())
+    }
+
+    fn operations(&self, query: &ListOperationsQuery) -> anyhow::Result<Vec<Operation>> {
+        Ok(self
+            .database
+            .operations
+            .values()
+            .filter(|operation| query.is_match(operation))
+            .cloned()
+            .collect())
+    }
+
+    fn scheduled_operations(
+        &self,
+        starts_at: DateTime<Utc>,
+        ends_at: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Vec<Operation>> {
+        Ok(self
+            .database
+            .operations
+            .values()
+            .filter(|operation| {
+                !operation.status.is_finished()
+                    && operation
+                        .starts_at
+                        .is_some_and(|t| ends_at.is_none_or(|ends_at| t < ends_at))
+                    && operation.ends_at.is_none_or(|t| starts_at < t)
+            })
+            .cloned()
+            .collect())
+    }
+
+    fn dependents(&self, id: u64) -> anyhow::Result<Vec<Operation>> {
+        Ok(self
+            .database
+            .operations
+            .values()
+            .filter(|operation| operation.depends_on.contains(&id))
+            .cloned()
+            .collect())
+    }
+
+    fn put_operation(&mut self, operation: &Operation) -> anyhow::Result<()> {
+        self.touch(Key::Operation(operation.id));
+        self.database
+            .operations
+            .insert(operation.id, operation.clone());
+        Ok(())
+    }
+
+    fn component(&self, name: &str) -> anyhow::Result<Option<Component>> {
+        Ok(self.database.components.get(name).cloned())
+    }
+
+    fn components(&self) -> anyhow::Result<Vec<Component>> {
+        Ok(self.database.components.values().cloned().collect())
+    }
+
+    fn components_referring_to(&self, principal: &str) -> anyhow::Result<Vec<Component>> {
+        Ok(self
+            .database
+            .components
+            .values()
+            .filter(|component| {
+                component
+                    .owners
+                    .iter()
+                    .chain(&component.approval.requires_approval_by)
+                    .any(|p| p == principal)
+            })
+            .cloned()
+            .collect())
+    }
+
+    fn put_component(&mut self, component: &Component) -> anyhow::Result<()> {
+        self.touch(Key::Component(component.name.clone()));
+        self.database
+            .components
+            .insert(component.name.clone(), component.clone());
+        Ok(())
+    }
+
+    fn tag(&self, name: &str) -> anyhow::Result<Option<Tag>> {
+        Ok(self.database.tags.get(name).cloned())
+    }
+
+    fn tags(&self) -> anyhow::Result<Vec<Tag>> {
+        Ok(self.database.tags.values().cloned().collect())
+    }
+
+    fn tags_referring_to(&self, principal: &str) -> anyhow::Result<Vec<Tag>> {
+        Ok(self
+            .database
+            .tags
+            .values()
+            .filter(|tag| {
+                tag.approval
+                    .requires_approval_by
+                    .iter()
+                    .any(|p| p == principal)
+            })
+            .cloned()
+            .collect())
+    }
+
+    fn put_tag(&mut self, tag: &Tag) -> anyhow::Result<()> {
+        self.touch(Key::Tag(tag.name.clone()));
+        self.database.tags.insert(tag.name.clone(), tag.clone());
+        Ok(())
+    }
+
+    fn group(&self, name: &str) -> anyhow::Result<Option<Group>> {
+        Ok(self.database.groups.get(name).cloned())
+    }
+
+    fn groups(&self) -> anyhow::Result<Vec<Group>> {
+        Ok(self.database.groups.values().cloned().collect())
+    }
+
+    fn groups_of(&self, member: &str) -> anyhow::Result<Vec<Group>> {
+        Ok(self
+            .database
+            .groups
+            .values()
+            .filter(|group| group.members.iter().any(|m| m == member))
+            .cloned()
+            .collect())
+    }
+
+    fn put_group(&mut self, group: &Group) -> anyhow::Result<()> {
+        self.touch(Key::Group(group.name.clone()));
+        self.database
+            .groups
+            .insert(group.name.clone(), group.clone());
+        Ok(())
+    }
+
+    fn delete_group(&mut self, name: &str) -> anyhow::Result<()> {
+        self.touch(Key::Group(name.to_owned()));
+        self.database.groups.remove(name);
+        Ok(())
+    }
+
+    fn events(
+        &self,
+        operation: Option<u64>,
+        query: &HistoryQuery,
+    ) -> anyhow::Result<Vec<OperationEvent>> {
+        Ok(self
+            .database
+            .history
+            .iter()
+            .filter(|event| operation.is_none_or(|id| event.operation.id == id))
+            .filter(|event| query.is_match(event))
+            .take(query.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect())
+    }
+
+    fn last_event_id(&self) -> anyhow::Result<Option<u64>> {
+        Ok(self.database.history.last().map(|event| event.id))
+    }
+
+    fn append_event(&mut self, event: &OperationEvent) -> anyhow::Result<()> {
+        self.touch(Key::Event(event.id));
+        self.database.history.push(event.clone());
+        Ok(())
+    }
+
+    fn next_webhook_id(&self) -> anyhow::Result<u64> {
+        Ok(self.database.next_webhook_id)
+    }
+
+    fn set_next_webhook_id(&mut self, next_id: u64) -> anyhow::Result<()> {
+        self.touch(Key::NextWebhookId);
+        self.database.next_webhook_id = next_id;
+        Ok(())
+    }
+
+    fn webhook(&self, id: u64) -> anyhow::Result<Option<Webhook>> {
+        Ok(self.database.webhooks.get(&id).cloned())
+    }
+
+    fn webhooks(&self) -> anyhow::Result<Vec<Webhook>> {
+        Ok(self.database.webhooks.values().cloned().collect())
+    }
+
+    fn put_webhook(&mut self, webhook: &Webhook) -> anyhow::Result<()> {
+        self.touch(Key::Webhook(webhook.id));
+        self.database.webhooks.insert(webhook.id, webhook.clone());
+        Ok(())
+    }
+
+    fn delete_webhook(&mut self, id: u64) -> anyhow::Result<()> {
+        self.touch(Key::Webhook(id));
+        self.database.webhooks.remove(&id);
+        for delivery in self.failed_deliveries(id)? {
+            self.delete_failed_delivery(delivery.id)?;
+        }
+        Ok(())
+    }
+
+    fn failed_delivery(&self, id: u64) -> anyhow::Result<Option<FailedDelivery>> {
+        Ok(self.database.failed_deliveries.get(&id).cloned())
+    }
+
+    fn failed_deliveries(&self, webhook_id: u64) -> anyhow::Result<Vec<FailedDelivery>> {
+        Ok(self
+            .database
+            .failed_deliveries
+            .values()
+            .filter(|delivery| delivery.webhook_id == webhook_id)
+            .cloned()
+            .collect())
+    }
+
+    fn last_failed_delivery_id(&self) -> anyhow::Result<Option<u64>> {
+        Ok(self.database.failed_deliveries.keys().next_back().copied())
+    }
+
+    fn put_failed_delivery(&mut self, delivery: &FailedDelivery) -> anyhow::Result<()> {
+        self.touch(Key::FailedDelivery(delivery.id));
+        self.database
+            .failed_deliveries
+            .insert(delivery.id, delivery.clone());
+        Ok(())
+    }
+
+    fn delete_failed_delivery(&mut self, id: u64) -> anyhow::Result<()> {
+        self.touch(Key::FailedDelivery(id));
+        self.database.failed_deliveries.remove(&id);
+        Ok(())
+    }
+
+    fn channel(&self, id: &str) -> anyhow::Result<Option<Channel>> {
+        Ok(self.database.channels.get(id).cloned())
+    }
+
+    fn channels(&self) -> anyhow::Result<Vec<Channel>> {
+        Ok(self.database.channels.values().cloned().collect())
+    }
+
+    fn subscribed_channels(&self, subjects: &[Subject]) -> anyhow::Result<Vec<Channel>> {
+        Ok(self
+            .database
+            .channels
+            .values()
+            .filter(|channel| subjects.iter().any(|s| s.is_in(&channel.subscriptions)))
+            .cloned()
+            .collect())
+    }
+
+    fn put_channel(&mut self, channel: &Channel) -> anyhow::Result<()> {
+        self.touch(Key::Channel(channel.id.clone()));
+        self.database
+            .channels
+            .insert(channel.id.clone(), channel.clone());
+        Ok(())
+    }
+
+    fn refresh_token(&self, hash: &str) -> anyhow::Result<Option<RefreshToken>> {
+        Ok(self.database.refresh_tokens.get(hash).cloned())
+    }
+
+    fn refresh_tokens(&self, username: &str) -> anyhow::Result<Vec<RefreshToken>> {
+        Ok(self
+            .database
+            .refresh_tokens
+            .values()
+            .filter(|token| token.username == username)
+            .cloned()
+            .collect())
+    }
+
+    fn put_refresh_token(&mut self, token: &RefreshToken) -> anyhow::Result<()> {
+        self.touch(Key::RefreshToken(token.hash.clone()));
+        self.database
+            .refresh_tokens
+            .insert(token.hash.clone(), token.clone());
+        Ok(())
+    }
+
+    fn delete_refresh_token(&mut self, hash: &str) -> anyhow::Result<()> {
+        self.touch(Key::RefreshToken(hash.to_owned()));
+        self.database.refresh_tokens.remove(hash);
+        Ok(())
+    }
+
+    fn service_account(&self, name: &str) -> anyhow::Result<Option<ServiceAccount>> {
+        Ok(self.database.service_accounts.get(name).cloned())
+    }
+
+    fn service_accounts(&self) -> anyhow::Result<Vec<ServiceAccount>> {
+        Ok(self.database.service_accounts.values().cloned().collect())
+    }
+
+    fn put_service_account(&mut self, account: &ServiceAccount) -> anyhow::Result<()> {
+        self.touch(Key::ServiceAccount(account.name.clone()));
+        self.database
+            .service_accounts
+            .insert(account.name.clone(), account.clone());
+        Ok(())
+    }
+
+    fn next_api_token_id(&self) -> anyhow::Result<u64> {
+        Ok(self.database.next_api_token_id)
+    }
+
+    fn set_next_api_token_id(&mut self, next_id: u64) -> anyhow::Result<()> {
+        self.touch(Key::NextApiTokenId);
+        self.database.next_api_token_id = next_id;
+        Ok(())
+    }
+
+    fn api_token(&self, id: u64) -> anyhow::Result<Option<ApiToken>> {
+        Ok(self.database.api_tokens.get(&id).cloned())
+    }
+
+    fn api_tokens(&self, account: &str) -> anyhow::Result<Vec<ApiToken>> {
+        Ok(self
+            .database
+            .api_tokens
+            .values()
+            .filter(|token| token.account == account)
+            .cloned()
+            .collect())
+    }
+
+    fn put_api_token(&mut self, token: &ApiToken) -> anyhow::Result<()> {
+        self.touch(Key::ApiToken(token.id));
+        self.database.api_tokens.insert(token.id, token.clone());
+        Ok(())
+    }
+
+    fn delete_api_token(&mut self, id: u64) -> anyhow::Result<()> {
+        self.touch(Key::ApiToken(id));
+        self.database.api_tokens.remove(&id);
+        Ok(())
+    }
+
+    fn commit(&mut self) -> anyhow::Result<()> {
+        if self.dirty.is_empty() {
+            return Ok(());
+        }
+        let mut seen = HashSet::new();
+        let records: Vec<_> = self
+            .dirty
+            .iter()
+            .filter(|key| seen.insert(*key))
+            .map(|key| Record::of(&self.database, key.clone()))
+            .collect();
+        let wal = self.wal.as_mut().context("the storage is read-only")?;
+        wal.append(&records)?;
+        self.dirty.clear();
+        self.undo.clear();
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> anyhow::Result<()> {
+        for (_, record) in self.undo.drain() {
+            record.apply(&mut self.database);
+        }
+        self.dirty.clear();
+        Ok(())
+    }
+
+    /// Writes a snapshot of the database, which empties the write-ahead log.
+    fn compact(&mut self) -> anyhow::Result<()> {
+        self.commit()?;
+        let Some(wal) = self.wal.as_mut().filter(|wal| !wal.is_empty()) else {
+            return Ok(());
+        };
+        let db = &self.database;
+        tracing::debug!(
+            "saving snapshot: users={}, operations={}, components={}, tags={}, groups={}, events={}, webhooks={}",
+            db.users.len(),
+            db.operations.len(),
+            db.components.len(),
+            db.tags.len(),
+            db.groups.len(),
+            db.history.len(),
+            db.webhooks.len(),
+        );
+        wal.snapshot(&self.database)
+    }
+}
+
+/// A migration of the snapshot from one version to the next.
+///
+/// Returns descriptions of the changes made.
+type Migration = fn(&mut Map<String, Value>) -> anyhow::Result<Vec<String>>;
+
+/// Migrations of the snapshot, where `MIGRATIONS[i]` upgrades version `i` to
+/// version `i + 1`.
+///
+/// Snapshots written before versioning was introduced have no `version` field
+/// and are version 0. Never modify a migration once it is released; add a new
+/// one instead.
+const MIGRATIONS: &[Migration] = &[add_collections, add_refresh_tokens, add_service_accounts];
+
+/// Version of the snapshot written by this version of the server
+const CURRENT_VERSION: u64 = MIGRATIONS.len() as u64;
+
+/// Upgrades the serialized database to the current version.
+///
+/// Returns descriptions of the changes made, which is empty if the database
+/// is already up to date.
+fn migrate(database: &mut Map<String, Value>) -> anyhow::Result<Vec<String>> {
+    let version = match database.get("version") {
+        Some(version) => version.as_u64().context("invalid version")?,
+        None => 0,
+    };
+    anyhow::ensure!(
+        version <= CURRENT_VERSION,
+        "state version {version} is newer than supported ({CURRENT_VERSION})"
+    );
+    let mut changes = Vec::new();
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let to = i as u64 + 1;
+        for change in migration(database)? {
+            changes.push(format!("version {to}: {change}"));
+        }
+        database.insert("version".to_owned(), to.into());
+        changes.push(format!("upgraded to version {to}"));
+    }
+    Ok(changes)
+}
+
+/// Version 1: adds the collections and counters that older snapshots may
+/// lack, as they were added after the first release.
+#[allow(clippy::unnecessary_wraps)] // to match the signature of `Migration`
+fn add_collections(database: &mut Map<String, Value>) -> anyhow::Result<Vec<String>> {
+    let mut changes = Vec::new();
+    for (name, empty) in [
+        ("groups", Value::Object(Map::new())),
+        ("history", Value::Array(Vec::new())),
+        ("next_webhook_id", Value::from(1)),
+        ("webhooks", Value::Object(Map::new())),
+        ("failed_deliveries", Value::Object(Map::new())),
+        ("channels", Value::Object(Map::new())),
+    ] {
+        if !database.contains_key(name) {
+            changes.push(format!("added {name} = {empty}"));
+            database.insert(name.to_owned(), empty);
+        }
+    }
+    Ok(changes)
+}
+
+/// Version 2: adds refresh tokens. Tokens issued before were not stored, so
+/// they can no longer be used.
+#[allow(clippy::unnecessary_wraps)] // to match the signature of `Migration`
+fn add_refresh_tokens(database: &mut Map<String, Value>) -> anyhow::Result<Vec<String>> {
+    database.insert("refresh_tokens".to_owned(), Value::Object(Map::new()));
+    Ok(vec!["added refresh_tokens = {}".to_owned()])
+}
+
+/// Version 3: adds service accounts and their API tokens.
+#[allow(clippy::unnecessary_wraps)] // to match the signature of `Migration`
+fn add_service_accounts(database: &mut Map<String, Value>) -> anyhow::Result<Vec<String>> {
+    let mut changes = Vec::new();
+    for (name, empty) in [
+        ("service_accounts", Value::Object(Map::new())),
+        ("next_api_token_id", Value::from(1)),
+        ("api_tokens", Value::Object(Map::new())),
+    ] {
+        changes.push(format!("added {name} = {empty}"));
+        database.insert(name.to_owned(), empty);
+    }
+    Ok(changes)
+}
+
+/// Deserializes the database migrated to the current version.
+fn parse(database: Map<String, Value>, path: &Path) -> anyhow::Result<Database> {
+    serde_json::from_value(Value::Object(database))
+        .with_context(|| format!("failed to parse {}", path.display()))
+}
+
+#[derive(Serialize, Deserialize)]
+struct Database {
+    version: u64,
+    next_id: u64,
+    users: HashMap<String, User>,
+    operations: BTreeMap<u64, Operation>,
+    components: HashMap<String, Component>,
+    tags: HashMap<String, Tag>,
+    groups: HashMap<String, Group>,
+    history: Vec<OperationEvent>,
+
+    /// ID of the next webhook, so that IDs of deleted webhooks are not reused
+    next_webhook_id: u64,
+
+    webhooks: BTreeMap<u64, Webhook>,
+    failed_deliveries: BTreeMap<u64, FailedDelivery>,
+    channels: HashMap<String, Channel>,
+
+    /// Keyed by the hashes of the tokens
+    refresh_tokens: HashMap<String, RefreshToken>,
+
+    service_accounts: HashMap<String, ServiceAccount>,
+
+    /// ID of the next API token, so that IDs of revoked tokens are not reused
+    next_api_token_id: u64,
+
+    api_tokens: BTreeMap<u64, ApiToken>,
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Self {
+            version: CURRENT_VERSION,
+            next_id: INITIAL_NEXT_ID,
+            users: HashMap::new(),
+            operations: BTreeMap::new(),
+            components: HashMap::new(),
+            tags: HashMap::new(),
+            groups: HashMap::new(),
+            history: Vec::new(),
+            next_webhook_id: 1,
+            webhooks: BTreeMap::new(),
+            failed_deliveries: BTreeMap::new(),
+            channels: HashMap::new(),
+            refresh_tokens: HashMap::new(),
+            service_accounts: HashMap::new(),
+            next_api_token_id: 1,
+            api_tokens: BTreeMap::new(),
+        }
+    }
+}
+
+/// Identifies an entry of the database.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(tag = "type", content = "id", rename_all = "snake_case")]
+enum Key {
+    NextId,
+    User(String),
+    Operation(u64),
+    Component(String),
+    Tag(String),
+    Group(String),
+    Event(u64),
+    NextWebhookId,
+    Webhook(u64),
+    FailedDelivery(u64),
+    Channel(String),
+    RefreshToken(String),
+    ServiceAccount(String),
+    NextApiTokenId,
+    ApiToken(u64),
+}
+
+/// The value of an entry of the database after a change.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+enum Record {
+    NextId(u64),
+    User(User),
+    Operation(Operation),
+    Component(Component),
+    Tag(Tag),
+    Group(Group),
+    Event(OperationEvent),
+    NextWebhookId(u64),
+    Webhook(Webhook),
+    FailedDelivery(FailedDelivery),
+    Channel(Channel),
+    RefreshToken(RefreshToken),
+    ServiceAccount(ServiceAccount),
+    NextApiTokenId(u64),
+    ApiToken(ApiToken),
+
+    /// The entry was deleted.
+    Deleted(Key),
+}
+
+impl Record {
+    /// Returns the record of the current value of the entry.
+    fn of(database: &Database, key: Key) -> Self {
+        let record = match &key {
+            Key::NextId => Some(Self::NextId(database.next_id)),
+            Key::User(name) => database.users.get(name).cloned().map(Self::User),
+            Key::Operation(id) => database.operations.get(id).cloned().map(Self::Operation),
+            Key::Component(name) => database.components.get(name).cloned().map(Self::Component),
+            Key::Tag(name) => database.tags.get(name).cloned().map(Self::Tag),
+            Key::Group(name) => database.groups.get(name).cloned().map(Self::Group),
+            Key::Event(id) => database
+                .history
+                .binary_search_by_key(id, |event| event.id)
+                .ok()
+                .map(|i| Self::Event(database.history[i].clone())),
+            Key::NextWebhookId => Some(Self::NextWebhookId(database.next_webhook_id)),
+            Key::Webhook(id) => database.webhooks.get(id).cloned().map(Self::Webhook),
+            Key::FailedDelivery(id) => database
+                .failed_deliveries
+                .get(id)
+                .cloned()
+                .map(Self::FailedDelivery),
+            Key::Channel(id) => database.channels.get(id).cloned().map(Self::Channel),
+            Key::RefreshToken(hash) => database
+                .refresh_tokens
+                .get(hash)
+                .cloned()
+                .map(Self::RefreshToken),
+            Key::ServiceAccount(name) => database
+                .service_accounts
+                .get(name)
+                .cloned()
+                .map(Self::ServiceAccount),
+            Key::NextApiTokenId => Some(Self::NextApiTokenId(database.next_api_token_id)),
+            Key::ApiToken(id) => database.api_tokens.get(id).cloned().map(Self::ApiToken),
+        };
+        record.unwrap_or(Self::Deleted(key))
+    }
+
+    /// Sets the entry to the value of the record.
+    fn apply(self, database: &mut Database) {
+        match self {
+            Self::NextId(next_id) => database.next_id = next_id,
+            Self::User(user) => {
+                database.users.insert(user.name.clone(), user);
+            }
+            Self::Operation(operation) => {
+                database.operations.insert(operation.id, operation);
+            }
+            Self::Component(component) => {
+                database
+                    .components
+                    .insert(component.name.clone(), component);
+            }
+            Self::Tag(tag) => {
+                database.tags.insert(tag.name.clone(), tag);
+            }
+            Self::Group(group) => {
+                database.groups.insert(group.name.clone(), group);
+            }
+            Self::Event(event) => {
+                // Events are never modified once recorded.
+                if database
+                    .history
+                    .last()
+                    .is_none_or(|last| last.id < event.id)
+                {
+                    database.history.push(event);
+                }
+            }
+            Self::NextWebhookId(next_id) => database.next_webhook_id = next_id,
+            Self::Webhook(webhook) => {
+                database.webhooks.insert(webhook.id, webhook);
+            }
+            Self::FailedDelivery(delivery) => {
+                database.failed_deliveries.insert(delivery.id, delivery);
+            }
+            Self::Channel(channel) => {
+                database.channels.insert(channel.id.clone(), channel);
+            }
+            Self::RefreshToken(token) => {
+                database.refresh_tokens.insert(token.hash.clone(), token);
+            }
+            Self::ServiceAccount(account) => {
+                database
+                    .service_accounts
+                    .insert(account.name.clone(), account);
+            }
+            Self::NextApiTokenId(next_id) => database.next_api_token_id = next_id,
+            Self::ApiToken(token) => {
+                database.api_tokens.insert(token.id, token);
+            }
+            Self::Deleted(key) => match key {
+                // Counters always have a value.
+                Key::NextId | Key::NextWebhookId | Key::NextApiTokenId => {}
+                // Only rolling back an event deletes it.
+                Key::Event(id) => database.history.retain(|event| event.id != id),
+                Key::User(name) => {
+                    database.users.remove(&name);
+                }
+                Key::Operation(id) => {
+                    database.operations.remove(&id);
+                }
+                Key::Component(name) => {
+                    database.components.remove(&name);
+                }
+                Key::Tag(name) => {
+                    database.tags.remove(&name);
+                }
+                Key::Group(name) => {
+                    database.groups.remove(&name);
+                }
+                Key::Webhook(id) => {
+                    database.webhooks.remove(&id);
+                }
+                Key::FailedDelivery(id) => {
+                    database.failed_deliveries.remove(&id);
+                }
+                Key::Channel(id) => {
+                    database.channels.remove(&id);
+                }
+                Key::RefreshToken(hash) => {
+                    database.refresh_tokens.remove(&hash);
+                }
+                Key::ServiceAccount(name) => {
+                    database.service_accounts.remove(&name);
+                }
+                Key::ApiToken(id) => {
+                    database.api_tokens.remove(&id);
+                }
+            },
+        }
+    }
+}
+
+/// Applies a record of the write-ahead log to the serialized database.
+///
+/// Records are applied before the database is migrated, as they have the
+/// same version as the snapshot. Applying the same record more than once has
+/// the same effect as applying it once.
+fn apply_record(database: &mut Map<String, Value>, record: Value) -> anyhow::Result<()> {
+    let Value::Object(mut record) = record else {
+        anyhow::bail!("record is not an object");
+    };
+    let kind = record.remove("type").context("missing type")?;
+    let value = record.remove("value").context("missing value")?;
+    match kind.as_str().context("invalid type")? {
+        kind @ ("next_id" | "next_webhook_id" | "next_api_token_id") => {
+            database.insert(kind.to_owned(), value);
+        }
+        "event" => {
+            let Value::Array(history) = database
+                .entry("history")
+                .or_insert_with(|| Value::Array(Vec::new()))
+            else {
+                anyhow::bail!("history is not an array");
+            };
+
+            // Events are never modified once recorded.
+            let id = |event: &Value| event.get("id").and_then(Value::as_u64);
+            if history.last().and_then(id) < id(&value) {
+                history.push(value);
+            }
+        }
+        "deleted" => {
+            let kind = value
+                .get("type")
+                .and_then(Value::as_str)
+                .context("invalid key")?;
+            if let Some((collection, _)) = collection_of(kind) {
+                let key = key_string(value.get("id").context("missing id")?)?;
+                if let Some(Value::Object(entries)) = database.get_mut(collection) {
+                    entries.remove(&key);
+                }
+            }
+        }
+        kind => {
+            let (collection, key_field) =
+                collection_of(kind).with_context(|| format!("unknown record type {kind}"))?;
+            let key = key_string(value.get(key_field).context("missing key")?)?;
+            let Value::Object(entries) = database
+                .entry(collection)
+                .or_insert_with(|| Value::Object(Map::new()))
+            else {
+                anyhow::bail!("{collection} is not an object");
+            };
+            entries.insert(key, value);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the field of the database storing the entries of the record type,
+/// and the field of the entries used as keys.
+fn collection_of(kind: &str) -> Option<(&'static str, &'static str)> {
+    let collection = match kind {
+        "user" => ("users", "name"),
+        "operation" => ("operations", "id"),
+        "component" => ("components", "name"),
+        "tag" => ("tags", "name"),
+        "group" => ("groups", "name"),
+        "webhook" => ("webhooks", "id"),
+        "failed_delivery" => ("failed_deliveries", "id"),
+        "channel" => ("channels", "id"),
+        "refresh_token" => ("refresh_tokens", "hash"),
+        "service_account" => ("service_accounts", "name"),
+        "api_token" => ("api_tokens", "id"),
+        _ => return None,
+    };
+    Some(collection)
+}
+
+/// Returns the key of a serialized map entry, which is always a string.
+fn key_string(key: &Value) -> anyhow::Result<String> {
+    match key {
+        Value::String(key) => Ok(key.clone()),
+        Value::Number(key) => Ok(key.to_string()),
+        _ => anyhow::bail!("invalid key {key}"),
+    }
+}
+
+/// Write-ahead log of changes to the database.
+///
+/// The log is a file of JSON arrays of records, one commit per line, next to
+/// the snapshot of the database. Since only complete lines are replayed, a
+/// commit is replayed either entirely or not at all. Taking a snapshot
+/// empties the log.
+struct Wal {
+    snapshot_path: PathBuf,
+    log: File,
+
+    /// Number of records in the log
+    len: usize,
+
+    /// Whether a failed append may have left a partial commit at the end of
+    /// the log, after which nothing can be appended
+    poisoned: bool,
+}
+
+impl Wal {
+    /// Opens the log of the snapshot at `snapshot_path`, and loads the
+    /// serialized database by replaying the log on top of the snapshot.
+    fn open(snapshot_path: &Path) -> anyhow::Result<(Self, Map<String, Value>)> {
+        let loaded = load(snapshot_path)?;
+        let log_path = with_suffix(snapshot_path, ".wal");
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .read(true)
+            .open(&log_path)
+            .with_context(|| format!("failed to open {}", log_path.display()))?;
+
+        // A crash while appending can leave an incomplete commit at the end.
+        // The API has not acknowledged the change yet, so it is safe to
+        // discard it.
+        if loaded.valid_len < log.metadata()?.len() {
+            tracing::warn!(
+                "discarding incomplete commit at the end of {}",
+                log_path.display()
+            );
+            log.set_len(loaded.valid_len)?;
+            log.sync_all()?;
+        }
+
+        let wal = Self {
+            snapshot_path: snapshot_path.to_owned(),
+            log,
+            len: loaded.records,
+            poisoned: false,
+        };
+        Ok((wal, loaded.database))
+    }
+
+    const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Appends the records of a commit to the log, and flushes them to the
+    /// disk.
+    ///
+    /// On failure, the records written so far are truncated, so that they
+    /// are not replayed.
+    fn append(&mut self, records: &[Record]) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            !self.poisoned,
+            "the log was left corrupted by a failed write"
+        );
+        let mut buf = serde_json::to_vec(records)?;
+        buf.push(b'\n');
+        let len = self.log.metadata()?.len();
+        if let Err(e) = self.log.write_all(&buf).and_then(|()| self.log.sync_data()) {
+            if let Err(e) = self.log.set_len(len) {
+                tracing::error!("failed to truncate the log: {}", e);
+                self.poisoned = true;
+            }
+            return Err(e.into());
+        }
+        self.len += records.len();
+        Ok(())
+    }
+
+    /// Atomically replaces the snapshot with the database, and empties the
+    /// log.
+    ///
+    /// All the changes to the database must have been appended to the log.
+    fn snapshot(&mut self, database: &Database) -> anyhow::Result<()> {
+        let tmp_path = with_suffix(&self.snapshot_path, ".tmp");
+        let mut tmp = File::create(&tmp_path)?;
+        serde_json::to_writer(&mut tmp, database)?;
+        tmp.sync_all()?;
+        drop(tmp);
+        std::fs::rename(&tmp_path, &self.snapshot_path)?;
+        if let Some(dir) = self.snapshot_path.parent() {
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            File::open(dir)?.sync_all()?;
+        }
+
+        // If we crash before emptying the log, the records are replayed on
+        // top of the new snapshot, which is harmless.
+        self.log.set_len(0)?;
+        self.log.sync_all()?;
+        self.len = 0;
+        Ok(())
+    }
+}
+
+/// Serialized database loaded from a snapshot and its write-ahead log
+struct Loaded {
+    database: Map<String, Value>,
+
+    /// Number of records replayed from the log
+    records: usize,
+
+    /// Length of the log up to the end of the last complete commit
+    valid_len: u64,
+}
+
+/// Loads the serialized database by replaying the log on top of the snapshot
+/// at `snapshot_path`, without modifying any files.
+fn load(snapshot_path: &Path) -> anyhow::Result<Loaded> {
+    let database = match std::fs::read(snapshot_path) {
+        Ok(serialized) => {
+            tracing::info!("loading state from {}", snapshot_path.display());
+            serde_json::from_slice(&serialized)
+                .with_context(|| format!("failed to parse {}", snapshot_path.display()))?
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            serde_json::to_value(Database::default())?
+        }
+        Err(e) => {
+            return Err(e).with_context(|| format!("failed to read {}", snapshot_path.display()))
+        }
+    };
+    let Value::Object(mut database) = database else {
+        anyhow::bail!("{} is not a JSON object", snapshot_path.display());
+    };
+
+    let log_path = with_suffix(snapshot_path, ".wal");
+    let content = match std::fs::read_to_string(&log_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", log_path.display())),
+    };
+    let valid_len = content.rfind('\n').map_or(0, |i| i + 1);
+    let mut records = 0;
+    for (i, line) in content[..valid_len].lines().enumerate() {
+        let commit: Vec<Value> = serde_json::from_str(line)
+            .with_context(|| format!("failed to parse {}:{}", log_path.display(), i + 1))?;
+        records += commit.len();
+        for record in commit {
+            apply_record(&mut database, record)
+                .with_context(|| format!("failed to replay {}:{}", log_path.display(), i + 1))?;
+        }
+    }
+    if records > 0 {
+        tracing::info!("replayed {} records from {}", records, log_path.display());
+    }
+
+    Ok(Loaded {
+        database,
+        records,
+        valid_len: valid_len as u64,
+    })
+}
+
+/// Appends `suffix` to the file name (e.g. `state.json` -> `state.json.wal`).
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smokestack::model::SubscriptionSet;
+
+    fn user(name: &str) -> Record {
+        Record::User(User {
+            name: name.to_owned(),
+            email: None,
+            subscriptions: SubscriptionSet::default(),
+        })
+    }
+
+    /// Appends the records to the log, and applies them to the database.
+    fn commit(wal: &mut Wal, database: &mut Database, records: Vec<Record>) {
+        wal.append(&records).unwrap();
+        for record in records {
+            record.apply(database);
+        }
+    }
+
+    fn to_json(database: &Database) -> serde_json::Value {
+        serde_json::to_value(database).unwrap()
+    }
+
+    /// Opens the log of the snapshot at the current version.
+    fn open(path: &Path) -> (Wal, Database) {
+        let (wal, database) = Wal::open(path).unwrap();
+        (wal, parse(database, path).unwrap())
+    }
+
+    #[test]
+    fn reopening_restores_exactly_the_committed_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let (mut wal, mut database) = open(&path);
+        commit(
+            &mut wal,
+            &mut database,
+            vec![user("alice"), Record::NextId(2)],
+        );
+        let committed = to_json(&database);
+        wal.append(&[user("bob"), Record::NextId(3)]).unwrap();
+        drop(wal);
+
+        // Cut the last commit in the middle, as a crash while appending would.
+        let log_path = with_suffix(&path, ".wal");
+        let log = OpenOptions::new().write(true).open(&log_path).unwrap();
+        log.set_len(log.metadata().unwrap().len() - 5).unwrap();
+        drop(log);
+
+        let (mut wal, mut database) = open(&path);
+        assert_eq!(to_json(&database), committed);
+
+        // Commits appended after reopening are not mixed with the discarded
+        // one.
+        commit(&mut wal, &mut database, vec![user("carol")]);
+        let committed = to_json(&database);
+        drop(wal);
+        let (_, database) = open(&path);
+        assert_eq!(to_json(&database), committed);
+        assert!(database.users.contains_key("carol"));
+        assert!(!database.users.contains_key("bob"));
+    }
+
+    #[test]
+    fn snapshots_replace_the_state_file_and_empty_the_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let (mut wal, mut database) = open(&path);
+        commit(&mut wal, &mut database, vec![user("alice")]);
+        wal.snapshot(&database).unwrap();
+        assert!(wal.is_empty());
+        commit(&mut wal, &mut database, vec![user("bob")]);
+        wal.snapshot(&database).unwrap();
+        drop(wal);
+
+        let snapshot: Database = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(to_json(&snapshot), to_json(&database));
+        assert!(!with_suffix(&path, ".tmp").exists());
+        assert_eq!(
+            std::fs::metadata(with_suffix(&path, ".wal")).unwrap().len(),
+            0
+        );
+        let (wal, reopened) = open(&path);
+        assert!(wal.is_empty());
+        assert_eq!(to_json(&reopened), to_json(&database));
+    }
+
+    #[test]
+    fn migrates_snapshots_written_before_versioning() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let snapshot = include_str!("../../testdata/state-v0.json");
+        std::fs::write(&path, snapshot).unwrap();
+        let log = "[{\"type\":\"next_id\",\"value\":1237}]\n";
+        std::fs::write(with_suffix(&path, ".wal"), log).unwrap();
+
+        // Checking reports the changes without making them.
+        let changes = JsonStorage::check_migrations(&path).unwrap();
+        assert_eq!(
+            changes,
+            [
+                "version 1: added groups = {}",
+                "version 1: added history = []",
+                "version 1: added next_webhook_id = 1",
+                "version 1: added webhooks = {}",
+                "version 1: added failed_deliveries = {}",
+                "version 1: added channels = {}",
+                "upgraded to version 1",
+                "version 2: added refresh_tokens = {}",
+                "upgraded to version 2",
+                "version 3: added service_accounts = {}",
+                "version 3: added next_api_token_id = 1",
+                "version 3: added api_tokens = {}",
+                "upgraded to version 3",
+            ]
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), snapshot);
+        assert_eq!(
+            std::fs::read_to_string(with_suffix(&path, ".wal")).unwrap(),
+            log
+        );
+
+        let storage = JsonStorage::open(&path).unwrap();
+        assert_eq!(storage.next_id().unwrap(), 1237);
+        assert_eq!(storage.next_webhook_id().unwrap(), 1);
+        let alice = storage.user("alice").unwrap().unwrap();
+        assert!(alice.subscriptions.operations.contains_key(&1234));
+        assert!(alice.subscriptions.components.contains_key("foo"));
+        let operation = storage.operation(1235).unwrap().unwrap();
+        assert_eq!(operation.depends_on, [1234]);
+        assert_eq!(operation.annotations["ticket"], "OPS-1");
+        assert_eq!(operation.starts_at, None);
+        assert_eq!(storage.dependents(1234).unwrap()[0].id, 1235);
+        assert_eq!(storage.component("foo").unwrap().unwrap().owners, ["alice"]);
+        assert!(storage.tag("network").unwrap().is_some());
+        assert!(storage.groups().unwrap().is_empty());
+        drop(storage);
+
+        // The snapshot is rewritten at the current version.
+        let migrated: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(migrated["version"], CURRENT_VERSION);
+        assert_eq!(migrated["next_id"], 1237);
+        assert!(JsonStorage::check_migrations(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn refuses_snapshots_of_newer_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let mut snapshot = serde_json::to_value(Database::default()).unwrap();
+        snapshot["version"] = (CURRENT_VERSION + 1).into();
+        std::fs::write(&path, snapshot.to_string()).unwrap();
+
+        assert!(JsonStorage::check_migrations(&path).is_err());
+        assert!(JsonStorage::open(&path).is_err());
+    }
+}
@@ -0,0 +1,1063 @@
+use super::{Storage, Subject, INITIAL_NEXT_ID};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, types::Value, Connection, OptionalExtension, Params, ToSql};
+use serde::{de::DeserializeOwned, Serialize};
+use smokestack::{
+    api::{HistoryQuery, ListOperationsQuery},
+    model::{
+        ApiToken, Channel, Component, FailedDelivery, Group, Operation, OperationEvent,
+        RefreshToken, ServiceAccount, SubscriptionSet, Tag, User, Webhook,
+    },
+};
+use std::{fmt::Write as _, path::Path, sync::Mutex};
+
+/// Schema migrations, applied in order.
+///
+/// The number of applied migrations is kept in the `user_version` pragma of
+/// the database. Never modify a migration once it is released; add a new one
+/// instead.
+const MIGRATIONS: &[&str] = &[
+    include_str!("../../migrations/0001_init.sql"),
+    include_str!("../../migrations/0002_refresh_tokens.sql"),
+    include_str!("../../migrations/0003_service_accounts.sql"),
+];
+
+/// Storage backed by an SQLite database.
+///
+/// Entities are stored as JSON, along with the columns needed to look them up
+/// efficiently.
+pub struct SqliteStorage {
+    // `Connection` is not `Sync`.
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    /// Opens the database at `path`, creating it if it does not exist.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let mut conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "FULL")?;
+        migrate(&mut conn)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Returns whether the database contains no data.
+    ///
+    /// The counters in `meta` are set by the migrations, so they do not
+    /// count as data.
+    pub fn is_empty(&self) -> anyhow::Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT name FROM sqlite_master
+             WHERE type = 'table' AND name NOT LIKE 'sqlite_%' AND name != 'meta'",
+        )?;
+        let tables = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for table in tables {
+            let has_rows: bool = conn.query_row(
+                &format!("SELECT EXISTS (SELECT 1 FROM {table})"),
+                [],
+                |row| row.get(0),
+            )?;
+            if has_rows {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn get<T: DeserializeOwned>(
+        &self,
+        sql: &str,
+        params: impl Params,
+    ) -> anyhow::Result<Option<T>> {
+        let conn = self.conn.lock().unwrap();
+        let data: Option<String> = conn.query_row(sql, params, |row| row.get(0)).optional()?;
+        Ok(data.map(|data| serde_json::from_str(&data)).transpose()?)
+    }
+
+    fn list<T: DeserializeOwned>(&self, sql: &str, params: impl Params) -> anyhow::Result<Vec<T>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached(sql)?;
+        let mut rows = stmt.query(params)?;
+        let mut items = Vec::new();
+        while let Some(row) = rows.next()? {
+            let data: String = row.get(0)?;
+            items.push(serde_json::from_str(&data)?);
+        }
+        Ok(items)
+    }
+
+    /// Returns the connection, starting a transaction if none is in progress.
+    fn writer(&mut self) -> anyhow::Result<&mut Connection> {
+        let conn = self.conn.get_mut().unwrap();
+        if conn.is_autocommit() {
+            conn.execute_batch("BEGIN IMMEDIATE")?;
+        }
+        Ok(conn)
+    }
+
+    fn meta(&self, key: &str) -> anyhow::Result<Option<u64>> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn
+            .query_row("SELECT value FROM meta WHERE key = ?1", [key], |row| {
+                row.get(0)
+            })
+            .optional()?)
+    }
+
+    fn set_meta(&mut self, key: &str, value: u64) -> anyhow::Result<()> {
+        self.writer()?.execute(
+            "INSERT INTO meta (key, value) VALUES (?1, ?2)
+             ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+            params![key, value],
+        )?;
+        Ok(())
+    }
+
+    fn put<T: Serialize>(
+        &mut self,
+        table: &str,
+        key: &dyn rusqlite::ToSql,
+        value: &T,
+    ) -> anyhow::Result<()> {
+        let data = serde_json::to_string(value)?;
+        let key_column = match table {
+            "users" | "components" | "tags" | "groups" | "service_accounts" => "name",
+            _ => "id",
+        };
+        self.writer()?.execute(
+            &format!(
+                "INSERT INTO {table} ({key_column}, data) VALUES (?1, ?2)
+                 ON CONFLICT ({key_column}) DO UPDATE SET data = excluded.data"
+            ),
+            params![key, data],
+        )?;
+        Ok(())
+    }
+
+    /// Replaces the rows of the index `table` for the entity whose key in
+    /// `key_column` is `key` with the values of `value_columns`.
+    fn index(
+        &mut self,
+        table: &str,
+        key_column: &str,
+        value_columns: &[&str],
+        key: &dyn ToSql,
+        rows: impl IntoIterator<Item = Vec<Value>>,
+    ) -> anyhow::Result<()> {
+        let conn = self.writer()?;
+        conn.execute(
+            &format!("DELETE FROM {table} WHERE {key_column} = ?1"),
+            [key],
+        )?;
+        let sql = format!(
+            "INSERT OR IGNORE INTO {table} ({key_column}, {}) VALUES (?, {})",
+            value_columns.join(", "),
+            placeholders(value_columns.len())
+        );
+        let mut stmt = conn.prepare_cached(&sql)?;
+        for row in rows {
+            let mut params: Vec<&dyn ToSql> = vec![key];
+            params.extend(row.iter().map(|value| value as &dyn ToSql));
+            stmt.execute(params.as_slice())?;
+        }
+        Ok(())
+    }
+
+    /// Lists the entities of `table` subscribed to any of the subjects
+    /// according to the index `index`.
+    fn subscribers<T: DeserializeOwned>(
+        &self,
+        table: &str,
+        index: &str,
+        subjects: &[Subject],
+    ) -> anyhow::Result<Vec<T>> {
+        if subjects.is_empty() {
+            return Ok(Vec::new());
+        }
+        let (key_column, index_key_column) = match table {
+            "users" => ("name", "user_name"),
+            _ => ("id", "channel_id"),
+        };
+        let conditions = vec!["(kind = ? AND subject = ?)"; subjects.len()].join(" OR ");
+        let sql = format!(
+            "SELECT data FROM {table} WHERE {key_column} IN
+             (SELECT {index_key_column} FROM {index} WHERE {conditions})"
+        );
+        let params = subjects.iter().flat_map(|subject| {
+            let (kind, subject) = subject_row(*subject);
+            [Value::Text(kind.to_owned()), Value::Text(subject)]
+        });
+        self.list(&sql, rusqlite::params_from_iter(params))
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn next_id(&self) -> anyhow::Result<u64> {
+        Ok(self.meta("next_id")?.unwrap_or(INITIAL_NEXT_ID))
+    }
+
+    fn set_next_id(&mut self, next_id: u64) -> anyhow::Result<()> {
+        self.set_meta("next_id", next_id)
+    }
+
+    fn user(&self, name: &str) -> anyhow::Result<Option<User>> {
+        self.get("SELECT data FROM users WHERE name = ?1", [name])
+    }
+
+    fn users(&self) -> anyhow::Result<Vec<User>> {
+        self.list("SELECT data FROM users", [])
+    }
+
+    fn subscribed_users(&self, subjects: &[Subject]) -> anyhow::Result<Vec<User>> {
+        self.subscribers("users", "user_subscriptions", subjects)
+    }
+
+    fn put_user(&mut self, user: &User) -> anyhow::Result<()> {
+        self.put("users", &user.name, user)?;
+        self.index(
+            "user_subscriptions",
+            "user_name",
+            &["kind", "subject"],
+            &user.name,
+            subscription_rows(&user.subscriptions),
+        )
+    }
+
+    fn operation(&self, id: u64) -> anyhow::Result<Option<Operation>> {
+        self.get("SELECT data FROM operations WHERE id = ?1", [id])
+    }
+
+    fn operations(&self, query: &ListOperationsQuery) -> anyhow::Result<Vec<Operation>> {
+        let mut sql = "SELECT data FROM operations WHERE 1 = 1".to_owned();
+        let mut params = Vec::new();
+        filter_by_operation(
+            &mut sql,
+            &mut params,
+            "operation",
+            &query.components,
+            &query.tags,
+            &query.statuses,
+        );
+        sql.push_str(" ORDER BY id");
+        let operations: Vec<Operation> = self.list(&sql, rusqlite::params_from_iter(params))?;
+        Ok(operations
+            .into_iter()
+            .filter(|operation| query.is_match(operation))
+            .collect())
+    }
+
+    fn scheduled_operations(
+        &self,
+        starts_at: DateTime<Utc>,
+        ends_at: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Vec<Operation>> {
+        let mut sql = "SELECT data FROM operations
+                       WHERE starts_at IS NOT NULL AND (ends_at IS NULL OR ends_at > ?)"
+            .to_owned();
+        let mut params = vec![starts_at.timestamp_micros()];
+        if let Some(ends_at) = ends_at {
+            sql.push_str(" AND starts_at < ?");
+            params.push(ends_at.timestamp_micros());
+        }
+        sql.push_str(" ORDER BY id");
+        let operations: Vec<Operation> = self.list(&sql, rusqlite::params_from_iter(params))?;
+        Ok(operations
+            .into_iter()
+            .filter(|operation| !operation.status.is_finished())
+            .collect())
+    }
+
+    fn dependents(&self, id: u64) -> anyhow::Result<Vec<Operation>> {
+        self.list(
+            "SELECT data FROM operations WHERE id IN
+             (SELECT operation_id FROM operation_dependencies WHERE dependency = ?1)
+             ORDER BY id",
+            [id],
+        )
+    }
+
+    fn put_operation(&mut self, operation: &Operation) -> anyhow::Result<()> {
+        let data = serde_json::to_string(operation)?;
+        let conn = self.writer()?;
+        conn.execute(
+            "INSERT INTO operations (id, status, starts_at, ends_at, data)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (id) DO UPDATE SET status = excluded.status,
+                 starts_at = excluded.starts_at, ends_at = excluded.ends_at, data = excluded.data",
+            params![
+                operation.id,
+                operation.status.to_string(),
+                operation.starts_at.map(|t| t.timestamp_micros()),
+                operation.ends_at.map(|t| t.timestamp_micros()),
+                data
+            ],
+        )?;
+        conn.execute(
+            "DELETE FROM operation_components WHERE operation_id = ?1",
+            [operation.id],
+        )?;
+        for component in &operation.components {
+            conn.execute(
+                "INSERT INTO operation_components (operation_id, component) VALUES (?1, ?2)",
+                params![operation.id, component],
+            )?;
+        }
+        conn.execute(
+            "DELETE FROM operation_tags WHERE operation_id = ?1",
+            [operation.id],
+        )?;
+        for tag in &operation.tags {
+            conn.execute(
+                "INSERT INTO operation_tags (operation_id, tag) VALUES (?1, ?2)",
+                params![operation.id, tag],
+            )?;
+        }
+        self.index(
+            "operation_dependencies",
+            "operation_id",
+            &["dependency"],
+            &operation.id,
+            operation
+                .depends_on
+                .iter()
+                .map(|id| vec![Value::Integer(*id as i64)]),
+        )
+    }
+
+    fn component(&self, name: &str) -> anyhow::Result<Option<Component>> {
+        self.get("SELECT data FROM components WHERE name = ?1", [name])
+    }
+
+    fn components(&self) -> anyhow::Result<Vec<Component>> {
+        self.list("SELECT data FROM components", [])
+    }
+
+    fn components_referring_to(&self, principal: &str) -> anyhow::Result<Vec<Component>> {
+        self.list(
+            "SELECT data FROM components WHERE name IN
+             (SELECT component FROM component_principals WHERE principal = ?1)",
+            [principal],
+        )
+    }
+
+    fn put_component(&mut self, component: &Component) -> anyhow::Result<()> {
+        self.put("components", &component.name, component)?;
+        self.index(
+            "component_principals",
+            "component",
+            &["principal"],
+            &component.name,
+            component
+                .owners
+                .iter()
+                .chain(&component.approval.requires_approval_by)
+                .map(|principal| vec![Value::Text(principal.clone())]),
+        )
+    }
+
+    fn tag(&self, name: &str) -> anyhow::Result<Option<Tag>> {
+        self.get("SELECT data FROM tags WHERE name = ?1", [name])
+    }
+
+    fn tags(&self) -> anyhow::Result<Vec<Tag>> {
+        self.list("SELECT data FROM tags", [])
+    }
+
+    fn tags_referring_to(&self, principal: &str) -> anyhow::Result<Vec<Tag>> {
+        self.list(
+            "SELECT data FROM tags WHERE name IN
+             (SELECT tag FROM tag_principals WHERE principal = ?1)",
+            [principal],
+        )
+    }
+
+    fn put_tag(&mut self, tag: &Tag) -> anyhow::Result<()> {
+        self.put("tags", &tag.name, tag)?;
+        self.index(
+            "tag_principals",
+            "tag",
+            &["principal"],
+            &tag.name,
+            tag.approval
+                .requires_approval_by
+                .iter()
+                .map(|principal| vec![Value::Text(principal.clone())]),
+        )
+    }
+
+    fn group(&self, name: &str) -> anyhow::Result<Option<Group>> {
+        self.get("SELECT data FROM groups WHERE name = ?1", [name])
+    }
+
+    fn groups(&self) -> anyhow::Result<Vec<Group>> {
+        self.list("SELECT data FROM groups", [])
+    }
+
+    fn groups_of(&self, member: &str) -> anyhow::Result<Vec<Group>> {
+        self.list(
+            "SELECT data FROM groups WHERE name IN
+             (SELECT group_name FROM group_members WHERE member = ?1)",
+            [member],
+        )
+    }
+
+    fn put_group(&mut self, group: &Group) -> anyhow::Result<()> {
+        self.put("groups", &group.name, group)?;
+        self.index(
+            "group_members",
+            "group_name",
+            &["member"],
+            &group.name,
+            group
+                .members
+                .iter()
+                .map(|member| vec![Value::Text(member.clone())]),
+        )
+    }
+
+    fn delete_group(&mut self, name: &str) -> anyhow::Result<()> {
+        let conn = self.writer()?;
+        conn.execute("DELETE FROM groups WHERE name = ?1", [name])?;
+        conn.execute("DELETE FROM group_members WHERE group_name = ?1", [name])?;
+        Ok(())
+    }
+
+    fn events(
+        &self,
+        operation: Option<u64>,
+        query: &HistoryQuery,
+    ) -> anyhow::Result<Vec<OperationEvent>> {
+        let mut sql = "SELECT data FROM events WHERE 1 = 1".to_owned();
+        let mut params = Vec::new();
+        if let Some(operation) = operation {
+            sql.push_str(" AND operation_id = ?");
+            params.push(Value::Integer(operation as i64));
+        }
+        if let Some(after) = query.after {
+            sql.push_str(" AND id > ?");
+            params.push(Value::Integer(after as i64));
+        }
+        if let Some(since) = query.since {
+            sql.push_str(" AND timestamp >= ?");
+            params.push(Value::Integer(since.timestamp_micros()));
+        }
+        if let Some(until) = query.until {
+            sql.push_str(" AND timestamp < ?");
+            params.push(Value::Integer(until.timestamp_micros()));
+        }
+        filter_by_operation(
+            &mut sql,
+            &mut params,
+            "event",
+            &query.components,
+            &query.tags,
+            &query.statuses,
+        );
+        sql.push_str(" ORDER BY id");
+
+        // Actors and operators are filtered here as they are not indexed.
+        let limit = query.limit.unwrap_or(usize::MAX);
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached(&sql)?;
+        let mut rows = stmt.query(rusqlite::params_from_iter(params))?;
+        let mut events = Vec::new();
+        while events.len() < limit {
+            let Some(row) = rows.next()? else {
+                break;
+            };
+            let data: String = row.get(0)?;
+            let event: OperationEvent = serde_json::from_str(&data)?;
+            if query.is_match(&event) {
+                events.push(event);
+            }
+        }
+        Ok(events)
+    }
+
+    fn last_event_id(&self) -> anyhow::Result<Option<u64>> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.query_row("SELECT MAX(id) FROM events", [], |row| row.get(0))?)
+    }
+
+    fn append_event(&mut self, event: &OperationEvent) -> anyhow::Result<()> {
+        let data = serde_json::to_string(event)?;
+        let conn = self.writer()?;
+        conn.execute(
+            "INSERT INTO events (id, operation_id, timestamp, status, data)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                event.id,
+                event.operation.id,
+                event.timestamp.timestamp_micros(),
+                event.operation.status.to_string(),
+                data
+            ],
+        )?;
+        for component in &event.operation.components {
+            conn.execute(
+                "INSERT INTO event_components (event_id, component) VALUES (?1, ?2)",
+                params![event.id, component],
+            )?;
+        }
+        for tag in &event.operation.tags {
+            conn.execute(
+                "INSERT INTO event_tags (event_id, tag) VALUES (?1, ?2)",
+                params![event.id, tag],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn next_webhook_id(&self) -> anyhow::Result<u64> {
+        Ok(self.meta("next_webhook_id")?.unwrap_or(1))
+    }
+
+    fn set_next_webhook_id(&mut self, next_id: u64) -> anyhow::Result<()> {
+        self.set_meta("next_webhook_id", next_id)
+    }
+
+    fn webhook(&self, id: u64) -> anyhow::Result<Option<Webhook>> {
+        self.get("SELECT data FROM webhooks WHERE id = ?1", [id])
+    }
+
+    fn webhooks(&self) -> anyhow::Result<Vec<Webhook>> {
+        self.list("SELECT data FROM webhooks ORDER BY id", [])
+    }
+
+    fn put_webhook(&mut self, webhook: &Webhook) -> anyhow::Result<()> {
+        self.put("webhooks", &webhook.id, webhook)
+    }
+
+    fn delete_webhook(&mut self, id: u64) -> anyhow::Result<()> {
+        let conn = self.writer()?;
+        conn.execute("DELETE FROM webhooks WHERE id = ?1", [id])?;
+        conn.execute("DELETE FROM failed_deliveries WHERE webhook_id = ?1", [id])?;
+        Ok(())
+    }
+
+    fn failed_delivery(&self, id: u64) -> anyhow::Result<Option<FailedDelivery>> {
+        self.get("SELECT data FROM failed_deliveries WHERE id = ?1", [id])
+    }
+
+    fn failed_deliveries(&self, webhook_id: u64) -> anyhow::Result<Vec<FailedDelivery>> {
+        self.list(
+            "SELECT data FROM failed_deliveries WHERE webhook_id = ?1 ORDER BY id",
+            [webhook_id],
+        )
+    }
+
+    fn last_failed_delivery_id(&self) -> anyhow::Result<Option<u64>> {
+        let conn = self.conn.lock().unwrap();
+        Ok(
+            conn.query_row("SELECT MAX(id) FROM failed_deliveries", [], |row| {
+                row.get(0)
+            })?,
+        )
+    }
+
+    fn put_failed_delivery(&mut self, delivery: &FailedDelivery) -> anyhow::Result<()> {
+        let data = serde_json::to_string(delivery)?;
+        self.writer()?.execute(
+            "INSERT INTO failed_deliveries (id, webhook_id, data) VALUES (?1, ?2, ?3)
+             ON CONFLICT (id) DO UPDATE SET data = excluded.data",
+            params![delivery.id, delivery.webhook_id, data],
+        )?;
+        Ok(())
+    }
+
+    fn delete_failed_delivery(&mut self, id: u64) -> anyhow::Result<()> {
+        self.writer()?
+            .execute("DELETE FROM failed_deliveries WHERE id = ?1", [id])?;
+        Ok(())
+    }
+
+    fn channel(&self, id: &str) -> anyhow::Result<Option<Channel>> {
+        self.get("SELECT data FROM channels WHERE id = ?1", [id])
+    }
+
+    fn channels(&self) -> anyhow::Result<Vec<Channel>> {
+        self.list("SELECT data FROM channels", [])
+    }
+
+    fn subscribed_channels(&self, subjects: &[Subject]) -> anyhow::Result<Vec<Channel>> {
+        self.subscribers("channels", "channel_subscriptions", subjects)
+    }
+
+    fn put_channel(&mut self, channel: &Channel) -> anyhow::Result<()> {
+        self.put("channels", &channel.id, channel)?;
+        self.index(
+            "channel_subscriptions",
+            "channel_id",
+            &["kind", "subject"],
+            &channel.id,
+            subscription_rows(&channel.subscriptions),
+        )
+    }
+
+    fn refresh_token(&self, hash: &str) -> anyhow::Result<Option<RefreshToken>> {
+        self.get("SELECT data FROM refresh_tokens WHERE hash = ?1", [hash])
+    }
+
+    fn refresh_tokens(&self, username: &str) -> anyhow::Result<Vec<RefreshToken>> {
+        self.list(
+            "SELECT data FROM refresh_tokens WHERE username = ?1",
+            [username],
+        )
+    }
+
+    fn put_refresh_token(&mut self, token: &RefreshToken) -> anyhow::Result<()> {
+        let data = serde_json::to_string(token)?;
+        self.writer()?.execute(
+            "INSERT INTO refresh_tokens (hash, username, data) VALUES (?1, ?2, ?3)
+             ON CONFLICT (hash) DO UPDATE SET data = excluded.data",
+            params![token.hash, token.username, data],
+        )?;
+        Ok(())
+    }
+
+    fn delete_refresh_token(&mut self, hash: &str) -> anyhow::Result<()> {
+        self.writer()?
+            .execute("DELETE FROM refresh_tokens WHERE hash = ?1", [hash])?;
+        Ok(())
+    }
+
+    fn service_account(&self, name: &str) -> anyhow::Result<Option<ServiceAccount>> {
+        self.get("SELECT data FROM service_accounts WHERE name = ?1", [name])
+    }
+
+    fn service_accounts(&self) -> anyhow::Result<Vec<ServiceAccount>> {
+        self.list("SELECT data FROM service_accounts ORDER BY name", [])
+    }
+
+    fn put_service_account(&mut self, account: &ServiceAccount) -> anyhow::Result<()> {
+        self.put("service_accounts", &account.name, account)
+    }
+
+    fn next_api_token_id(&self) -> anyhow::Result<u64> {
+        Ok(self.meta("next_api_token_id")?.unwrap_or(1))
+    }
+
+    fn set_next_api_token_id(&mut self, next_id: u64) -> anyhow::Result<()> {
+        self.set_meta("next_api_token_id", next_id)
+    }
+
+    fn api_token(&self, id: u64) -> anyhow::Result<Option<ApiToken>> {
+        self.get("SELECT data FROM api_tokens WHERE id = ?1", [id])
+    }
+
+    fn api_tokens(&self, account: &str) -> anyhow::Result<Vec<ApiToken>> {
+        self.list(
+            "SELECT data FROM api_tokens WHERE account = ?1 ORDER BY id",
+            [account],
+        )
+    }
+
+    fn put_api_token(&mut self, token: &ApiToken) -> anyhow::Result<()> {
+        let data = serde_json::to_string(token)?;
+        self.writer()?.execute(
+            "INSERT INTO api_tokens (id, account, data) VALUES (?1, ?2, ?3)
+             ON CONFLICT (id) DO UPDATE SET data = excluded.data",
+            params![token.id, token.account, data],
+        )?;
+        Ok(())
+    }
+
+    fn delete_api_token(&mut self, id: u64) -> anyhow::Result<()> {
+        self.writer()?
+            .execute("DELETE FROM api_tokens WHERE id = ?1", [id])?;
+        Ok(())
+    }
+
+    fn commit(&mut self) -> anyhow::Result<()> {
+        let conn = self.conn.get_mut().unwrap();
+        if !conn.is_autocommit() {
+            conn.execute_batch("COMMIT")?;
+        }
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> anyhow::Result<()> {
+        let conn = self.conn.get_mut().unwrap();
+        if !conn.is_autocommit() {
+            conn.execute_batch("ROLLBACK")?;
+        }
+        Ok(())
+    }
+
+    fn compact(&mut self) -> anyhow::Result<()> {
+        self.commit()?;
+        let conn = self.conn.get_mut().unwrap();
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        Ok(())
+    }
+}
+
+/// Applies the migrations that have not been applied yet.
+fn migrate(conn: &mut Connection) -> anyhow::Result<()> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    anyhow::ensure!(
+        version <= MIGRATIONS.len(),
+        "database schema version {version} is newer than supported ({})",
+        MIGRATIONS.len()
+    );
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        tracing::info!("migrating database schema to version {}", i + 1);
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+/// Appends conditions on the components, tags and status of operations to
+/// the `WHERE` clause of a query on the `operations` or `events` table.
+fn filter_by_operation(
+    sql: &mut String,
+    params: &mut Vec<Value>,
+    table: &str,
+    components: &[String],
+    tags: &[String],
+    statuses: &[smokestack::model::OperationState],
+) {
+    if !statuses.is_empty() {
+        write!(sql, " AND status IN ({})", placeholders(statuses.len())).unwrap();
+        params.extend(
+            statuses
+                .iter()
+                .map(|status| Value::Text(status.to_string())),
+        );
+    }
+    if !components.is_empty() {
+        write!(
+            sql,
+            " AND id IN (SELECT {table}_id FROM {table}_components WHERE component IN ({}))",
+            placeholders(components.len())
+        )
+        .unwrap();
+        params.extend(components.iter().cloned().map(Value::Text));
+    }
+    if !tags.is_empty() {
+        write!(
+            sql,
+            " AND id IN (SELECT {table}_id FROM {table}_tags WHERE tag IN ({}))",
+            placeholders(tags.len())
+        )
+        .unwrap();
+        params.extend(tags.iter().cloned().map(Value::Text));
+    }
+}
+
+/// Returns the `kind` and `subject` columns of the subscription indexes for
+/// the subject.
+fn subject_row(subject: Subject) -> (&'static str, String) {
+    match subject {
+        Subject::Operation(id) => ("operation", id.to_string()),
+        Subject::Component(name) => ("component", name.to_owned()),
+        Subject::Tag(name) => ("tag", name.to_owned()),
+    }
+}
+
+/// Returns the rows of the subscription indexes for the subscriptions.
+fn subscription_rows(subscriptions: &SubscriptionSet) -> Vec<Vec<Value>> {
+    let subjects = subscriptions
+        .operations
+        .keys()
+        .map(|id| Subject::Operation(*id))
+        .chain(
+            subscriptions
+                .components
+                .keys()
+                .map(|c| Subject::Component(c)),
+        )
+        .chain(subscriptions.tags.keys().map(|t| Subject::Tag(t)));
+    subjects
+        .map(|subject| {
+            let (kind, subject) = subject_row(subject);
+            vec![Value::Text(kind.to_owned()), Value::Text(subject)]
+        })
+        .collect()
+}
+
+fn placeholders(n: usize) -> String {
+    vec!["?"; n].join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use smokestack::model::{EventKind, OperationState, SubscriptionFilter};
+    use std::collections::{BTreeMap, HashMap};
+
+    fn storage() -> (tempfile::TempDir, SqliteStorage) {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = SqliteStorage::open(&dir.path().join("state.db")).unwrap();
+        (dir, storage)
+    }
+
+    fn time(hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 6, 1, hour, 0, 0).unwrap()
+    }
+
+    fn operation(id: u64, components: &[&str], tags: &[&str]) -> Operation {
+        Operation {
+            id,
+            title: format!("operation {id}"),
+            purpose: "testing".to_owned(),
+            url: "https://example.com/".parse().unwrap(),
+            components: components.iter().map(ToString::to_string).collect(),
+            locks: Vec::new(),
+            tags: tags.iter().map(ToString::to_string).collect(),
+            depends_on: Vec::new(),
+            starts_at: None,
+            ends_at: None,
+            operators: vec!["alice".to_owned()],
+            on_behalf_of: None,
+            approved_by: Vec::new(),
+            status: OperationState::Planned,
+            annotations: HashMap::new(),
+        }
+    }
+
+    fn event(id: u64, hour: u32, actor: &str, operation: &Operation) -> OperationEvent {
+        OperationEvent {
+            id,
+            timestamp: time(hour),
+            actor: actor.to_owned(),
+            kind: EventKind::Edited,
+            changes: BTreeMap::new(),
+            operation: operation.clone(),
+        }
+    }
+
+    fn ids<T>(items: &[T], id: impl Fn(&T) -> u64) -> Vec<u64> {
+        items.iter().map(id).collect()
+    }
+
+    #[test]
+    fn events_are_filtered_by_the_query() {
+        let (_dir, mut storage) = storage();
+        let foo = operation(1, &["foo"], &["network"]);
+        let mut bar = operation(2, &["bar"], &[]);
+        bar.status = OperationState::InProgress;
+        storage.append_event(&event(1, 1, "alice", &foo)).unwrap();
+        storage.append_event(&event(2, 2, "bob", &bar)).unwrap();
+        storage.append_event(&event(3, 3, "alice", &bar)).unwrap();
+        storage.append_event(&event(4, 4, "bob", &foo)).unwrap();
+        storage.commit().unwrap();
+
+        let events = |operation, query| ids(&storage.events(operation, &query).unwrap(), |e| e.id);
+        assert_eq!(events(None, HistoryQuery::default()), [1, 2, 3, 4]);
+        assert_eq!(events(Some(2), HistoryQuery::default()), [2, 3]);
+        assert_eq!(
+            events(
+                None,
+                HistoryQuery {
+                    after: Some(2),
+                    ..Default::default()
+                }
+            ),
+            [3, 4]
+        );
+        assert_eq!(
+            events(
+                None,
+                HistoryQuery {
+                    since: Some(time(2)),
+                    until: Some(time(4)),
+                    ..Default::default()
+                }
+            ),
+            [2, 3]
+        );
+        assert_eq!(
+            events(
+                None,
+                HistoryQuery {
+                    components: vec!["foo".to_owned()],
+                    ..Default::default()
+                }
+            ),
+            [1, 4]
+        );
+        assert_eq!(
+            events(
+                None,
+                HistoryQuery {
+                    tags: vec!["network".to_owned()],
+                    ..Default::default()
+                }
+            ),
+            [1, 4]
+        );
+        assert_eq!(
+            events(
+                None,
+                HistoryQuery {
+                    statuses: vec![OperationState::InProgress],
+                    ..Default::default()
+                }
+            ),
+            [2, 3]
+        );
+        // The limit applies after the filters that are not indexed.
+        assert_eq!(
+            events(
+                None,
+                HistoryQuery {
+                    actors: vec!["bob".to_owned()],
+                    limit: Some(1),
+                    ..Default::default()
+                }
+            ),
+            [2]
+        );
+        assert_eq!(storage.last_event_id().unwrap(), Some(4));
+    }
+
+    #[test]
+    fn scheduled_operations_overlap_the_window() {
+        let (_dir, mut storage) = storage();
+        let mut unscheduled = operation(1, &["foo"], &[]);
+        unscheduled.status = OperationState::InProgress;
+        let mut open_ended = operation(2, &["foo"], &[]);
+        open_ended.starts_at = Some(time(1));
+        let mut early = operation(3, &["foo"], &[]);
+        early.starts_at = Some(time(1));
+        early.ends_at = Some(time(2));
+        let mut late = operation(4, &["foo"], &[]);
+        late.starts_at = Some(time(5));
+        late.ends_at = Some(time(6));
+        let mut finished = operation(5, &["foo"], &[]);
+        finished.starts_at = Some(time(3));
+        finished.status = OperationState::Canceled;
+        for operation in [&unscheduled, &open_ended, &early, &late, &finished] {
+            storage.put_operation(operation).unwrap();
+        }
+        storage.commit().unwrap();
+
+        let scheduled = |storage: &SqliteStorage, starts_at, ends_at| {
+            ids(
+                &storage.scheduled_operations(starts_at, ends_at).unwrap(),
+                |o| o.id,
+            )
+        };
+        assert_eq!(scheduled(&storage, time(3), Some(time(4))), [2]);
+        assert_eq!(scheduled(&storage, time(0), Some(time(5))), [2, 3]);
+        assert_eq!(scheduled(&storage, time(3), None), [2, 4]);
+
+        // Rescheduling updates the indexed columns.
+        late.starts_at = Some(time(3));
+        storage.put_operation(&late).unwrap();
+        assert_eq!(scheduled(&storage, time(3), Some(time(4))), [2, 4]);
+    }
+
+    #[test]
+    fn dependents_follow_updates_of_dependencies() {
+        let (_dir, mut storage) = storage();
+        let mut first = operation(2, &["foo"], &[]);
+        first.depends_on = vec![1];
+        let mut second = operation(3, &["foo"], &[]);
+        second.depends_on = vec![1, 2];
+        for operation in [&operation(1, &["foo"], &[]), &first, &second] {
+            storage.put_operation(operation).unwrap();
+        }
+        storage.commit().unwrap();
+
+        let dependents =
+            |storage: &SqliteStorage, id| ids(&storage.dependents(id).unwrap(), |o| o.id);
+        assert_eq!(dependents(&storage, 1), [2, 3]);
+        assert_eq!(dependents(&storage, 2), [3]);
+        assert!(dependents(&storage, 3).is_empty());
+
+        second.depends_on = vec![2];
+        storage.put_operation(&second).unwrap();
+        assert_eq!(dependents(&storage, 1), [2]);
+        assert_eq!(dependents(&storage, 2), [3]);
+    }
+
+    #[test]
+    fn subscribed_users_match_any_subject() {
+        let (_dir, mut storage) = storage();
+        let mut alice = User {
+            name: "alice".to_owned(),
+            email: None,
+            subscriptions: SubscriptionSet::default(),
+        };
+        alice
+            .subscriptions
+            .operations
+            .insert(1, SubscriptionFilter::default());
+        let mut bob = User {
+            name: "bob".to_owned(),
+            email: None,
+            subscriptions: SubscriptionSet::default(),
+        };
+        bob.subscriptions
+            .components
+            .insert("foo".to_owned(), SubscriptionFilter::default());
+        bob.subscriptions
+            .tags
+            .insert("network".to_owned(), SubscriptionFilter::default());
+        storage.put_user(&alice).unwrap();
+        storage.put_user(&bob).unwrap();
+        storage.commit().unwrap();
+
+        let subscribed = |storage: &SqliteStorage, subjects: &[Subject]| {
+            let mut names: Vec<_> = storage
+                .subscribed_users(subjects)
+                .unwrap()
+                .into_iter()
+                .map(|user| user.name)
+                .collect();
+            names.sort();
+            names
+        };
+        let foo = operation(1, &["foo"], &["network"]);
+        assert_eq!(subscribed(&storage, &Subject::of(&foo)), ["alice", "bob"]);
+        assert_eq!(subscribed(&storage, &[Subject::Operation(1)]), ["alice"]);
+        assert_eq!(subscribed(&storage, &[Subject::Tag("network")]), ["bob"]);
+        assert!(subscribed(
+            &storage,
+            &[Subject::Operation(2), Subject::Component("bar")]
+        )
+        .is_empty());
+        assert!(subscribed(&storage, &[]).is_empty());
+
+        alice.subscriptions.operations.clear();
+        storage.put_user(&alice).unwrap();
+        assert!(subscribed(&storage, &[Subject::Operation(1)]).is_empty());
+    }
+
+    #[test]
+    fn rollback_discards_uncommitted_changes() {
+        let (_dir, mut storage) = storage();
+        storage.put_operation(&operation(1, &["foo"], &[])).unwrap();
+        storage.set_next_id(2).unwrap();
+        storage.commit().unwrap();
+
+        let mut dependent = operation(2, &["foo"], &[]);
+        dependent.depends_on = vec![1];
+        storage.put_operation(&dependent).unwrap();
+        storage
+            .append_event(&event(1, 1, "alice", &dependent))
+            .unwrap();
+        storage.set_next_id(3).unwrap();
+        storage.rollback().unwrap();
+
+        assert!(storage.operation(2).unwrap().is_none());
+        assert!(storage.dependents(1).unwrap().is_empty());
+        assert!(storage
+            .events(None, &HistoryQuery::default())
+            .unwrap()
+            .is_empty());
+        assert_eq!(storage.next_id().unwrap(), 2);
+        assert!(storage.operation(1).unwrap().is_some());
+    }
+}
@@ -0,0 +1,317 @@
+use crate::{Error, EventReceiver, Result, SharedState};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use smokestack::model::{OperationEvent, Webhook};
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Number of attempts made to deliver an event before giving up
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Delay before the first retry. Doubled after each failed attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delivers operation events to the matching webhooks.
+pub async fn run(state: SharedState) {
+    let client = client();
+    let shutdown = state.read().unwrap().shutdown.subscribe();
+    let mut receiver = match EventReceiver::new(state.clone()) {
+        Ok(receiver) => receiver,
+        Err(e) => {
+            tracing::error!("failed to start webhook deliveries: {}", e);
+            return;
+        }
+    };
+    while let Some(events) = receiver.recv().await {
+        for event in events {
+            let webhooks = match state.read().unwrap().webhooks() {
+                Ok(webhooks) => webhooks,
+                Err(e) => {
+                    tracing::error!("failed to look up webhooks: {}", e);
+                    continue;
+                }
+            };
+            for webhook in webhooks
+                .into_iter()
+                .filter(|webhook| webhook.is_match(&event))
+            {
+                tokio::spawn(deliver_with_retries(
+                    state.clone(),
+                    client.clone(),
+                    webhook,
+                    event.clone(),
+                    INITIAL_BACKOFF,
+                    shutdown.clone(),
+                ));
+            }
+        }
+    }
+}
+
+pub fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        .build()
+        .expect("failed to build HTTP client")
+}
+
+/// Delivers the event to the webhook, retrying failed attempts.
+///
+/// Deliveries failing every attempt are recorded, and so are those left to
+/// retry when the server shuts down. The server waits for `shutdown` to be
+/// dropped before exiting.
+async fn deliver_with_retries(
+    state: SharedState,
+    client: reqwest::Client,
+    webhook: Webhook,
+    event: OperationEvent,
+    mut backoff: Duration,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let Err(e) = deliver(&client, &webhook, &event).await else {
+            return;
+        };
+        tracing::warn!(
+            "failed to deliver event {} to webhook {} (attempt {}/{}): {}",
+            event.id,
+            webhook.id,
+            attempts,
+            MAX_ATTEMPTS,
+            e
+        );
+        if attempts >= MAX_ATTEMPTS {
+            record_failure(&state, webhook.id, event, attempts, e.to_string());
+            return;
+        }
+        #[allow(clippy::redundant_pub_crate)]
+        let shutting_down = tokio::select! {
+            () = tokio::time::sleep(backoff) => false,
+            _ = shutdown.wait_for(|shutdown| *shutdown) => true,
+        };
+        if shutting_down {
+            tracing::info!(
+                "giving up delivering event {} to webhook {} on shutdown",
+                event.id,
+                webhook.id
+            );
+            record_failure(&state, webhook.id, event, attempts, e.to_string());
+            return;
+        }
+        backoff *= 2;
+    }
+}
+
+/// Records the delivery as failed, so that it can be redelivered later.
+fn record_failure(
+    state: &SharedState,
+    webhook_id: u64,
+    event: OperationEvent,
+    attempts: u32,
+    error: String,
+) {
+    let mut state = state.write().unwrap();
+    if let Err(e) = state
+        .record_failed_delivery(webhook_id, event, attempts, error)
+        .and_then(|()| state.commit())
+    {
+        tracing::error!("failed to record failed delivery: {}", e);
+    }
+}
+
+/// Makes a single attempt to deliver the event to the webhook.
+///
+/// The payload is the event serialized as JSON. It is signed with the secret
+/// of the webhook, and the signature is sent in the `X-Smokestack-Signature`
+/// header as `sha256=<hex-encoded HMAC-SHA256>`.
+pub async fn deliver(
+    client: &reqwest::Client,
+    webhook: &Webhook,
+    event: &OperationEvent,
+) -> Result<()> {
+    let payload = serde_json::to_vec(event).map_err(|_| Error::Internal)?;
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(webhook.secret.as_bytes()).map_err(|_| Error::Internal)?;
+    mac.update(&payload);
+    let signature = hex::encode(mac.finalize().into_bytes());
+    client
+        .post(webhook.url.to_string())
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header("X-Smokestack-Event", event.kind.to_string())
+        .header("X-Smokestack-Delivery", event.id.to_string())
+        .header("X-Smokestack-Signature", format!("sha256={signature}"))
+        .body(payload)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|e| Error::DeliveryFailed(e.to_string()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestServer;
+    use axum::{
+        body::Bytes,
+        http::{HeaderMap, StatusCode},
+        routing::post,
+        Router,
+    };
+    use serde_json::json;
+    use smokestack::api::HistoryQuery;
+    use std::sync::{Arc, Mutex};
+
+    /// Headers and bodies of the requests received by an endpoint
+    type Received = Arc<Mutex<Vec<(HeaderMap, Bytes)>>>;
+
+    /// Starts an endpoint failing the first `failures` requests, and returns
+    /// its URL.
+    async fn endpoint(failures: usize) -> (String, Received) {
+        let received = Received::default();
+        let handler = {
+            let received = received.clone();
+            move |headers: HeaderMap, body: Bytes| async move {
+                let mut received = received.lock().unwrap();
+                received.push((headers, body));
+                if received.len() <= failures {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                } else {
+                    StatusCode::NO_CONTENT
+                }
+            }
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let app = Router::new().route("/hook", post(handler));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, received)
+    }
+
+    /// Registers a webhook notifying the URL, and returns it along with an
+    /// event to deliver.
+    async fn setup(server: &TestServer, url: &str) -> (Webhook, OperationEvent) {
+        let alice = server.login("alice");
+        server.create_component(&alice, "foo", &["alice"]).await;
+        let (status, body) = server
+            .post(
+                "/webhooks",
+                &alice,
+                json!({ "url": url, "secret": "s3cret" }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        server.create_operation(&alice, &["foo"]).await;
+
+        let state = server.state.read().unwrap();
+        let webhook = state.webhook(body["id"].as_u64().unwrap()).unwrap();
+        let mut events = state
+            .storage
+            .events(None, &HistoryQuery::default())
+            .unwrap();
+        (webhook, events.pop().unwrap())
+    }
+
+    #[tokio::test]
+    async fn signs_and_retries_deliveries() {
+        let server = TestServer::with_admins(&["alice"]);
+        let (url, received) = endpoint(2).await;
+        let (webhook, event) = setup(&server, &url).await;
+
+        let backoff = Duration::from_millis(1);
+        let shutdown = server.state.read().unwrap().shutdown.subscribe();
+        deliver_with_retries(
+            server.state.clone(),
+            client(),
+            webhook,
+            event.clone(),
+            backoff,
+            shutdown,
+        )
+        .await;
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 3);
+        for (headers, body) in received.iter() {
+            let mut mac = Hmac::<Sha256>::new_from_slice(b"s3cret").unwrap();
+            mac.update(body);
+            let signature = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+            assert_eq!(headers["x-smokestack-signature"], signature.as_str());
+            assert_eq!(headers["x-smokestack-event"], "created");
+            assert_eq!(
+                headers["x-smokestack-delivery"],
+                event.id.to_string().as_str()
+            );
+            let delivered: OperationEvent = serde_json::from_slice(body).unwrap();
+            assert_eq!(delivered.id, event.id);
+        }
+        let state = server.state.read().unwrap();
+        assert!(state.failed_deliveries(1).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn records_deliveries_failing_after_retries() {
+        let server = TestServer::with_admins(&["alice"]);
+        let (url, received) = endpoint(usize::MAX).await;
+        let (webhook, event) = setup(&server, &url).await;
+
+        let backoff = Duration::from_millis(1);
+        let shutdown = server.state.read().unwrap().shutdown.subscribe();
+        deliver_with_retries(
+            server.state.clone(),
+            client(),
+            webhook,
+            event.clone(),
+            backoff,
+            shutdown,
+        )
+        .await;
+
+        assert_eq!(received.lock().unwrap().len(), MAX_ATTEMPTS as usize);
+        let deliveries = server.state.read().unwrap().failed_deliveries(1).unwrap();
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].webhook_id, 1);
+        assert_eq!(deliveries[0].event.id, event.id);
+        assert_eq!(deliveries[0].attempts, MAX_ATTEMPTS);
+        assert!(
+            deliveries[0].error.contains("500"),
+            "{}",
+            deliveries[0].error
+        );
+    }
+
+    #[tokio::test]
+    async fn records_deliveries_left_to_retry_on_shutdown() {
+        let server = TestServer::with_admins(&["alice"]);
+        let (url, received) = endpoint(usize::MAX).await;
+        let (webhook, event) = setup(&server, &url).await;
+
+        let shutdown = server.state.read().unwrap().shutdown.subscribe();
+        let delivery = tokio::spawn(deliver_with_retries(
+            server.state.clone(),
+            client(),
+            webhook,
+            event.clone(),
+            Duration::from_hours(1),
+            shutdown,
+        ));
+        while received.lock().unwrap().is_empty() {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        server.state.read().unwrap().shutdown.send_replace(true);
+        tokio::time::timeout(Duration::from_secs(5), delivery)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(received.lock().unwrap().len(), 1);
+        let deliveries = server.state.read().unwrap().failed_deliveries(1).unwrap();
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].event.id, event.id);
+        assert_eq!(deliveries[0].attempts, 1);
+    }
+}
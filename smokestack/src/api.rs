@@ -1,4 +1,8 @@
-use crate::model::{Component, Operation, OperationState, Tag};
+use crate::model::{
+    ApiToken, ApprovalRequirement, Component, FailedDelivery, Group, Operation, OperationEvent,
+    OperationState, Scope, ServiceAccount, SubscriptionFilter, Tag, Webhook,
+};
+use chrono::{DateTime, Utc};
 use http::Uri;
 use serde::{de, Deserialize, Serialize};
 use std::collections::HashMap;
@@ -88,11 +92,25 @@ where
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthRequest {
     pub username: String,
+
+    /// Not needed when the server trusts a header set by a reverse proxy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthResponse {
+    /// Short-lived token used to authenticate requests
     pub token: String,
+
+    /// Long-lived token used to obtain a new `token` with
+    /// `RefreshTokenRequest`
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshTokenRequest {
+    pub refresh_token: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -114,14 +132,25 @@ pub struct CreateOperationRequest {
     #[serde(default)]
     pub depends_on: Vec<u64>,
 
+    #[serde(default)]
+    pub starts_at: Option<DateTime<Utc>>,
+
+    #[serde(default)]
+    pub ends_at: Option<DateTime<Utc>>,
+
     #[serde(default)]
     pub operators: Vec<String>,
 
+    /// User on whose behalf a service account creates the operation. The
+    /// user becomes the operator unless `operators` is specified.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_behalf_of: Option<String>,
+
     #[serde(default)]
     pub annotations: HashMap<String, String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct ListOperationsQuery {
     #[serde(alias = "component", default)]
     pub components: Vec<String>,
@@ -136,6 +165,50 @@ pub struct ListOperationsQuery {
     pub statuses: Vec<OperationState>,
 }
 
+impl ListOperationsQuery {
+    pub fn is_match(&self, operation: &Operation) -> bool {
+        matches_filters(
+            operation,
+            &self.components,
+            &self.tags,
+            &self.operators,
+            &self.statuses,
+        )
+    }
+}
+
+fn matches_filters(
+    operation: &Operation,
+    components: &[String],
+    tags: &[String],
+    operators: &[String],
+    statuses: &[OperationState],
+) -> bool {
+    if !components.is_empty()
+        && !operation
+            .components
+            .iter()
+            .any(|component| components.contains(component))
+    {
+        return false;
+    }
+    if !tags.is_empty() && !operation.tags.iter().any(|tag| tags.contains(tag)) {
+        return false;
+    }
+    if !operators.is_empty()
+        && !operation
+            .operators
+            .iter()
+            .any(|operator| operators.contains(operator))
+    {
+        return false;
+    }
+    if !statuses.is_empty() && !statuses.contains(&operation.status) {
+        return false;
+    }
+    true
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ListOperationsResponse {
     pub operations: Vec<Operation>,
@@ -153,6 +226,23 @@ pub struct UpdateOperationRequest {
     pub locks: Option<Vec<String>>,
     pub tags: Option<Vec<String>>,
     pub depends_on: Option<Vec<u64>>,
+
+    /// `Some(None)` clears the scheduled start time.
+    #[serde(
+        default,
+        with = "crate::serde_double_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub starts_at: Option<Option<DateTime<Utc>>>,
+
+    /// `Some(None)` clears the scheduled end time.
+    #[serde(
+        default,
+        with = "crate::serde_double_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub ends_at: Option<Option<DateTime<Utc>>>,
+
     pub operators: Option<Vec<String>>,
     pub status: Option<OperationState>,
 
@@ -160,11 +250,90 @@ pub struct UpdateOperationRequest {
     pub annotations: HashMap<String, String>,
 }
 
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct HistoryQuery {
+    #[serde(alias = "component", default)]
+    pub components: Vec<String>,
+
+    #[serde(alias = "tag", default)]
+    pub tags: Vec<String>,
+
+    #[serde(alias = "operator", default)]
+    pub operators: Vec<String>,
+
+    #[serde(alias = "status", default)]
+    pub statuses: Vec<OperationState>,
+
+    /// Users who caused the events
+    #[serde(alias = "actor", default)]
+    pub actors: Vec<String>,
+
+    /// Only return events that happened at or after this time.
+    pub since: Option<DateTime<Utc>>,
+
+    /// Only return events that happened before this time.
+    pub until: Option<DateTime<Utc>>,
+
+    /// Only return events with IDs greater than this.
+    ///
+    /// Used for pagination with `ListHistoryResponse::next`.
+    pub after: Option<u64>,
+
+    /// Maximum number of events to return.
+    pub limit: Option<usize>,
+}
+
+impl HistoryQuery {
+    pub fn is_match(&self, event: &OperationEvent) -> bool {
+        if self.after.is_some_and(|after| event.id <= after) {
+            return false;
+        }
+        if self.since.is_some_and(|since| event.timestamp < since) {
+            return false;
+        }
+        if self.until.is_some_and(|until| event.timestamp >= until) {
+            return false;
+        }
+        if !self.actors.is_empty() && !self.actors.contains(&event.actor) {
+            return false;
+        }
+        matches_filters(
+            &event.operation,
+            &self.components,
+            &self.tags,
+            &self.operators,
+            &self.statuses,
+        )
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListHistoryResponse {
+    /// Events in the order they happened.
+    pub events: Vec<OperationEvent>,
+
+    /// If there are more events, the value to pass as `after` to get the
+    /// next page.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<u64>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateComponentRequest {
     pub name: String,
     pub description: String,
     pub owners: Vec<String>,
+
+    #[serde(flatten)]
+    pub approval: ApprovalRequirement,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UpdateComponentRequest {
+    pub description: Option<String>,
+    pub owners: Option<Vec<String>>,
+    pub requires_approval_by: Option<Vec<String>>,
+    pub required_approvals: Option<u32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -176,6 +345,16 @@ pub struct ListComponentsResponse {
 pub struct CreateTagRequest {
     pub name: String,
     pub description: String,
+
+    #[serde(flatten)]
+    pub approval: ApprovalRequirement,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UpdateTagRequest {
+    pub description: Option<String>,
+    pub requires_approval_by: Option<Vec<String>>,
+    pub required_approvals: Option<u32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -183,16 +362,134 @@ pub struct ListTagsResponse {
     pub tags: Vec<Tag>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateGroupRequest {
+    pub name: String,
+    pub description: String,
+
+    #[serde(default)]
+    pub members: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UpdateUserRequest {
+    /// `Some(None)` clears the email address.
+    #[serde(
+        default,
+        with = "crate::serde_double_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub email: Option<Option<String>>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UpdateGroupRequest {
+    pub description: Option<String>,
+    pub members: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListGroupsResponse {
+    pub groups: Vec<Group>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateSubscriptionRequest {
     pub operation: Option<u64>,
     pub component: Option<String>,
     pub tag: Option<String>,
+
+    #[serde(flatten)]
+    pub filter: SubscriptionFilter,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DeleteSubscriptionQuery {
+    pub operation: Option<u64>,
+    pub component: Option<String>,
+    pub tag: Option<String>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct ListSubscriptionResponse {
-    pub operations: Vec<u64>,
+    pub operations: Vec<SubscriptionEntry<u64>>,
+    pub components: Vec<SubscriptionEntry<String>>,
+    pub tags: Vec<SubscriptionEntry<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SubscriptionEntry<T> {
+    /// The subscribed operation ID, component name or tag name
+    pub target: T,
+
+    #[serde(flatten)]
+    pub filter: SubscriptionFilter,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct WatchQuery {
+    /// Replay events after the event with this ID before sending new events
+    pub since: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateWebhookRequest {
+    #[serde(with = "crate::serde_uri")]
+    pub url: Uri,
+
+    pub secret: String,
+
+    #[serde(default)]
     pub components: Vec<String>,
+
+    #[serde(default)]
     pub tags: Vec<String>,
+
+    #[serde(default)]
+    pub statuses: Vec<OperationState>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListWebhooksResponse {
+    pub webhooks: Vec<Webhook>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListFailedDeliveriesResponse {
+    pub deliveries: Vec<FailedDelivery>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateServiceAccountRequest {
+    pub name: String,
+
+    #[serde(default)]
+    pub description: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListServiceAccountsResponse {
+    pub accounts: Vec<ServiceAccount>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateApiTokenRequest {
+    #[serde(default)]
+    pub description: String,
+
+    pub scopes: Vec<Scope>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateApiTokenResponse {
+    #[serde(flatten)]
+    pub info: ApiToken,
+
+    /// The token to authenticate with, which cannot be retrieved later
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListApiTokensResponse {
+    pub tokens: Vec<ApiToken>,
 }
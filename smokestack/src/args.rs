@@ -0,0 +1,128 @@
+//! Arguments of the commands shared by the CLI and the chat commands of the
+//! server.
+
+use crate::{
+    api::{CreateSubscriptionRequest, DeleteSubscriptionQuery, ListOperationsQuery},
+    model::{EventKind, OperationState, SubscriptionFilter},
+};
+use clap::Args;
+
+#[derive(Debug, Args)]
+pub struct SubscribeArgs {
+    #[command(flatten)]
+    pub target: SubscriptionTarget,
+
+    /// Only notify events after which operations are in any of these states
+    #[arg(
+        short,
+        long = "status",
+        name = "STATUS",
+        num_args = 1..,
+        conflicts_with = "list"
+    )]
+    pub statuses: Vec<OperationState>,
+
+    /// Only notify events of these kinds (e.g. created, edited, transitioned)
+    #[arg(
+        short,
+        long = "kind",
+        name = "KIND",
+        num_args = 1..,
+        conflicts_with = "list"
+    )]
+    pub kinds: Vec<EventKind>,
+}
+
+impl SubscribeArgs {
+    /// Returns the request creating the subscription, or `None` if
+    /// subscriptions are to be listed instead.
+    pub fn into_request(self) -> Option<CreateSubscriptionRequest> {
+        let SubscriptionTarget {
+            list,
+            operation,
+            component,
+            tag,
+        } = self.target;
+        (!list).then_some(CreateSubscriptionRequest {
+            operation,
+            component,
+            tag,
+            filter: SubscriptionFilter {
+                statuses: self.statuses,
+                kinds: self.kinds,
+            },
+        })
+    }
+}
+
+#[derive(Debug, Args)]
+#[group(required = true, multiple = false)]
+pub struct SubscriptionTarget {
+    /// List subscriptions
+    #[arg(short, long)]
+    pub list: bool,
+
+    /// Operation ID to subscribe to
+    #[arg(short, long)]
+    pub operation: Option<u64>,
+
+    /// Component name to subscribe to
+    #[arg(short, long)]
+    pub component: Option<String>,
+
+    /// Tag name to subscribe to
+    #[arg(short, long)]
+    pub tag: Option<String>,
+}
+
+#[derive(Debug, Args)]
+#[group(required = true, multiple = false)]
+pub struct UnsubscribeArgs {
+    /// Operation ID to unsubscribe from
+    #[arg(short, long)]
+    pub operation: Option<u64>,
+
+    /// Component name to unsubscribe from
+    #[arg(short, long)]
+    pub component: Option<String>,
+
+    /// Tag name to unsubscribe from
+    #[arg(short, long)]
+    pub tag: Option<String>,
+}
+
+impl From<UnsubscribeArgs> for DeleteSubscriptionQuery {
+    fn from(args: UnsubscribeArgs) -> Self {
+        Self {
+            operation: args.operation,
+            component: args.component,
+            tag: args.tag,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ListArgs {
+    #[arg(short, long = "component", name = "COMPONENT", num_args = 1..)]
+    pub components: Vec<String>,
+
+    #[arg(short, long = "tag", name = "TAG", num_args = 1..)]
+    pub tags: Vec<String>,
+
+    #[arg(short, long = "status", name = "STATUS", num_args = 1..)]
+    pub statuses: Vec<OperationState>,
+
+    #[arg(short, long = "operator", name = "OPERATOR", num_args = 1..)]
+    pub operators: Vec<String>,
+}
+
+impl From<ListArgs> for ListOperationsQuery {
+    fn from(args: ListArgs) -> Self {
+        Self {
+            components: args.components,
+            tags: args.tags,
+            operators: args.operators,
+            statuses: args.statuses,
+        }
+    }
+}
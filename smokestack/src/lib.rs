@@ -1,4 +1,6 @@
 pub mod api;
+#[cfg(feature = "clap")]
+pub mod args;
 pub mod model;
 
 pub mod serde_uri {
@@ -44,3 +46,31 @@ pub mod serde_uri_option {
         Ok(uri.map(|Wrapper(uri)| uri))
     }
 }
+
+/// Distinguishes a missing field (`None`) from an explicit `null`
+/// (`Some(None)`).
+///
+/// Use with `#[serde(default)]` so that a missing field deserializes to
+/// `None`.
+pub mod serde_double_option {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<T, S>(value: &Option<Option<T>>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Serialize,
+        S: Serializer,
+    {
+        value
+            .as_ref()
+            .and_then(Option::as_ref)
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+    where
+        T: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Option::deserialize(deserializer).map(Some)
+    }
+}
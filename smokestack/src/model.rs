@@ -1,20 +1,144 @@
+use chrono::{DateTime, Utc};
 use http::Uri;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap},
     str::FromStr,
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub name: String,
+
+    /// Address to which notifications are emailed
+    #[serde(default)]
+    pub email: Option<String>,
+
     pub subscriptions: SubscriptionSet,
 }
 
+/// A group of users.
+///
+/// Groups can be used in place of users as component owners and approvers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Group {
+    pub name: String,
+    pub description: String,
+    pub members: Vec<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub exp: u64,
     pub username: String,
+
+    /// Scopes the request is limited to when authenticated with an API token.
+    /// Users logged in with `auth` are not limited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<Scope>>,
+}
+
+/// An account used by automation tools, which authenticates with API tokens
+/// instead of logging in.
+///
+/// Each service account has a user of the same name, which holds its
+/// subscriptions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceAccount {
+    pub name: String,
+    pub description: String,
+}
+
+/// A long-lived token with which a service account authenticates.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub id: u64,
+
+    /// Name of the service account the token authenticates as
+    pub account: String,
+
+    pub description: String,
+    pub scopes: Vec<Scope>,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+
+    /// SHA-256 hash of the secret part of the token, in hex. The token itself
+    /// is shown only when it is created, and the hash is never returned.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub secret_hash: String,
+}
+
+impl ApiToken {
+    /// Returns the information about the token without the hash of its
+    /// secret.
+    #[must_use]
+    pub fn redacted(self) -> Self {
+        Self {
+            secret_hash: String::new(),
+            ..self
+        }
+    }
+}
+
+/// What an API token is allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scope {
+    /// View operations, their history, components and tags
+    #[serde(rename = "operations:read")]
+    OperationsRead,
+
+    /// Create, update and approve operations
+    #[serde(rename = "operations:write")]
+    OperationsWrite,
+
+    /// Subscribe to and unsubscribe from notifications
+    #[serde(rename = "subscriptions:write")]
+    SubscriptionsWrite,
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "operations:read" => Ok(Self::OperationsRead),
+            "operations:write" => Ok(Self::OperationsWrite),
+            "subscriptions:write" => Ok(Self::SubscriptionsWrite),
+            _ => Err(format!("unknown scope: {s}")),
+        }
+    }
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::OperationsRead => "operations:read",
+            Self::OperationsWrite => "operations:write",
+            Self::SubscriptionsWrite => "subscriptions:write",
+        }
+        .fmt(f)
+    }
+}
+
+/// A refresh token issued to a user.
+///
+/// Only the hash of the token is stored, so that the tokens cannot be
+/// recovered from the stored data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefreshToken {
+    /// SHA-256 hash of the token in hex
+    pub hash: String,
+
+    pub username: String,
+
+    /// Identifies the chain of tokens obtained by refreshing the token issued
+    /// on login
+    pub session: String,
+
+    pub expires_at: DateTime<Utc>,
+
+    /// Whether the token has been exchanged for a new one
+    pub used: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -30,7 +154,26 @@ pub struct Operation {
     pub locks: Vec<String>,
     pub tags: Vec<String>,
     pub depends_on: Vec<u64>,
+
+    /// Scheduled start time, or actual start time once started
+    #[serde(default)]
+    pub starts_at: Option<DateTime<Utc>>,
+
+    /// Scheduled end time, or actual end time once finished
+    #[serde(default)]
+    pub ends_at: Option<DateTime<Utc>>,
+
     pub operators: Vec<String>,
+
+    /// User on whose behalf a service account created the operation, who is
+    /// responsible for it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_behalf_of: Option<String>,
+
+    /// Users who approved the operation
+    #[serde(default)]
+    pub approved_by: Vec<String>,
+
     pub status: OperationState,
     pub annotations: HashMap<String, String>,
 }
@@ -102,35 +245,501 @@ impl OperationState {
                 | (Self::Planned, Self::Canceled)
         )
     }
+
+    /// Returns whether an operation in this state holds locks on its
+    /// components.
+    ///
+    /// Paused operations keep their locks so that they can be resumed.
+    pub const fn holds_locks(self) -> bool {
+        matches!(self, Self::InProgress | Self::Paused)
+    }
+
+    /// Returns whether the operation is finished, successfully or not, and
+    /// cannot transition to any other state.
+    pub const fn is_finished(self) -> bool {
+        matches!(self, Self::Completed | Self::Aborted | Self::Canceled)
+    }
+}
+
+/// An entry in the history of an operation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OperationEvent {
+    /// Sequence number of the event, increasing monotonically across all
+    /// operations.
+    pub id: u64,
+
+    pub timestamp: DateTime<Utc>,
+
+    /// The user who caused the event.
+    pub actor: String,
+
+    pub kind: EventKind,
+
+    /// Fields of the operation changed by the event, keyed by field name.
+    ///
+    /// Empty for `created` events.
+    #[serde(default)]
+    pub changes: BTreeMap<String, FieldChange>,
+
+    /// The operation as it was right after the event.
+    pub operation: Operation,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// The operation was created.
+    Created,
+
+    /// Fields of the operation other than the status were changed.
+    Edited,
+
+    /// The status of the operation was changed.
+    Transitioned,
+
+    /// The operation was approved by a user.
+    Approved,
+
+    /// A user revoked their approval of the operation.
+    ApprovalRevoked,
+
+    /// Approvals of the operation were dropped because the operation was
+    /// materially edited.
+    ApprovalsInvalidated,
+}
+
+impl FromStr for EventKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "created" => Ok(Self::Created),
+            "edited" => Ok(Self::Edited),
+            "transitioned" => Ok(Self::Transitioned),
+            "approved" => Ok(Self::Approved),
+            "approval_revoked" => Ok(Self::ApprovalRevoked),
+            "approvals_invalidated" => Ok(Self::ApprovalsInvalidated),
+            _ => Err(format!("unknown event kind: {s}")),
+        }
+    }
+}
+
+impl std::fmt::Display for EventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Created => "created",
+            Self::Edited => "edited",
+            Self::Transitioned => "transitioned",
+            Self::Approved => "approved",
+            Self::ApprovalRevoked => "approval_revoked",
+            Self::ApprovalsInvalidated => "approvals_invalidated",
+        }
+        .fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub old: serde_json::Value,
+    pub new: serde_json::Value,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Component {
     pub name: String,
     pub description: String,
+
+    /// Users or groups owning the component
     pub owners: Vec<String>,
+
+    #[serde(flatten)]
+    pub approval: ApprovalRequirement,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Tag {
     pub name: String,
     pub description: String,
+
+    #[serde(flatten)]
+    pub approval: ApprovalRequirement,
 }
 
+/// Approvals required for operations targeting a component or tagged with a
+/// tag before they can start.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApprovalRequirement {
+    /// Users or groups whose approvals count. If empty, approvals by anyone
+    /// count.
+    #[serde(default)]
+    pub requires_approval_by: Vec<String>,
+
+    /// Number of approvals required. Zero means no approval is required.
+    #[serde(default)]
+    pub required_approvals: u32,
+}
+
+/// Subscriptions of a user or a channel, keyed by the subscribed entity.
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SubscriptionSet {
-    pub operations: HashSet<u64>,
-    pub components: HashSet<String>,
-    pub tags: HashSet<String>,
+    #[serde(deserialize_with = "deserialize_subscriptions")]
+    pub operations: HashMap<u64, SubscriptionFilter>,
+
+    #[serde(deserialize_with = "deserialize_subscriptions")]
+    pub components: HashMap<String, SubscriptionFilter>,
+
+    #[serde(deserialize_with = "deserialize_subscriptions")]
+    pub tags: HashMap<String, SubscriptionFilter>,
 }
 
 impl SubscriptionSet {
-    pub fn is_match(&self, operation: &Operation) -> bool {
-        self.operations.contains(&operation.id)
+    pub fn is_match(&self, event: &OperationEvent) -> bool {
+        let operation = &event.operation;
+        let filters = self
+            .operations
+            .get(&operation.id)
+            .into_iter()
+            .chain(
+                operation
+                    .components
+                    .iter()
+                    .filter_map(|c| self.components.get(c)),
+            )
+            .chain(operation.tags.iter().filter_map(|t| self.tags.get(t)));
+        for filter in filters {
+            if filter.is_match(event) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Deserializes subscriptions either as a map from entities to filters, or
+/// as a list of entities without filters, which is how subscriptions were
+/// stored before filters were introduced.
+fn deserialize_subscriptions<'de, D, K>(
+    deserializer: D,
+) -> Result<HashMap<K, SubscriptionFilter>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    K: Deserialize<'de> + Eq + std::hash::Hash,
+{
+    struct Visitor<K>(std::marker::PhantomData<K>);
+
+    impl<'de, K> serde::de::Visitor<'de> for Visitor<K>
+    where
+        K: Deserialize<'de> + Eq + std::hash::Hash,
+    {
+        type Value = HashMap<K, SubscriptionFilter>;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("a map or a sequence")
+        }
+
+        fn visit_seq<A: serde::de::SeqAccess<'de>>(
+            self,
+            mut seq: A,
+        ) -> Result<Self::Value, A::Error> {
+            let mut subscriptions = HashMap::new();
+            while let Some(key) = seq.next_element()? {
+                subscriptions.insert(key, SubscriptionFilter::default());
+            }
+            Ok(subscriptions)
+        }
+
+        fn visit_map<A: serde::de::MapAccess<'de>>(
+            self,
+            mut map: A,
+        ) -> Result<Self::Value, A::Error> {
+            let mut subscriptions = HashMap::new();
+            while let Some((key, filter)) = map.next_entry()? {
+                subscriptions.insert(key, filter);
+            }
+            Ok(subscriptions)
+        }
+    }
+
+    deserializer.deserialize_any(Visitor(std::marker::PhantomData))
+}
+
+/// Narrows down the events notified by a subscription.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubscriptionFilter {
+    /// Only notify events after which the operation is in any of these
+    /// states. If empty, events are not filtered by status.
+    #[serde(default)]
+    pub statuses: Vec<OperationState>,
+
+    /// Only notify events of these kinds. If empty, events are not filtered
+    /// by kind.
+    #[serde(default)]
+    pub kinds: Vec<EventKind>,
+}
+
+impl SubscriptionFilter {
+    pub fn is_match(&self, event: &OperationEvent) -> bool {
+        (self.statuses.is_empty() || self.statuses.contains(&event.operation.status))
+            && (self.kinds.is_empty() || self.kinds.contains(&event.kind))
+    }
+}
+
+/// A chat channel that receives notifications through the chat bot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Channel {
+    /// ID assigned to the channel by the chat service
+    pub id: String,
+
+    pub name: String,
+
+    /// Incoming webhook of the chat service that posts messages to the
+    /// channel
+    #[serde(with = "crate::serde_uri")]
+    pub webhook_url: Uri,
+
+    pub flavor: ChatFlavor,
+    pub subscriptions: SubscriptionSet,
+}
+
+/// Chat service that determines how messages are formatted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatFlavor {
+    Slack,
+    Mattermost,
+}
+
+impl FromStr for ChatFlavor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "slack" => Ok(Self::Slack),
+            "mattermost" => Ok(Self::Mattermost),
+            _ => Err(format!("unknown chat flavor: {s}")),
+        }
+    }
+}
+
+impl std::fmt::Display for ChatFlavor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Slack => "slack",
+            Self::Mattermost => "mattermost",
+        }
+        .fmt(f)
+    }
+}
+
+/// An HTTP endpoint notified of operation events.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    pub id: u64,
+
+    #[serde(with = "crate::serde_uri")]
+    pub url: Uri,
+
+    /// Key used to sign payloads with HMAC-SHA256. Only returned when the
+    /// webhook is created.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub secret: String,
+
+    /// Only notify events of operations targeting any of these components.
+    /// If empty, events are not filtered by component.
+    #[serde(default)]
+    pub components: Vec<String>,
+
+    /// Only notify events of operations tagged with any of these tags.
+    /// If empty, events are not filtered by tag.
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    /// Only notify events of operations that are in any of these states after
+    /// the events. If empty, events are not filtered by status.
+    #[serde(default)]
+    pub statuses: Vec<OperationState>,
+}
+
+impl Webhook {
+    /// Returns the webhook without its secret.
+    #[must_use]
+    pub fn redacted(self) -> Self {
+        Self {
+            secret: String::new(),
+            ..self
+        }
+    }
+
+    pub fn is_match(&self, event: &OperationEvent) -> bool {
+        let operation = &event.operation;
+        (self.components.is_empty()
             || operation
                 .components
                 .iter()
-                .any(|c| self.components.contains(c))
-            || operation.tags.iter().any(|t| self.tags.contains(t))
+                .any(|c| self.components.contains(c)))
+            && (self.tags.is_empty() || operation.tags.iter().any(|t| self.tags.contains(t)))
+            && (self.statuses.is_empty() || self.statuses.contains(&operation.status))
+    }
+}
+
+/// An event that could not be delivered to a webhook after retries.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FailedDelivery {
+    pub id: u64,
+    pub webhook_id: u64,
+    pub event: OperationEvent,
+    pub attempts: u32,
+
+    /// The error of the last attempt
+    pub error: String,
+
+    pub failed_at: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_transition_to() {
+        use OperationState::{Aborted, Canceled, Completed, InProgress, Paused, Planned};
+
+        let all = [Planned, InProgress, Paused, Completed, Aborted, Canceled];
+        let allowed = [
+            (Planned, InProgress),
+            (Planned, Canceled),
+            (InProgress, Paused),
+            (InProgress, Completed),
+            (InProgress, Aborted),
+            (Paused, InProgress),
+        ];
+        for from in all {
+            for to in all {
+                let expected = from == to || allowed.contains(&(from, to));
+                assert_eq!(
+                    from.can_transition_to(to),
+                    expected,
+                    "{from} -> {to} should be {}",
+                    if expected { "allowed" } else { "rejected" }
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn finished_states_hold_no_locks() {
+        for state in [
+            OperationState::Completed,
+            OperationState::Aborted,
+            OperationState::Canceled,
+        ] {
+            assert!(state.is_finished());
+            assert!(!state.holds_locks());
+        }
+    }
+
+    fn event(kind: EventKind, status: OperationState) -> OperationEvent {
+        OperationEvent {
+            id: 1,
+            timestamp: Utc::now(),
+            actor: "alice".to_owned(),
+            kind,
+            changes: BTreeMap::new(),
+            operation: Operation {
+                id: 42,
+                title: "Test".to_owned(),
+                purpose: "Testing".to_owned(),
+                url: Uri::from_static("https://example.com/"),
+                components: vec!["foo".to_owned()],
+                locks: Vec::new(),
+                tags: vec!["bar".to_owned()],
+                depends_on: Vec::new(),
+                starts_at: None,
+                ends_at: None,
+                operators: vec!["alice".to_owned()],
+                on_behalf_of: None,
+                approved_by: Vec::new(),
+                status,
+                annotations: HashMap::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn subscription_filters_match_statuses_and_kinds() {
+        let filter = SubscriptionFilter {
+            statuses: vec![OperationState::Completed, OperationState::Aborted],
+            kinds: vec![EventKind::Transitioned],
+        };
+        let cases = [
+            (EventKind::Transitioned, OperationState::Completed, true),
+            (EventKind::Transitioned, OperationState::Aborted, true),
+            (EventKind::Transitioned, OperationState::InProgress, false),
+            (EventKind::Edited, OperationState::Completed, false),
+        ];
+        for (kind, status, expected) in cases {
+            let event = event(kind, status);
+            for subscriptions in [
+                SubscriptionSet {
+                    operations: HashMap::from([(42, filter.clone())]),
+                    ..Default::default()
+                },
+                SubscriptionSet {
+                    components: HashMap::from([("foo".to_owned(), filter.clone())]),
+                    ..Default::default()
+                },
+                SubscriptionSet {
+                    tags: HashMap::from([("bar".to_owned(), filter.clone())]),
+                    ..Default::default()
+                },
+            ] {
+                assert_eq!(
+                    subscriptions.is_match(&event),
+                    expected,
+                    "{kind} to {status} with {subscriptions:?}"
+                );
+            }
+        }
+
+        // Any matching subscription is enough.
+        let subscriptions = SubscriptionSet {
+            operations: HashMap::from([(42, filter)]),
+            tags: HashMap::from([("bar".to_owned(), SubscriptionFilter::default())]),
+            ..Default::default()
+        };
+        assert!(subscriptions.is_match(&event(EventKind::Edited, OperationState::Planned)));
+        assert!(!SubscriptionSet::default()
+            .is_match(&event(EventKind::Created, OperationState::Planned)));
+    }
+
+    #[test]
+    fn deserializes_subscriptions_without_filters() {
+        let subscriptions: SubscriptionSet =
+            serde_json::from_str(r#"{ "operations": [42], "components": ["foo"], "tags": [] }"#)
+                .unwrap();
+        assert_eq!(
+            subscriptions.operations,
+            HashMap::from([(42, SubscriptionFilter::default())])
+        );
+        assert_eq!(
+            subscriptions.components,
+            HashMap::from([("foo".to_owned(), SubscriptionFilter::default())])
+        );
+        assert!(subscriptions.tags.is_empty());
+
+        let subscriptions: SubscriptionSet = serde_json::from_str(
+            r#"{
+                "operations": { "42": { "statuses": ["completed"] } },
+                "components": {},
+                "tags": { "bar": {} }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            subscriptions.operations[&42].statuses,
+            [OperationState::Completed]
+        );
+        assert!(subscriptions.operations[&42].kinds.is_empty());
+        assert_eq!(subscriptions.tags["bar"], SubscriptionFilter::default());
     }
 }